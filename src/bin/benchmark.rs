@@ -1,7 +1,5 @@
-use std::time::{Duration, Instant};
-use bytes::Bytes;
+use std::time::Instant;
 use clap::Parser;
-use tokio::time;
 
 // Import client from main crate
 use rudis::client::Client;
@@ -0,0 +1,141 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::sync::Notify;
+
+/// Clients blocked until something is pushed, in the order they blocked.
+///
+/// A push wakes the oldest waiter that has not been woken yet. The woken
+/// client then competes for the item like any other consumer; if it loses, it
+/// waits again without giving up its place, and if it leaves before using its
+/// wake-up, the wake-up is handed on to the next waiter.
+#[derive(Debug, Default)]
+pub struct WaitList {
+    waiters: Mutex<VecDeque<Arc<Waiter>>>,
+}
+
+/// One [`WaitList`] per key, for clients blocked on any of several keys.
+///
+/// A client blocked on more than one key sits in the list of each of them, so
+/// a push on any of those keys can wake it; once it is awake, pushes on its
+/// other keys pass it over for the next waiter there.
+#[derive(Debug, Default)]
+pub struct WaitRegistry {
+    keys: Mutex<HashMap<String, VecDeque<Arc<Waiter>>>>,
+}
+
+#[derive(Debug, Default)]
+struct Waiter {
+    notify: Notify,
+    woken: AtomicBool,
+}
+
+/// A registration in a [`WaitList`] or [`WaitRegistry`], removed when dropped.
+pub struct WaitGuard<'a> {
+    owner: Owner<'a>,
+    waiter: Arc<Waiter>,
+}
+
+enum Owner<'a> {
+    List(&'a WaitList),
+    Registry(&'a WaitRegistry, Vec<String>),
+}
+
+impl WaitList {
+    /// Joins the back of the list. Register before checking for an item, so a
+    /// push racing with the check still wakes this waiter.
+    pub fn register(&self) -> WaitGuard<'_> {
+        let waiter = Arc::new(Waiter::default());
+        self.waiters.lock().unwrap().push_back(Arc::clone(&waiter));
+        WaitGuard { owner: Owner::List(self), waiter }
+    }
+
+    /// Wakes the oldest waiter that is not already awake.
+    pub fn wake_one(&self) {
+        wake_first(&self.waiters.lock().unwrap());
+    }
+}
+
+impl WaitRegistry {
+    /// Joins the back of the list of each of `keys`. As with
+    /// [`WaitList::register`], register before checking the keys.
+    pub fn register(&self, keys: &[String]) -> WaitGuard<'_> {
+        let waiter = Arc::new(Waiter::default());
+        let mut lists = self.keys.lock().unwrap();
+        for key in keys {
+            lists.entry(key.clone()).or_default().push_back(Arc::clone(&waiter));
+        }
+        WaitGuard { owner: Owner::Registry(self, keys.to_vec()), waiter }
+    }
+
+    /// Wakes the oldest waiter on `key` that is not already awake.
+    pub fn wake_one(&self, key: &str) {
+        if let Some(waiters) = self.keys.lock().unwrap().get(key) {
+            wake_first(waiters);
+        }
+    }
+
+    /// Wakes every waiter on `key`, for additions that all of them can read,
+    /// such as a stream entry.
+    pub fn wake_all(&self, key: &str) {
+        if let Some(waiters) = self.keys.lock().unwrap().get(key) {
+            for waiter in waiters {
+                if !waiter.woken.swap(true, Ordering::AcqRel) {
+                    waiter.notify.notify_one();
+                }
+            }
+        }
+    }
+}
+
+fn wake_first(waiters: &VecDeque<Arc<Waiter>>) {
+    if let Some(waiter) = waiters.iter().find(|waiter| !waiter.woken.swap(true, Ordering::AcqRel)) {
+        waiter.notify.notify_one();
+    }
+}
+
+impl WaitGuard<'_> {
+    /// Waits for a wake-up. Cancel safe: a wake-up that arrives while the
+    /// future is being dropped is passed on when the guard is.
+    pub async fn wait(&self) {
+        self.waiter.notify.notified().await;
+        self.waiter.woken.store(false, Ordering::Release);
+    }
+}
+
+impl Drop for WaitGuard<'_> {
+    fn drop(&mut self) {
+        let is_other = |waiter: &Arc<Waiter>| !Arc::ptr_eq(waiter, &self.waiter);
+        let woken = || self.waiter.woken.load(Ordering::Acquire);
+
+        match &self.owner {
+            Owner::List(list) => {
+                list.waiters.lock().unwrap().retain(is_other);
+                if woken() {
+                    list.wake_one();
+                }
+            },
+            Owner::Registry(registry, keys) => {
+                let mut lists = registry.keys.lock().unwrap();
+                for key in keys {
+                    if let Some(waiters) = lists.get_mut(key) {
+                        waiters.retain(is_other);
+                        if waiters.is_empty() {
+                            lists.remove(key);
+                        }
+                    }
+                }
+
+                // Which key the unused wake-up came from is unknown, so offer
+                // it on all of them; a waiter that finds nothing waits again
+                if woken() {
+                    for key in keys {
+                        if let Some(waiters) = lists.get(key) {
+                            wake_first(waiters);
+                        }
+                    }
+                }
+            },
+        }
+    }
+}
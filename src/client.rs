@@ -4,7 +4,7 @@ use bytes::{BytesMut, Bytes};
 use std::time::Duration;
 use thiserror::Error;
 
-use crate::protocol::{RedisValue, serialize_response};
+use crate::protocol::{ProtocolVersion, RedisValue, parse_value, serialize_response};
 
 #[derive(Error, Debug)]
 pub enum ClientError {
@@ -12,6 +12,9 @@ pub enum ClientError {
     ConnectionError(#[from] std::io::Error),
     #[error("protocol error: {0}")]
     ProtocolError(String),
+    /// An error reply from the server, e.g. `ERR syntax error`.
+    #[error("{0}")]
+    ServerError(String),
     #[error("timeout")]
     Timeout,
 }
@@ -33,141 +36,106 @@ impl Client {
     }
     
     pub async fn get(&mut self, key: &str) -> Result<Option<Bytes>> {
-        let cmd = format!("GET {}\r\n", key);
-        self.stream.write_all(cmd.as_bytes()).await?;
-        
-        let mut response_buf = [0u8; 1024];
-        let n = self.stream.read(&mut response_buf).await?;
-        
-        if n == 0 {
-            return Err(ClientError::ConnectionError(std::io::Error::new(
-                std::io::ErrorKind::UnexpectedEof,
-                "Connection closed",
-            )));
-        }
-        
-        let response = std::str::from_utf8(&response_buf[..n])
-            .map_err(|_| ClientError::ProtocolError("Invalid UTF-8".to_string()))?;
-        
-        // Handle nil response
-        if response.starts_with("$-1") {
-            return Ok(None);
-        }
-        
-        // Simple parsing
-        if response.starts_with("$") {
-            // Extract the byte string
-            let parts: Vec<&str> = response.split("\r\n").collect();
-            if parts.len() >= 3 {
-                return Ok(Some(Bytes::from(parts[1].as_bytes().to_vec())));
-            }
+        match self.send_command(&[b"GET", key.as_bytes()]).await? {
+            RedisValue::Nil => Ok(None),
+            RedisValue::Bytes(value) => Ok(Some(value)),
+            other => Err(unexpected(other)),
         }
-        
-        Err(ClientError::ProtocolError(format!("Unexpected response: {}", response)))
     }
     
     pub async fn set(&mut self, key: &str, value: &[u8], ttl: Option<u64>) -> Result<()> {
-        let mut cmd = format!("SET {} {}", key, std::str::from_utf8(value)
-            .map_err(|_| ClientError::ProtocolError("Invalid UTF-8 in value".to_string()))?);
-        
-        if let Some(ttl) = ttl {
-            cmd = format!("{} EX {}", cmd, ttl);
+        let ttl = ttl.map(|ttl| ttl.to_string());
+        let mut args: Vec<&[u8]> = vec![b"SET", key.as_bytes(), value];
+        if let Some(ttl) = &ttl {
+            args.push(b"EX");
+            args.push(ttl.as_bytes());
         }
         
-        cmd = format!("{}\r\n", cmd);
-        self.stream.write_all(cmd.as_bytes()).await?;
-        
-        let mut response_buf = [0u8; 1024];
-        let n = self.stream.read(&mut response_buf).await?;
-        
-        if n == 0 {
-            return Err(ClientError::ConnectionError(std::io::Error::new(
-                std::io::ErrorKind::UnexpectedEof,
-                "Connection closed",
-            )));
+        match self.send_command(&args).await? {
+            RedisValue::String(s) if s == "OK" => Ok(()),
+            other => Err(unexpected(other)),
         }
-        
-        let response = std::str::from_utf8(&response_buf[..n])
-            .map_err(|_| ClientError::ProtocolError("Invalid UTF-8".to_string()))?;
-            
-        if response.contains("OK") {
-            Ok(())
-        } else {
-            Err(ClientError::ProtocolError(format!("Unexpected response: {}", response)))
+    }
+    
+    /// Atomically adds `increment` to the integer at `key`, which starts from
+    /// 0 if missing, and returns the result.
+    pub async fn incr_by(&mut self, key: &str, increment: i64) -> Result<i64> {
+        let increment = increment.to_string();
+        match self.send_command(&[b"INCRBY", key.as_bytes(), increment.as_bytes()]).await? {
+            RedisValue::Integer(value) => Ok(value),
+            other => Err(unexpected(other)),
         }
     }
     
     pub async fn pop(&mut self) -> Result<Option<(String, Bytes)>> {
-        let cmd = "POP\r\n";
-        self.stream.write_all(cmd.as_bytes()).await?;
-        
-        let mut response_buf = [0u8; 1024];
-        let n = self.stream.read(&mut response_buf).await?;
-        
-        if n == 0 {
-            return Err(ClientError::ConnectionError(std::io::Error::new(
-                std::io::ErrorKind::UnexpectedEof,
-                "Connection closed",
-            )));
-        }
-        
-        let response = std::str::from_utf8(&response_buf[..n])
-            .map_err(|_| ClientError::ProtocolError("Invalid UTF-8".to_string()))?;
-        
-        if response.starts_with("$-1") || response.starts_with("*-1") {
-            return Ok(None);
-        }
-        
-        // TODO: Implement proper RESP parsing
-        if response.starts_with("*") {
-            let parts: Vec<&str> = response.split("\r\n").collect();
-            if parts.len() >= 5 {
-                let key = parts[2].to_string();
-                let value = Bytes::from(parts[4].as_bytes().to_vec());
-                return Ok(Some((key, value)));
-            }
-        }
-        
-        Err(ClientError::ProtocolError(format!("Unexpected response: {}", response)))
+        let reply = self.send_command(&[b"POP"]).await?;
+        popped_pair(reply)
+    }
+    
+    /// Like [`Client::pop`], but waits up to `timeout` for a key to be SET
+    /// when there is none. A zero timeout waits forever.
+    pub async fn bpop(&mut self, timeout: Duration) -> Result<Option<(String, Bytes)>> {
+        let timeout = timeout.as_secs_f64().to_string();
+        let reply = self.send_command(&[b"BPOP", timeout.as_bytes()]).await?;
+        popped_pair(reply)
     }
     
     pub async fn keys(&mut self, pattern: &str) -> Result<Vec<String>> {
-        let cmd = format!("KEYS {}\r\n", pattern);
-        self.stream.write_all(cmd.as_bytes()).await?;
-        
-        let mut response_buf = [0u8; 4096]; // Larger buffer for potentially many keys
-        let n = self.stream.read(&mut response_buf).await?;
-        
-        if n == 0 {
-            return Err(ClientError::ConnectionError(std::io::Error::new(
-                std::io::ErrorKind::UnexpectedEof,
-                "Connection closed",
-            )));
-        }
-        
-        let response = std::str::from_utf8(&response_buf[..n])
-            .map_err(|_| ClientError::ProtocolError("Invalid UTF-8".to_string()))?;
-        
-        // Handle empty array
-        if response.starts_with("*0") {
-            return Ok(Vec::new());
+        match self.send_command(&[b"KEYS", pattern.as_bytes()]).await? {
+            RedisValue::Array(items) => items
+                .into_iter()
+                .map(|item| match item {
+                    RedisValue::String(key) => Ok(key),
+                    RedisValue::Bytes(key) => Ok(String::from_utf8_lossy(&key).into_owned()),
+                    other => Err(unexpected(other)),
+                })
+                .collect(),
+            other => Err(unexpected(other)),
         }
-        
-        // Simple parsing for array response
-        if response.starts_with("*") {
-            let parts: Vec<&str> = response.split("\r\n").collect();
-            let mut keys = Vec::new();
-            
-            // Skip the first line (*<count>) and process each key
-            for i in (1..parts.len()).step_by(2) {
-                if i + 1 < parts.len() && parts[i].starts_with("$") {
-                    keys.push(parts[i+1].to_string());
-                }
+    }
+
+    /// Sends a command as a RESP array of bulk strings and waits for its reply.
+    async fn send_command(&mut self, args: &[&[u8]]) -> Result<RedisValue> {
+        let request = RedisValue::Array(
+            args.iter()
+                .map(|arg| RedisValue::Bytes(Bytes::copy_from_slice(arg)))
+                .collect(),
+        );
+        self.stream.write_all(&serialize_response(request, ProtocolVersion::Resp2)).await?;
+        
+        loop {
+            if let Some(value) = parse_value(&mut self.buffer)
+                .map_err(|e| ClientError::ProtocolError(e.to_string()))?
+            {
+                return Ok(value);
             }
             
-            return Ok(keys);
+            if self.stream.read_buf(&mut self.buffer).await? == 0 {
+                return Err(ClientError::ConnectionError(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    "Connection closed",
+                )));
+            }
         }
-        
-        Err(ClientError::ProtocolError(format!("Unexpected response: {}", response)))
     }
-}
\ No newline at end of file
+}
+
+/// Decodes the `[key, value]` reply of POP and BPOP.
+fn popped_pair(reply: RedisValue) -> Result<Option<(String, Bytes)>> {
+    match reply {
+        RedisValue::Nil => Ok(None),
+        RedisValue::Array(items) => match <[RedisValue; 2]>::try_from(items) {
+            Ok([RedisValue::String(key), RedisValue::Bytes(value)]) => Ok(Some((key, value))),
+            Ok(other) => Err(unexpected(RedisValue::Array(other.into()))),
+            Err(items) => Err(unexpected(RedisValue::Array(items))),
+        },
+        other => Err(unexpected(other)),
+    }
+}
+
+fn unexpected(value: RedisValue) -> ClientError {
+    match value {
+        RedisValue::Error(message) => ClientError::ServerError(message),
+        other => ClientError::ProtocolError(format!("Unexpected response: {:?}", other)),
+    }
+}
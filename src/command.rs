@@ -0,0 +1,382 @@
+use crate::protocol::RedisValue;
+
+/// Static description of a command, used to validate requests before they are
+/// parsed and to answer COMMAND introspection.
+#[derive(Debug)]
+pub struct CommandSpec {
+    /// Lowercase command name.
+    pub name: &'static str,
+    /// Redis arity convention: `n` means exactly `n` arguments including the
+    /// command name, `-n` means at least `n`.
+    pub arity: i64,
+    pub flags: &'static [&'static str],
+    /// Position of the first key argument, or 0 if the command takes no keys.
+    pub first_key: i64,
+    /// Position of the last key argument; negative values count from the end.
+    pub last_key: i64,
+    /// Distance between consecutive key arguments.
+    pub step: i64,
+    pub group: &'static str,
+    pub summary: &'static str,
+    pub since: &'static str,
+}
+
+const WRITE: &[&str] = &["write", "denyoom"];
+const WRITE_FAST: &[&str] = &["write", "denyoom", "fast"];
+/// Fast writes that cannot grow memory use, so they are still allowed under
+/// memory pressure (no `denyoom`).
+const WRITE_FAST_SHRINK: &[&str] = &["write", "fast"];
+const READONLY: &[&str] = &["readonly"];
+const READONLY_FAST: &[&str] = &["readonly", "fast"];
+const FAST: &[&str] = &["fast"];
+const CONNECTION: &[&str] = &["noscript", "loading", "stale", "fast"];
+
+#[allow(clippy::too_many_arguments)]
+const fn spec(
+    name: &'static str,
+    arity: i64,
+    flags: &'static [&'static str],
+    first_key: i64,
+    last_key: i64,
+    step: i64,
+    group: &'static str,
+    summary: &'static str,
+    since: &'static str,
+) -> CommandSpec {
+    CommandSpec { name, arity, flags, first_key, last_key, step, group, summary, since }
+}
+
+/// Every command the server understands.
+pub static COMMANDS: &[CommandSpec] = &[
+    spec("append", 3, WRITE_FAST, 1, 1, 1, "string",
+        "Appends a string to the value of a key. Creates the key if it doesn't exist.", "2.0.0"),
+    spec("blmove", 6, &["write", "denyoom", "blocking"], 1, 2, 1, "list",
+        "Pops an element from a list, pushes it to another list and returns it. Blocks until an element is \
+         available otherwise.", "6.2.0"),
+    spec("blmpop", -5, &["write", "blocking"], 0, 0, 0, "list",
+        "Pops the first element from one of multiple lists. Blocks until an element is available otherwise.",
+        "7.0.0"),
+    spec("blpop", -3, &["write", "blocking"], 1, -2, 1, "list",
+        "Removes and returns the first element in a list. Blocks until an element is available otherwise.",
+        "2.0.0"),
+    spec("bpop", 2, &["write", "blocking"], 0, 0, 0, "rudis",
+        "Removes and returns the oldest key set through SET, blocking until one exists.", "0.1.0"),
+    spec("brpop", -3, &["write", "blocking"], 1, -2, 1, "list",
+        "Removes and returns the last element in a list. Blocks until an element is available otherwise.",
+        "2.0.0"),
+    spec("command", -1, &["loading", "stale"], 0, 0, 0, "server",
+        "Returns detailed information about all commands.", "2.8.13"),
+    spec("decr", 2, WRITE_FAST, 1, 1, 1, "string",
+        "Decrements the integer value of a key by one. Uses 0 as initial value if the key doesn't exist.", "1.0.0"),
+    spec("decrby", 3, WRITE_FAST, 1, 1, 1, "string",
+        "Decrements a number from the integer value of a key. Uses 0 as initial value if the key doesn't exist.",
+        "1.0.0"),
+    spec("del", -2, WRITE, 1, -1, 1, "generic",
+        "Deletes one or more keys.", "1.0.0"),
+    spec("expire", -3, WRITE_FAST_SHRINK, 1, 1, 1, "generic",
+        "Sets the expiration time of a key in seconds.", "1.0.0"),
+    spec("expireat", -3, WRITE_FAST_SHRINK, 1, 1, 1, "generic",
+        "Sets the expiration time of a key to a Unix timestamp.", "1.2.0"),
+    spec("expiretime", 2, READONLY_FAST, 1, 1, 1, "generic",
+        "Returns the expiration time of a key as a Unix timestamp.", "7.0.0"),
+    spec("get", 2, READONLY_FAST, 1, 1, 1, "string",
+        "Returns the string value of a key.", "1.0.0"),
+    spec("getdel", 2, WRITE_FAST_SHRINK, 1, 1, 1, "string",
+        "Returns the string value of a key after deleting the key.", "6.2.0"),
+    spec("getex", -2, WRITE_FAST_SHRINK, 1, 1, 1, "string",
+        "Returns the string value of a key after setting its expiration time.", "6.2.0"),
+    spec("getrange", 4, READONLY, 1, 1, 1, "string",
+        "Returns a substring of the string stored at a key.", "2.4.0"),
+    spec("getset", 3, WRITE_FAST, 1, 1, 1, "string",
+        "Returns the previous string value of a key after setting it to a new value.", "1.0.0"),
+    spec("hdel", -3, WRITE_FAST_SHRINK, 1, 1, 1, "hash",
+        "Deletes one or more fields and their values from a hash. Deletes the hash if no fields remain.",
+        "2.0.0"),
+    spec("hello", -1, CONNECTION, 0, 0, 0, "connection",
+        "Handshakes with the server.", "6.0.0"),
+    spec("hexists", 3, READONLY_FAST, 1, 1, 1, "hash",
+        "Determines whether a field exists in a hash.", "2.0.0"),
+    spec("hexpire", -6, WRITE_FAST_SHRINK, 1, 1, 1, "hash",
+        "Sets the expiration time of hash fields in seconds.", "7.4.0"),
+    spec("hget", 3, READONLY_FAST, 1, 1, 1, "hash",
+        "Returns the value of a field in a hash.", "2.0.0"),
+    spec("hgetall", 2, READONLY, 1, 1, 1, "hash",
+        "Returns all fields and values in a hash.", "2.0.0"),
+    spec("hincrby", 4, WRITE_FAST, 1, 1, 1, "hash",
+        "Increments the integer value of a field in a hash by a number. Uses 0 as initial value if the field \
+         doesn't exist.", "2.0.0"),
+    spec("hincrbyfloat", 4, WRITE_FAST, 1, 1, 1, "hash",
+        "Increments the floating point value of a field by a number. Uses 0 as initial value if the field \
+         doesn't exist.", "2.6.0"),
+    spec("hkeys", 2, READONLY, 1, 1, 1, "hash",
+        "Returns all fields in a hash.", "2.0.0"),
+    spec("hlen", 2, READONLY_FAST, 1, 1, 1, "hash",
+        "Returns the number of fields in a hash.", "2.0.0"),
+    spec("hmget", -3, READONLY_FAST, 1, 1, 1, "hash",
+        "Returns the values of all fields in a hash.", "2.0.0"),
+    spec("hpersist", -5, WRITE_FAST_SHRINK, 1, 1, 1, "hash",
+        "Removes the expiration time of hash fields.", "7.4.0"),
+    spec("hpexpire", -6, WRITE_FAST_SHRINK, 1, 1, 1, "hash",
+        "Sets the expiration time of hash fields in milliseconds.", "7.4.0"),
+    spec("hscan", -3, READONLY, 1, 1, 1, "hash",
+        "Iterates over fields and values of a hash.", "2.8.0"),
+    spec("hset", -4, WRITE_FAST, 1, 1, 1, "hash",
+        "Creates or modifies the value of a field in a hash.", "2.0.0"),
+    spec("httl", -5, READONLY_FAST, 1, 1, 1, "hash",
+        "Returns the expiration time in seconds of hash fields.", "7.4.0"),
+    spec("hvals", 2, READONLY, 1, 1, 1, "hash",
+        "Returns all values in a hash.", "2.0.0"),
+    spec("incr", 2, WRITE_FAST, 1, 1, 1, "string",
+        "Increments the integer value of a key by one. Uses 0 as initial value if the key doesn't exist.", "1.0.0"),
+    spec("incrby", 3, WRITE_FAST, 1, 1, 1, "string",
+        "Increments the integer value of a key by a number. Uses 0 as initial value if the key doesn't exist.",
+        "1.0.0"),
+    spec("incrbyfloat", 3, WRITE_FAST, 1, 1, 1, "string",
+        "Increment the floating point value of a key by a number. Uses 0 as initial value if the key doesn't \
+         exist.", "2.6.0"),
+    spec("info", -1, &["loading", "stale"], 0, 0, 0, "server",
+        "Returns information and statistics about the server.", "1.0.0"),
+    spec("keys", 2, READONLY, 0, 0, 0, "generic",
+        "Returns all key names that match a pattern.", "1.0.0"),
+    spec("lindex", 3, READONLY, 1, 1, 1, "list",
+        "Returns an element from a list by its index.", "1.0.0"),
+    spec("linsert", 5, WRITE, 1, 1, 1, "list",
+        "Inserts an element before or after another element in a list.", "2.2.0"),
+    spec("llen", 2, READONLY_FAST, 1, 1, 1, "list",
+        "Returns the length of a list.", "1.0.0"),
+    spec("lmove", 5, WRITE, 1, 2, 1, "list",
+        "Returns an element after popping it from one list and pushing it to another.", "6.2.0"),
+    spec("lpop", -2, WRITE_FAST_SHRINK, 1, 1, 1, "list",
+        "Returns the first elements in a list after removing it.", "1.0.0"),
+    spec("lpush", -3, WRITE_FAST, 1, 1, 1, "list",
+        "Prepends one or more elements to a list.", "1.0.0"),
+    spec("lrange", 4, READONLY, 1, 1, 1, "list",
+        "Returns a range of elements from a list.", "1.0.0"),
+    spec("lrem", 4, WRITE, 1, 1, 1, "list",
+        "Removes elements from a list.", "1.0.0"),
+    spec("lset", 4, WRITE, 1, 1, 1, "list",
+        "Sets the value of an element in a list by its index.", "1.0.0"),
+    spec("ltrim", 4, WRITE, 1, 1, 1, "list",
+        "Removes elements from both ends a list.", "1.0.0"),
+    spec("persist", 2, WRITE_FAST_SHRINK, 1, 1, 1, "generic",
+        "Removes the expiration time of a key.", "2.2.0"),
+    spec("pexpire", -3, WRITE_FAST_SHRINK, 1, 1, 1, "generic",
+        "Sets the expiration time of a key in milliseconds.", "2.6.0"),
+    spec("pexpireat", -3, WRITE_FAST_SHRINK, 1, 1, 1, "generic",
+        "Sets the expiration time of a key to a Unix milliseconds timestamp.", "2.6.0"),
+    spec("pexpiretime", 2, READONLY_FAST, 1, 1, 1, "generic",
+        "Returns the expiration time of a key as a Unix milliseconds timestamp.", "7.0.0"),
+    spec("ping", -1, FAST, 0, 0, 0, "connection",
+        "Returns the server's liveliness response.", "1.0.0"),
+    spec("pop", 1, WRITE_FAST, 0, 0, 0, "rudis",
+        "Removes and returns the oldest key set through SET.", "0.1.0"),
+    spec("psetex", 4, WRITE, 1, 1, 1, "string",
+        "Sets both string value and expiration time in milliseconds of a key. The key is created if it doesn't \
+         exist.", "2.6.0"),
+    spec("pttl", 2, READONLY_FAST, 1, 1, 1, "generic",
+        "Returns the expiration time in milliseconds of a key.", "2.6.0"),
+    spec("qack", -3, WRITE_FAST_SHRINK, 1, 1, 1, "rudis",
+        "Acknowledges reserved items, deleting them from a named queue.", "0.1.0"),
+    spec("qcancel", -3, WRITE_FAST_SHRINK, 1, 1, 1, "rudis",
+        "Deletes scheduled items from a named queue before they become visible.", "0.1.0"),
+    spec("qdepth", 2, READONLY_FAST, 1, 1, 1, "rudis",
+        "Returns the number of items at each priority of a named queue.", "0.1.0"),
+    spec("qdlq", -3, WRITE, 2, 2, 1, "rudis",
+        "Lists, counts, replays or purges the dead-letter queue of a named queue.", "0.1.0"),
+    spec("qlen", 2, READONLY_FAST, 1, 1, 1, "rudis",
+        "Returns the number of items in a named queue.", "0.1.0"),
+    spec("qnack", -3, WRITE_FAST_SHRINK, 1, 1, 1, "rudis",
+        "Returns reserved items to a named queue so they are delivered again.", "0.1.0"),
+    spec("qpeek", -2, READONLY, 1, 1, 1, "rudis",
+        "Returns items from the front of a named queue without removing them.", "0.1.0"),
+    spec("qpop", -2, WRITE_FAST_SHRINK, 1, 1, 1, "rudis",
+        "Removes and returns items from the front of a named queue.", "0.1.0"),
+    spec("qpush", -3, WRITE, 1, 1, 1, "rudis",
+        "Appends an item to a named queue, creating it if needed.", "0.1.0"),
+    spec("qreject", -3, WRITE_FAST_SHRINK, 1, 1, 1, "rudis",
+        "Rejects reserved items, dead-lettering those that failed too often.", "0.1.0"),
+    spec("qreserve", -2, WRITE_FAST_SHRINK, 1, 1, 1, "rudis",
+        "Hides the first item of a named queue for a visibility timeout and returns it.", "0.1.0"),
+    spec("qscheduled", -2, READONLY, 1, 1, 1, "rudis",
+        "Returns the items of a named queue that are scheduled to become visible later.", "0.1.0"),
+    spec("rpop", -2, WRITE_FAST_SHRINK, 1, 1, 1, "list",
+        "Returns and removes the last elements of a list.", "1.0.0"),
+    spec("rpush", -3, WRITE_FAST, 1, 1, 1, "list",
+        "Appends one or more elements to a list.", "1.0.0"),
+    spec("sadd", -3, WRITE_FAST, 1, 1, 1, "set",
+        "Adds one or more members to a set. Creates the key if it doesn't exist.", "1.0.0"),
+    spec("scard", 2, READONLY_FAST, 1, 1, 1, "set",
+        "Returns the number of members in a set.", "1.0.0"),
+    spec("sdiff", -2, READONLY, 1, -1, 1, "set",
+        "Returns the difference of multiple sets.", "1.0.0"),
+    spec("sdiffstore", -3, WRITE, 1, -1, 1, "set",
+        "Stores the difference of multiple sets in a key.", "1.0.0"),
+    spec("set", -3, WRITE, 1, 1, 1, "string",
+        "Sets the string value of a key, ignoring its type.", "1.0.0"),
+    spec("setex", 4, WRITE, 1, 1, 1, "string",
+        "Sets the string value and expiration time of a key. Creates the key if it doesn't exist.", "2.0.0"),
+    spec("setnx", 3, WRITE_FAST, 1, 1, 1, "string",
+        "Set the string value of a key only when the key doesn't exist.", "1.0.0"),
+    spec("setrange", 4, WRITE, 1, 1, 1, "string",
+        "Overwrites a part of a string value with another by an offset. Creates the key if it doesn't exist.",
+        "2.2.0"),
+    spec("sinter", -2, READONLY, 1, -1, 1, "set",
+        "Returns the intersect of multiple sets.", "1.0.0"),
+    spec("sintercard", -3, READONLY, 0, 0, 0, "set",
+        "Returns the number of members of the intersect of multiple sets.", "7.0.0"),
+    spec("sinterstore", -3, WRITE, 1, -1, 1, "set",
+        "Stores the intersect of multiple sets in a key.", "1.0.0"),
+    spec("sismember", 3, READONLY_FAST, 1, 1, 1, "set",
+        "Determines whether a member belongs to a set.", "1.0.0"),
+    spec("smembers", 2, READONLY, 1, 1, 1, "set",
+        "Returns all members of a set.", "1.0.0"),
+    spec("smismember", -3, READONLY_FAST, 1, 1, 1, "set",
+        "Determines whether multiple members belong to a set.", "6.2.0"),
+    spec("spop", -2, WRITE_FAST_SHRINK, 1, 1, 1, "set",
+        "Returns one or more random members from a set after removing them. Deletes the set if the last member \
+         was popped.", "1.0.0"),
+    spec("srandmember", -2, READONLY, 1, 1, 1, "set",
+        "Get one or multiple random members from a set", "1.0.0"),
+    spec("srem", -3, WRITE_FAST_SHRINK, 1, 1, 1, "set",
+        "Removes one or more members from a set. Deletes the set if the last member was removed.", "1.0.0"),
+    spec("strlen", 2, READONLY_FAST, 1, 1, 1, "string",
+        "Returns the length of a string value.", "2.2.0"),
+    spec("sunion", -2, READONLY, 1, -1, 1, "set",
+        "Returns the union of multiple sets.", "1.0.0"),
+    spec("sunionstore", -3, WRITE, 1, -1, 1, "set",
+        "Stores the union of multiple sets in a key.", "1.0.0"),
+    spec("ttl", 2, READONLY_FAST, 1, 1, 1, "generic",
+        "Returns the expiration time in seconds of a key.", "1.0.0"),
+    spec("xack", -4, WRITE_FAST_SHRINK, 1, 1, 1, "stream",
+        "Returns the number of messages that were successfully acknowledged by the consumer group member of a stream.",
+        "5.0.0"),
+    spec("xadd", -5, WRITE_FAST, 1, 1, 1, "stream",
+        "Appends a new message to a stream. Creates the key if it doesn't exist.", "5.0.0"),
+    spec("xautoclaim", -6, WRITE_FAST_SHRINK, 1, 1, 1, "stream",
+        "Changes, or acquires, ownership of messages in a consumer group, as if the messages were delivered to as \
+         consumer group member.", "6.2.0"),
+    spec("xclaim", -6, WRITE_FAST_SHRINK, 1, 1, 1, "stream",
+        "Changes, or acquires, ownership of a message in a consumer group, as if the message was delivered a \
+         consumer group member.", "5.0.0"),
+    spec("xdel", -3, WRITE_FAST_SHRINK, 1, 1, 1, "stream",
+        "Returns the number of messages after removing them from a stream.", "5.0.0"),
+    spec("xgroup", -2, WRITE, 0, 0, 0, "stream",
+        "A container for consumer groups commands.", "5.0.0"),
+    spec("xinfo", -2, READONLY, 0, 0, 0, "stream",
+        "A container for stream introspection commands.", "5.0.0"),
+    spec("xlen", 2, READONLY_FAST, 1, 1, 1, "stream",
+        "Return the number of messages in a stream.", "5.0.0"),
+    spec("xpending", -3, READONLY, 1, 1, 1, "stream",
+        "Returns the information and entries from a stream consumer group's pending entries list.", "5.0.0"),
+    spec("xrange", -4, READONLY, 1, 1, 1, "stream",
+        "Returns the messages from a stream within a range of IDs.", "5.0.0"),
+    spec("xread", -4, &["readonly", "blocking"], 0, 0, 0, "stream",
+        "Returns messages from multiple streams with IDs greater than the ones requested. Blocks until a message is \
+         available otherwise.", "5.0.0"),
+    spec("xreadgroup", -7, &["write", "blocking"], 0, 0, 0, "stream",
+        "Returns new or historical messages from a stream for a consumer in a group. Blocks until a message is \
+         available otherwise.", "5.0.0"),
+    spec("xrevrange", -4, READONLY, 1, 1, 1, "stream",
+        "Returns the messages from a stream within a range of IDs in reverse order.", "5.0.0"),
+    spec("zadd", -4, WRITE_FAST, 1, 1, 1, "sorted_set",
+        "Adds one or more members to a sorted set, or updates their scores. Creates the key if it doesn't exist.",
+        "1.2.0"),
+    spec("zcard", 2, READONLY_FAST, 1, 1, 1, "sorted_set",
+        "Returns the number of members in a sorted set.", "1.2.0"),
+    spec("zcount", 4, READONLY_FAST, 1, 1, 1, "sorted_set",
+        "Returns the count of members in a sorted set that have scores within a range.", "2.0.0"),
+    spec("zincrby", 4, WRITE_FAST, 1, 1, 1, "sorted_set",
+        "Increments the score of a member in a sorted set.", "1.2.0"),
+    spec("zinterstore", -4, WRITE, 1, 1, 1, "sorted_set",
+        "Stores the intersect of multiple sorted sets in a key.", "2.0.0"),
+    spec("zmscore", -3, READONLY_FAST, 1, 1, 1, "sorted_set",
+        "Returns the score of one or more members in a sorted set.", "6.2.0"),
+    spec("zpopmax", -2, WRITE_FAST_SHRINK, 1, 1, 1, "sorted_set",
+        "Returns the highest-scoring members from a sorted set after removing them. Deletes the sorted set if the \
+         last member was popped.", "5.0.0"),
+    spec("zpopmin", -2, WRITE_FAST_SHRINK, 1, 1, 1, "sorted_set",
+        "Returns the lowest-scoring members from a sorted set after removing them. Deletes the sorted set if the \
+         last member was popped.", "5.0.0"),
+    spec("zrange", -4, READONLY, 1, 1, 1, "sorted_set",
+        "Returns members in a sorted set within a range of indexes.", "1.2.0"),
+    spec("zrank", 3, READONLY_FAST, 1, 1, 1, "sorted_set",
+        "Returns the index of a member in a sorted set ordered by ascending scores.", "2.0.0"),
+    spec("zrem", -3, WRITE_FAST_SHRINK, 1, 1, 1, "sorted_set",
+        "Removes one or more members from a sorted set. Deletes the sorted set if all members were removed.",
+        "1.2.0"),
+    spec("zremrangebylex", 4, WRITE, 1, 1, 1, "sorted_set",
+        "Removes members in a sorted set within a lexicographical range. Deletes the sorted set if all members \
+         were removed.", "2.8.9"),
+    spec("zremrangebyrank", 4, WRITE, 1, 1, 1, "sorted_set",
+        "Removes members in a sorted set within a range of indexes. Deletes the sorted set if all members were \
+         removed.", "2.0.0"),
+    spec("zremrangebyscore", 4, WRITE, 1, 1, 1, "sorted_set",
+        "Removes members in a sorted set within a range of scores. Deletes the sorted set if all members were \
+         removed.", "1.2.0"),
+    spec("zrevrank", 3, READONLY_FAST, 1, 1, 1, "sorted_set",
+        "Returns the index of a member in a sorted set ordered by descending scores.", "2.0.0"),
+    spec("zscore", 3, READONLY_FAST, 1, 1, 1, "sorted_set",
+        "Returns the score of a member in a sorted set.", "1.2.0"),
+    spec("zunionstore", -4, WRITE, 1, 1, 1, "sorted_set",
+        "Stores the union of multiple sorted sets in a key.", "2.0.0"),
+];
+
+/// Looks up a command by name, ignoring case.
+pub fn lookup(name: &[u8]) -> Option<&'static CommandSpec> {
+    COMMANDS.iter().find(|spec| spec.name.as_bytes().eq_ignore_ascii_case(name))
+}
+
+impl CommandSpec {
+    /// Whether a request with `argc` arguments (including the name) satisfies
+    /// this command's arity.
+    pub fn accepts(&self, argc: usize) -> bool {
+        let argc = argc as i64;
+        if self.arity < 0 {
+            argc >= -self.arity
+        } else {
+            argc == self.arity
+        }
+    }
+
+    /// The reply element describing this command in COMMAND and COMMAND INFO.
+    pub fn info(&self) -> RedisValue {
+        let flags = self.flags.iter()
+            .map(|flag| RedisValue::String(flag.to_string()))
+            .collect();
+
+        let mut categories = Vec::new();
+        for flag in self.flags {
+            match *flag {
+                "write" => categories.push("@write".to_string()),
+                "readonly" => categories.push("@read".to_string()),
+                "blocking" => categories.push("@blocking".to_string()),
+                _ => {},
+            }
+        }
+        categories.push(if self.flags.contains(&"fast") { "@fast" } else { "@slow" }.to_string());
+        categories.push(format!("@{}", self.group));
+        let categories = categories.into_iter().map(RedisValue::String).collect();
+
+        RedisValue::Array(vec![
+            RedisValue::Bytes(self.name.into()),
+            RedisValue::Integer(self.arity),
+            RedisValue::Set(flags),
+            RedisValue::Integer(self.first_key),
+            RedisValue::Integer(self.last_key),
+            RedisValue::Integer(self.step),
+            RedisValue::Set(categories),
+            RedisValue::Set(Vec::new()),
+            RedisValue::Array(Vec::new()),
+            RedisValue::Array(Vec::new()),
+        ])
+    }
+
+    /// The reply element describing this command in COMMAND DOCS.
+    pub fn docs(&self) -> RedisValue {
+        RedisValue::Map(vec![
+            (RedisValue::Bytes("summary".into()), RedisValue::Bytes(self.summary.into())),
+            (RedisValue::Bytes("since".into()), RedisValue::Bytes(self.since.into())),
+            (RedisValue::Bytes("group".into()), RedisValue::Bytes(self.group.into())),
+        ])
+    }
+}
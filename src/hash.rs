@@ -0,0 +1,199 @@
+use std::collections::{BTreeSet, HashMap};
+use std::hash::{DefaultHasher, Hash as _, Hasher};
+use std::time::Instant;
+use bytes::Bytes;
+
+use crate::storage::Expiry;
+
+/// Hashes with more fields than this are stored as a table.
+const MAX_COMPACT_FIELDS: usize = 128;
+/// Hashes holding a field or value longer than this are stored as a table.
+const MAX_COMPACT_LEN: usize = 64;
+
+/// The fields of a hash value.
+///
+/// Small hashes are kept as a flat list of pairs searched linearly, which at
+/// that size is both smaller and about as fast as a table. As in Redis, a hash
+/// that outgrows the compact limits is converted to a table for good.
+///
+/// Fields may carry a TTL of their own. The hash only records the deadlines;
+/// deleting fields once they pass is up to the caller.
+#[derive(Debug)]
+pub struct Hash {
+    fields: Fields,
+    expiries: HashMap<Bytes, Expiry>,
+    /// Fields with a TTL as `(deadline, field)`, soonest first.
+    deadlines: BTreeSet<(Instant, Bytes)>,
+}
+
+#[derive(Debug)]
+enum Fields {
+    Compact(Vec<(Bytes, Bytes)>),
+    /// A table, with its fields also ordered by `scan_position` so that an
+    /// HSCAN step can resume where the last one stopped.
+    Table(HashMap<Bytes, Bytes>, BTreeSet<(u64, Bytes)>),
+}
+
+impl Default for Hash {
+    fn default() -> Self {
+        Self { fields: Fields::Compact(Vec::new()), expiries: HashMap::new(), deadlines: BTreeSet::new() }
+    }
+}
+
+impl Hash {
+    pub fn len(&self) -> usize {
+        match &self.fields {
+            Fields::Compact(pairs) => pairs.len(),
+            Fields::Table(table, _) => table.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, field: &[u8]) -> Option<&Bytes> {
+        match &self.fields {
+            Fields::Compact(pairs) => pairs.iter().find(|(name, _)| name == field).map(|(_, value)| value),
+            Fields::Table(table, _) => table.get(field),
+        }
+    }
+
+    /// Sets `field` to `value`, returning whether the field is new. A TTL the
+    /// field had is kept.
+    pub fn insert(&mut self, field: Bytes, value: Bytes) -> bool {
+        if let Fields::Compact(pairs) = &mut self.fields {
+            let current = pairs.iter_mut().find(|(name, _)| *name == field);
+            if value.len() <= MAX_COMPACT_LEN {
+                if let Some((_, current)) = current {
+                    *current = value;
+                    return false;
+                }
+                if pairs.len() < MAX_COMPACT_FIELDS && field.len() <= MAX_COMPACT_LEN {
+                    pairs.push((field, value));
+                    return true;
+                }
+            }
+            let order = pairs.iter().map(|(name, _)| (scan_position(name), name.clone())).collect();
+            self.fields = Fields::Table(std::mem::take(pairs).into_iter().collect(), order);
+        }
+
+        let Fields::Table(table, order) = &mut self.fields else {
+            unreachable!("converted above");
+        };
+        let added = table.insert(field.clone(), value).is_none();
+        if added {
+            order.insert((scan_position(&field), field));
+        }
+        added
+    }
+
+    pub fn remove(&mut self, field: &[u8]) -> Option<Bytes> {
+        if let Some(expiry) = self.expiries.remove(field) {
+            self.deadlines.remove(&(expiry.at, Bytes::copy_from_slice(field)));
+        }
+        match &mut self.fields {
+            Fields::Compact(pairs) => {
+                let position = pairs.iter().position(|(name, _)| name == field)?;
+                Some(pairs.remove(position).1)
+            },
+            Fields::Table(table, order) => {
+                let (field, value) = table.remove_entry(field)?;
+                order.remove(&(scan_position(&field), field));
+                Some(value)
+            },
+        }
+    }
+
+    pub fn expiry(&self, field: &[u8]) -> Option<Expiry> {
+        self.expiries.get(field).copied()
+    }
+
+    /// Sets or, given `None`, clears the TTL of an existing field. Returns the
+    /// TTL it had before.
+    pub fn set_expiry(&mut self, field: &[u8], expiry: Option<Expiry>) -> Option<Expiry> {
+        let field = Bytes::copy_from_slice(field);
+        let old = match expiry {
+            Some(expiry) if self.get(&field).is_some() => {
+                self.deadlines.insert((expiry.at, field.clone()));
+                self.expiries.insert(field.clone(), expiry)
+            },
+            Some(_) => return None,
+            None => self.expiries.remove(&field),
+        };
+        if let Some(old) = old
+            && expiry.is_none_or(|expiry| expiry.at != old.at) {
+            self.deadlines.remove(&(old.at, field));
+        }
+        old
+    }
+
+    /// Fields with a TTL and their deadlines.
+    pub fn expiries(&self) -> impl Iterator<Item = (&Bytes, Expiry)> {
+        self.expiries.iter().map(|(field, &expiry)| (field, expiry))
+    }
+
+    /// Whether any field is past its deadline.
+    pub fn has_expired_fields(&self, now: Instant) -> bool {
+        self.deadlines.first().is_some_and(|(at, _)| *at <= now)
+    }
+
+    /// Deletes the fields past their deadline, returning them with the TTLs
+    /// they had.
+    pub fn remove_expired(&mut self, now: Instant) -> Vec<(Bytes, Expiry)> {
+        let mut expired = Vec::new();
+        while let Some((at, _)) = self.deadlines.first()
+            && *at <= now {
+            let (_, field) = self.deadlines.pop_first().expect("checked above");
+            let expiry = self.expiries.remove(&field).expect("indexed field has a TTL");
+            self.remove(&field);
+            expired.push((field, expiry));
+        }
+        expired
+    }
+
+    /// Fields and their values, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (&Bytes, &Bytes)> {
+        let (compact, table) = match &self.fields {
+            Fields::Compact(pairs) => (Some(pairs), None),
+            Fields::Table(table, _) => (None, Some(table)),
+        };
+        compact.into_iter().flatten()
+            .map(|(field, value)| (field, value))
+            .chain(table.into_iter().flatten())
+    }
+
+    /// One step of an HSCAN that started at cursor 0: returns roughly `count`
+    /// fields and the cursor to continue from, 0 once the scan is complete.
+    ///
+    /// A compact hash is returned whole in one step. A table is walked in the
+    /// order of a fixed hash of its fields, the cursor being the next hash
+    /// value to visit, so a field present for the whole scan is returned
+    /// exactly once however the hash changes in between.
+    pub fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<(&Bytes, &Bytes)>) {
+        let Fields::Table(table, order) = &self.fields else {
+            return (0, self.iter().collect());
+        };
+
+        let mut fields = Vec::new();
+        let mut last = None;
+        for (position, field) in order.range((cursor, Bytes::new())..) {
+            // Fields sharing a position must be returned together, or the
+            // cursor could not resume between them
+            if fields.len() >= count && last != Some(*position) {
+                return (*position, fields);
+            }
+            fields.extend(table.get_key_value(field));
+            last = Some(*position);
+        }
+        (0, fields)
+    }
+}
+
+/// Where a field sits in the order HSCAN walks a table. The hasher is not
+/// seeded, so positions stay the same across calls.
+fn scan_position(field: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    field.hash(&mut hasher);
+    hasher.finish()
+}
@@ -1,6 +1,18 @@
+mod blocking;
+mod command;
+mod glob;
+mod hash;
+mod list;
 mod storage;
 mod protocol;
+mod queue;
+mod set;
+mod sorted_set;
+mod stream;
 mod server;
 pub mod client;
 
 pub use server::Server;
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,71 @@
+use std::collections::VecDeque;
+use bytes::Bytes;
+
+/// The end of a list an element is pushed to or popped from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListEnd {
+    Left,
+    Right,
+}
+
+pub fn push(list: &mut VecDeque<Bytes>, end: ListEnd, value: Bytes) {
+    match end {
+        ListEnd::Left => list.push_front(value),
+        ListEnd::Right => list.push_back(value),
+    }
+}
+
+pub fn pop(list: &mut VecDeque<Bytes>, end: ListEnd) -> Option<Bytes> {
+    match end {
+        ListEnd::Left => list.pop_front(),
+        ListEnd::Right => list.pop_back(),
+    }
+}
+
+/// Resolves a Redis index, where negative values count from the end, to a
+/// position in a list of `len` elements.
+pub fn index(len: usize, index: i64) -> Option<usize> {
+    let index = if index < 0 { len as i64 + index } else { index };
+    (0..len as i64).contains(&index).then_some(index as usize)
+}
+
+/// Resolves an inclusive Redis range to positions in a list of `len`
+/// elements, clamping out-of-range bounds the way LRANGE and LTRIM do.
+/// Returns `None` if the range is empty.
+pub fn range(len: usize, start: i64, stop: i64) -> Option<(usize, usize)> {
+    let len = len as i64;
+    let start = if start < 0 { (len + start).max(0) } else { start };
+    let stop = if stop < 0 { len + stop } else { stop.min(len - 1) };
+
+    (start <= stop && start < len).then_some((start as usize, stop as usize))
+}
+
+/// Removes up to `count` elements equal to `value`: from the head when
+/// `count` is positive, from the tail when negative, and all of them when
+/// zero. Returns how many were removed.
+pub fn remove(list: &mut VecDeque<Bytes>, count: i64, value: &[u8]) -> usize {
+    let limit = if count == 0 { usize::MAX } else { count.unsigned_abs() as usize };
+    let mut removed = 0;
+
+    if count >= 0 {
+        let mut i = 0;
+        while i < list.len() && removed < limit {
+            if list[i] == value {
+                list.remove(i);
+                removed += 1;
+            } else {
+                i += 1;
+            }
+        }
+    } else {
+        let mut i = list.len();
+        while i > 0 && removed < limit {
+            i -= 1;
+            if list[i] == value {
+                list.remove(i);
+                removed += 1;
+            }
+        }
+    }
+    removed
+}
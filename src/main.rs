@@ -1,9 +1,6 @@
-mod storage;
-mod protocol;
-mod server;
-
 use clap::Parser;
 use log::info;
+use rudis::Server;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -11,6 +8,10 @@ struct Args {
     /// Listen address
     #[arg(short, long, default_value = "127.0.0.1:6379")]
     address: String,
+    
+    /// Rejections after which a queue item is moved to the dead-letter queue
+    #[arg(long)]
+    max_failures: Option<u64>,
 }
 
 #[tokio::main]
@@ -23,8 +24,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     
     info!("Starting Rudis server");
     
-    let server = server::Server::new(args.address);
+    let mut server = Server::new(args.address);
+    if let Some(max_failures) = args.max_failures {
+        server = server.with_max_failures(max_failures);
+    }
     server.run().await?;
     
     Ok(())
-}
\ No newline at end of file
+}
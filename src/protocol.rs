@@ -1,19 +1,226 @@
 use bytes::{Bytes, BytesMut, Buf, BufMut};
 use std::io;
+use std::ops::Bound;
+use std::time::Duration;
 use thiserror::Error;
 
+use crate::command;
+use crate::list::ListEnd;
+use crate::set::SetOp;
+use crate::sorted_set::{Aggregate, LexBound, Limit, RangeBy, ScoreBound};
+use crate::storage::{ExpireCondition, SetCondition, StreamAddOptions, ZAddOptions};
+use crate::stream::{ClaimOptions, Fields, GroupRead, NewId, PendingFilter, StreamId, Trim};
+
+/// Largest bulk string accepted in a request (Redis' `proto-max-bulk-len`).
+const MAX_BULK_LEN: usize = 512 * 1024 * 1024;
+/// Largest number of elements accepted in a request array.
+const MAX_MULTIBULK_LEN: usize = 1024 * 1024;
+/// Largest inline command or header line we buffer while waiting for a terminator.
+const MAX_INLINE_LEN: usize = 64 * 1024;
+/// How long QRESERVE hides an item when no VISIBILITY is given.
+const DEFAULT_VISIBILITY: Duration = Duration::from_secs(30);
+/// How many elements a SCAN-family step visits when no COUNT is given.
+const DEFAULT_SCAN_COUNT: usize = 10;
+
 #[derive(Debug)]
 pub enum RedisCommand {
     Get { key: String },
-    Set { key: String, value: Bytes, ttl: Option<u64> },
-    Delete { key: String },
+    GetDel { key: String },
+    /// GETEX; `persist` removes the TTL instead of setting one.
+    GetEx { key: String, expiration: Option<Expiration>, persist: bool },
+    GetRange { key: String, start: i64, end: i64 },
+    Append { key: String, value: Bytes },
+    StrLen { key: String },
+    /// INCR / DECR / INCRBY / DECRBY, with decrements negated.
+    IncrBy { key: String, increment: i64 },
+    IncrByFloat { key: String, increment: f64 },
+    Set {
+        key: String,
+        value: Bytes,
+        expiration: Option<SetExpiration>,
+        condition: Option<SetCondition>,
+        get: bool,
+    },
+    SetNx { key: String, value: Bytes },
+    SetRange { key: String, offset: usize, value: Bytes },
+    Delete { keys: Vec<String> },
+    Expire { key: String, expiration: Expiration, condition: ExpireCondition },
+    Ttl { key: String, millis: bool },
+    ExpireTime { key: String, millis: bool },
+    Persist { key: String },
+    ListPush { key: String, end: ListEnd, values: Vec<Bytes> },
+    /// `count` is `None` when no count was given, which changes the reply shape.
+    ListPop { key: String, end: ListEnd, count: Option<usize> },
+    ListRange { key: String, start: i64, stop: i64 },
+    ListLen { key: String },
+    ListIndex { key: String, index: i64 },
+    ListSet { key: String, index: i64, value: Bytes },
+    ListRem { key: String, count: i64, value: Bytes },
+    ListTrim { key: String, start: i64, stop: i64 },
+    ListInsert { key: String, before: bool, pivot: Bytes, value: Bytes },
+    ListMove { source: String, destination: String, from: ListEnd, to: ListEnd },
+    /// BLPOP / BRPOP; `timeout` is `None` to wait forever.
+    BlockingListPop { keys: Vec<String>, end: ListEnd, timeout: Option<Duration> },
+    BlockingListMove {
+        source: String,
+        destination: String,
+        from: ListEnd,
+        to: ListEnd,
+        timeout: Option<Duration>,
+    },
+    BlockingListMPop { keys: Vec<String>, end: ListEnd, count: usize, timeout: Option<Duration> },
+    HashSet { key: String, pairs: Vec<(Bytes, Bytes)> },
+    HashGet { key: String, field: Bytes },
+    HashMGet { key: String, fields: Vec<Bytes> },
+    HashDel { key: String, fields: Vec<Bytes> },
+    HashGetAll { key: String },
+    HashIncrBy { key: String, field: Bytes, increment: i64 },
+    HashIncrByFloat { key: String, field: Bytes, increment: f64 },
+    HashExists { key: String, field: Bytes },
+    HashLen { key: String },
+    HashKeys { key: String },
+    HashVals { key: String },
+    HashExpire { key: String, expiration: Expiration, condition: ExpireCondition, fields: Vec<Bytes> },
+    HashTtl { key: String, fields: Vec<Bytes> },
+    HashPersist { key: String, fields: Vec<Bytes> },
+    /// `novalues` leaves the values out of the reply.
+    HashScan { key: String, cursor: u64, pattern: Option<Bytes>, count: usize, novalues: bool },
+    SetAdd { key: String, members: Vec<Bytes> },
+    SetRem { key: String, members: Vec<Bytes> },
+    SetMembers { key: String },
+    SetIsMember { key: String, member: Bytes },
+    SetMIsMember { key: String, members: Vec<Bytes> },
+    SetCard { key: String },
+    /// `count` is `None` when no count was given, which changes the reply shape.
+    SetPop { key: String, count: Option<usize> },
+    /// A negative `count` allows members to repeat.
+    SetRandMember { key: String, count: Option<i64> },
+    /// SINTER / SUNION / SDIFF.
+    SetCombine { keys: Vec<String>, op: SetOp },
+    SetCombineStore { destination: String, keys: Vec<String>, op: SetOp },
+    /// `limit` is 0 for no limit.
+    SetInterCard { keys: Vec<String>, limit: usize },
+    /// `increment` is ZADD's INCR flag, which allows a single member.
+    ZAdd { key: String, options: ZAddOptions, increment: bool, members: Vec<(f64, Bytes)> },
+    ZCard { key: String },
+    ZCount { key: String, by: RangeBy },
+    ZIncrBy { key: String, increment: f64, member: Bytes },
+    ZRange { key: String, by: RangeBy, rev: bool, limit: Limit, with_scores: bool },
+    /// ZRANK / ZREVRANK.
+    ZRank { key: String, member: Bytes, rev: bool },
+    ZScore { key: String, member: Bytes },
+    ZMScore { key: String, members: Vec<Bytes> },
+    ZRem { key: String, members: Vec<Bytes> },
+    /// ZREMRANGEBYRANK / ZREMRANGEBYSCORE / ZREMRANGEBYLEX.
+    ZRemRange { key: String, by: RangeBy },
+    /// ZPOPMIN / ZPOPMAX; `count` is `None` when not given, which changes the
+    /// reply shape.
+    ZPop { key: String, max: bool, count: Option<usize> },
+    /// ZUNIONSTORE / ZINTERSTORE; `weights` is empty when not given.
+    ZCombineStore { destination: String, keys: Vec<String>, weights: Vec<f64>, aggregate: Aggregate, op: SetOp },
+    XAdd { key: String, id: NewId, fields: Fields, options: StreamAddOptions },
+    XLen { key: String },
+    /// XRANGE / XREVRANGE; `start` is the lower end even when reversed.
+    XRange { key: String, start: Bound<StreamId>, end: Bound<StreamId>, count: Option<usize>, rev: bool },
+    XDel { key: String, ids: Vec<StreamId> },
+    /// A `None` ID stands for `$`. `block` is `None` without BLOCK, and
+    /// `Some(None)` to block forever.
+    XRead { streams: Vec<(String, Option<StreamId>)>, count: Option<usize>, block: Option<Option<Duration>> },
+    XReadGroup {
+        group: Bytes,
+        consumer: Bytes,
+        streams: Vec<(String, GroupRead)>,
+        count: Option<usize>,
+        block: Option<Option<Duration>>,
+        noack: bool,
+    },
+    XGroup { key: String, group: Bytes, subcommand: XGroupSubcommand },
+    XAck { key: String, group: Bytes, ids: Vec<StreamId> },
+    /// `filter` is `None` for the summary form.
+    XPending { key: String, group: Bytes, filter: Option<PendingFilter> },
+    XClaim { key: String, group: Bytes, consumer: Bytes, ids: Vec<StreamId>, options: ClaimOptions },
+    XAutoClaim { key: String, group: Bytes, consumer: Bytes, start: StreamId, count: usize, options: ClaimOptions },
+    XInfo { key: String, subcommand: XInfoSubcommand },
     Pop,
-    Ping,
+    /// POP that blocks until a key is SET; `timeout` is `None` to wait forever.
+    BPop { timeout: Option<Duration> },
+    /// `delay` keeps the item hidden until then (`DELAY ms` or `DELAYAT unix-ms`).
+    QPush {
+        queue: String,
+        value: Bytes,
+        expiration: Option<Expiration>,
+        delay: Option<Expiration>,
+        priority: i64,
+    },
+    /// `count` is `None` when no COUNT was given, which changes the reply shape.
+    QPop { queue: String, count: Option<usize> },
+    QPeek { queue: String, count: Option<usize> },
+    QLen { queue: String },
+    QDepth { queue: String },
+    QReserve { queue: String, visibility: Duration },
+    QAck { queue: String, deliveries: Vec<u64> },
+    QNack { queue: String, deliveries: Vec<u64> },
+    QReject { queue: String, deliveries: Vec<u64> },
+    QDlq { queue: String, subcommand: DlqSubcommand },
+    QScheduled { queue: String, count: Option<usize> },
+    QCancel { queue: String, ids: Vec<u64> },
+    Ping { message: Option<Bytes> },
     Info,
     Keys { pattern: String },
+    Hello { protover: Option<i64>, auth: Option<(String, Bytes)>, setname: Option<String> },
+    Command { subcommand: Option<CommandSubcommand> },
+}
+
+/// What QDLQ does with a queue's dead-letter queue.
+#[derive(Debug)]
+pub enum DlqSubcommand {
+    List { count: Option<usize> },
+    Len,
+    Replay { count: Option<usize> },
+    Purge,
 }
 
+/// What XGROUP does with a stream's consumer groups. A `None` ID stands for
+/// `$`, the stream's last ID.
 #[derive(Debug)]
+pub enum XGroupSubcommand {
+    Create { id: Option<StreamId>, mkstream: bool },
+    SetId { id: Option<StreamId> },
+    Destroy,
+    CreateConsumer { consumer: Bytes },
+    DelConsumer { consumer: Bytes },
+}
+
+#[derive(Debug)]
+pub enum XInfoSubcommand {
+    Stream,
+    Groups,
+    Consumers { group: Bytes },
+}
+
+/// A requested expiry, relative or as a wall-clock Unix timestamp.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expiration {
+    After(Duration),
+    AtUnixMillis(u64),
+}
+
+/// What SET does with the key's TTL.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetExpiration {
+    Expire(Expiration),
+    /// Retain whatever TTL the key already has (`KEEPTTL`).
+    KeepTtl,
+}
+
+#[derive(Debug)]
+pub enum CommandSubcommand {
+    Count,
+    Info { names: Vec<String> },
+    Docs { names: Vec<String> },
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum RedisValue {
     String(String),
     Bytes(Bytes),
@@ -21,183 +228,1877 @@ pub enum RedisValue {
     Nil,
     Error(String),
     Array(Vec<RedisValue>),
+    // RESP3 types; each has a RESP2 fallback encoding in `serialize_response`
+    Map(Vec<(RedisValue, RedisValue)>),
+    Set(Vec<RedisValue>),
+    Double(f64),
+    Boolean(bool),
+    BigNumber(String),
+    Verbatim { format: String, text: String },
+    Push(Vec<RedisValue>),
+    /// A null aggregate, encoded as `*-1` under RESP2 (unlike `Nil`'s `$-1`).
+    Null,
+}
+
+/// Wire protocol negotiated by a connection through HELLO.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ProtocolVersion {
+    #[default]
+    Resp2,
+    Resp3,
 }
 
+/// Errors raised while decoding a request. Their messages are the canonical
+/// Redis error replies, prefix included, so they can be sent back verbatim.
 #[derive(Error, Debug)]
 pub enum ProtocolError {
-    #[error("invalid protocol format")]
+    #[error("ERR invalid protocol format")]
     InvalidFormat,
-    #[error("invalid command")]
-    InvalidCommand,
+    #[error("ERR unknown command '{name}', with args beginning with: {args}")]
+    UnknownCommand { name: String, args: String },
+    #[error("ERR wrong number of arguments for '{0}' command")]
+    WrongArity(&'static str),
+    #[error("ERR unknown subcommand '{subcommand}'. Try {command} HELP.")]
+    UnknownSubcommand { command: &'static str, subcommand: String },
+    #[error("ERR syntax error")]
+    Syntax,
+    #[error("ERR value is not an integer or out of range")]
+    NotInteger,
+    #[error("ERR value is out of range, must be positive")]
+    NotPositive,
+    #[error("ERR value is not a valid float")]
+    NotFloat,
+    #[error("ERR value is NaN or Infinity")]
+    NaNOrInfinity,
+    #[error("ERR invalid cursor")]
+    InvalidCursor,
+    #[error("ERR timeout is not a float or out of range")]
+    InvalidTimeout,
+    #[error("ERR timeout is negative")]
+    NegativeTimeout,
+    #[error("ERR {0} should be greater than 0")]
+    NotGreaterThanZero(&'static str),
+    #[error("ERR invalid expire time in '{0}' command")]
+    InvalidExpireTime(&'static str),
+    #[error("ERR invalid expire time, must be >= 0")]
+    NegativeExpireTime,
+    #[error("ERR Mandatory argument FIELDS is missing or not at the right position")]
+    MissingFields,
+    #[error("ERR Parameter `numFields` should be greater than 0")]
+    InvalidNumFields,
+    #[error("ERR The `numfields` parameter must match the number of arguments")]
+    NumFieldsMismatch,
+    #[error("ERR Number of keys can't be greater than number of args")]
+    TooManyKeys,
+    #[error("ERR LIMIT can't be negative")]
+    NegativeLimit,
+    #[error("ERR min or max is not a float")]
+    InvalidScoreRange,
+    #[error("ERR min or max not valid string range item")]
+    InvalidLexRange,
+    #[error("ERR INCR option supports a single increment-element pair")]
+    IncrSinglePair,
+    #[error("ERR syntax error, LIMIT is only supported in combination with either BYSCORE or BYLEX")]
+    LimitWithoutBy,
+    #[error("ERR syntax error, WITHSCORES not supported in combination with BYLEX")]
+    WithScoresByLex,
+    #[error("ERR at least 1 input key is needed for '{0}' command")]
+    NoInputKeys(&'static str),
+    #[error("ERR weight value is not a float")]
+    InvalidWeight,
+    #[error("ERR decrement would overflow")]
+    DecrementOverflow,
+    #[error("ERR offset is out of range")]
+    OffsetOutOfRange,
+    #[error("ERR value is out of range")]
+    ValueOutOfRange,
+    #[error("ERR timeout is not an integer or out of range")]
+    TimeoutNotInteger,
+    #[error("ERR Invalid stream ID specified as stream command argument")]
+    InvalidStreamId,
+    #[error("ERR The ID specified in XADD must be greater than 0-0")]
+    StreamIdZero,
+    #[error("ERR The MAXLEN argument must be >= 0.")]
+    NegativeMaxLen,
+    #[error("ERR syntax error, LIMIT cannot be used without the special ~ option")]
+    LimitWithoutApprox,
+    #[error("ERR Unbalanced '{command}' list of streams: for each stream key an ID or '{id}' must be specified.")]
+    UnbalancedStreams { command: &'static str, id: &'static str },
+    #[error("ERR Unsupported option {0}")]
+    UnsupportedOption(String),
+    #[error("ERR {0} options at the same time are not compatible")]
+    IncompatibleOptions(&'static str),
+    /// The byte stream is malformed and cannot be resynchronised; the
+    /// connection should be closed after reporting it.
+    #[error("ERR Protocol error: {0}")]
+    Protocol(String),
     #[error("io error: {0}")]
     Io(#[from] io::Error),
 }
 
 type Result<T> = std::result::Result<T, ProtocolError>;
 
+/// Decodes the next command from `buffer`.
+///
+/// Returns `Ok(None)` when the buffer does not yet hold a complete frame. Only
+/// the bytes of the decoded frame are consumed, so anything after it (such as
+/// pipelined commands) stays in the buffer.
 pub fn parse_command(buffer: &mut BytesMut) -> Result<Option<RedisCommand>> {
-    if buffer.is_empty() {
-        return Ok(None);
+    match read_args(buffer)? {
+        Some(args) => command_from_args(args).map(Some),
+        None => Ok(None),
     }
-    
-    // Debug the raw buffer
-    println!("Raw buffer: {:?}", buffer);
-    
-    // Check if we have a complete command (ending with \r\n)
-    if !buffer.windows(2).any(|window| window == b"\r\n") {
+}
+
+/// Decodes one complete RESP value of any type from the front of `buffer`.
+///
+/// Used for reading replies; like [`parse_command`] it returns `Ok(None)` on a
+/// partial frame and leaves trailing bytes untouched.
+pub fn parse_value(buffer: &mut BytesMut) -> Result<Option<RedisValue>> {
+    match decode_value(buffer, 0)? {
+        Some((value, consumed)) => {
+            buffer.advance(consumed);
+            Ok(Some(value))
+        },
+        None => Ok(None),
+    }
+}
+
+/// Splits the next request frame into its raw arguments, skipping empty
+/// requests (blank inline lines, `*0`) the way Redis does.
+fn read_args(buffer: &mut BytesMut) -> Result<Option<Vec<Bytes>>> {
+    loop {
+        if buffer.is_empty() {
+            return Ok(None);
+        }
+
+        let decoded = if buffer[0] == b'*' {
+            decode_multibulk(buffer)?
+        } else {
+            decode_inline(buffer)?
+        };
+
+        let Some((args, consumed)) = decoded else {
+            return Ok(None);
+        };
+        buffer.advance(consumed);
+
+        if !args.is_empty() {
+            return Ok(Some(args));
+        }
+    }
+}
+
+/// Decodes a request of the form `*<n>\r\n$<len>\r\n<bytes>\r\n...`, honouring
+/// the declared bulk lengths so values may contain arbitrary bytes.
+fn decode_multibulk(buf: &[u8]) -> Result<Option<(Vec<Bytes>, usize)>> {
+    let Some((line, mut pos)) = read_line(buf, 0)? else {
         return Ok(None);
+    };
+
+    let count = parse_int(&line[1..])
+        .filter(|n| *n <= MAX_MULTIBULK_LEN as i64)
+        .ok_or_else(|| ProtocolError::Protocol("invalid multibulk length".to_string()))?;
+
+    let mut args = Vec::with_capacity(count.clamp(0, 1024) as usize);
+    for _ in 0..count {
+        let Some((line, start)) = read_line(buf, pos)? else {
+            return Ok(None);
+        };
+
+        if line.first() != Some(&b'$') {
+            let got = line.first().map(|&b| b as char).unwrap_or(' ');
+            return Err(ProtocolError::Protocol(format!("expected '$', got '{}'", got)));
+        }
+
+        let len = parse_int(&line[1..])
+            .filter(|n| (0..=MAX_BULK_LEN as i64).contains(n))
+            .ok_or_else(|| ProtocolError::Protocol("invalid bulk length".to_string()))? as usize;
+
+        let end = start + len;
+        if buf.len() < end + 2 {
+            return Ok(None);
+        }
+        if &buf[end..end + 2] != b"\r\n" {
+            return Err(ProtocolError::Protocol("bulk string not terminated by CRLF".to_string()));
+        }
+
+        args.push(Bytes::copy_from_slice(&buf[start..end]));
+        pos = end + 2;
     }
-    
-    // Convert to string for easier debugging
-    let cmd_str = std::str::from_utf8(buffer).map_err(|_| ProtocolError::InvalidFormat)?;
-    println!("Received command: {:?}", cmd_str);
-    
-    // Handle RESP protocol
-    if cmd_str.starts_with('*') {
-        // This is RESP array format
-        let lines: Vec<&str> = cmd_str.split("\r\n").collect();
-        println!("Split lines: {:?}", lines);
-        
-        if lines.len() < 3 {
-            return Err(ProtocolError::InvalidFormat);
+
+    Ok(Some((args, pos)))
+}
+
+/// Decodes a telnet-style inline command terminated by `\n` or `\r\n`.
+fn decode_inline(buf: &[u8]) -> Result<Option<(Vec<Bytes>, usize)>> {
+    let Some(end) = buf.iter().position(|&b| b == b'\n') else {
+        if buf.len() > MAX_INLINE_LEN {
+            return Err(ProtocolError::Protocol("too big inline request".to_string()));
         }
-        
-        // Extract command parts
-        let mut parts = Vec::new();
-        let mut i = 1; // Skip the first line (*n)
-        
-        while i < lines.len() {
-            if lines[i].starts_with('$') && i + 1 < lines.len() {
-                parts.push(lines[i + 1]);
-                i += 2;
-            } else {
-                i += 1;
-            }
+        return Ok(None);
+    };
+
+    let line = buf[..end].strip_suffix(b"\r").unwrap_or(&buf[..end]);
+    let args = split_inline_args(line)
+        .ok_or_else(|| ProtocolError::Protocol("unbalanced quotes in request".to_string()))?;
+
+    Ok(Some((args, end + 1)))
+}
+
+/// Splits an inline command into arguments following redis-cli quoting rules.
+///
+/// Double-quoted arguments understand `\xHH` and the usual C escapes, single-
+/// quoted arguments only `\'`. A closing quote must be followed by whitespace
+/// or the end of the line; returns `None` when quotes are unbalanced.
+fn split_inline_args(line: &[u8]) -> Option<Vec<Bytes>> {
+    let mut args = Vec::new();
+    let mut pos = 0;
+
+    loop {
+        while pos < line.len() && line[pos].is_ascii_whitespace() {
+            pos += 1;
         }
-        
-        println!("Parsed RESP parts: {:?}", parts);
-        
-        if parts.is_empty() {
-            return Err(ProtocolError::InvalidFormat);
+        if pos == line.len() {
+            return Some(args);
         }
-        
-        // Parse command
-        match parts[0].to_uppercase().as_str() {
-            "KEYS" => {
-                if parts.len() < 2 {
-                    return Err(ProtocolError::InvalidFormat);
-                }
-                println!("Processing KEYS command with pattern: {}", parts[1]);
-                Ok(Some(RedisCommand::Keys {
-                    pattern: parts[1].to_string()
-                }))
-            },
-            "GET" => {
-                if parts.len() < 2 {
-                    return Err(ProtocolError::InvalidFormat);
-                }
-                Ok(Some(RedisCommand::Get { 
-                    key: parts[1].to_string() 
-                }))
-            },
-            "SET" => {
-                if parts.len() < 3 {
-                    return Err(ProtocolError::InvalidFormat);
-                }
-                
-                let mut ttl = None;
-                if parts.len() > 4 && parts[3].to_uppercase() == "EX" {
-                    ttl = parts[4].parse::<u64>().ok();
-                }
-                
-                Ok(Some(RedisCommand::Set { 
-                    key: parts[1].to_string(),
-                    value: Bytes::from(parts[2].as_bytes().to_vec()),
-                    ttl,
-                }))
-            },
-            "DEL" => {
-                if parts.len() < 2 {
-                    return Err(ProtocolError::InvalidFormat);
-                }
-                Ok(Some(RedisCommand::Delete { 
-                    key: parts[1].to_string() 
-                }))
-            },
-            "POP" => Ok(Some(RedisCommand::Pop)),
-            "PING" => Ok(Some(RedisCommand::Ping)),
-            "INFO" => Ok(Some(RedisCommand::Info)),
-            _ => {
-                println!("Unknown command: {}", parts[0]);
-                Err(ProtocolError::InvalidCommand)
-            },
+
+        let mut arg = Vec::new();
+        let mut in_double = false;
+        let mut in_single = false;
+
+        loop {
+            let c = line.get(pos).copied();
+
+            if in_double {
+                match c {
+                    None => return None,
+                    Some(b'\\') if line.get(pos + 1) == Some(&b'x')
+                        && line.get(pos + 2).is_some_and(u8::is_ascii_hexdigit)
+                        && line.get(pos + 3).is_some_and(u8::is_ascii_hexdigit) =>
+                    {
+                        let hex = std::str::from_utf8(&line[pos + 2..pos + 4]).ok()?;
+                        arg.push(u8::from_str_radix(hex, 16).ok()?);
+                        pos += 3;
+                    },
+                    Some(b'\\') if pos + 1 < line.len() => {
+                        pos += 1;
+                        arg.push(match line[pos] {
+                            b'n' => b'\n',
+                            b'r' => b'\r',
+                            b't' => b'\t',
+                            b'b' => 0x08,
+                            b'a' => 0x07,
+                            other => other,
+                        });
+                    },
+                    Some(b'"') => {
+                        // The closing quote must end the argument
+                        if line.get(pos + 1).is_some_and(|b| !b.is_ascii_whitespace()) {
+                            return None;
+                        }
+                        pos += 1;
+                        break;
+                    },
+                    Some(other) => arg.push(other),
+                }
+            } else if in_single {
+                match c {
+                    None => return None,
+                    Some(b'\\') if line.get(pos + 1) == Some(&b'\'') => {
+                        pos += 1;
+                        arg.push(b'\'');
+                    },
+                    Some(b'\'') => {
+                        if line.get(pos + 1).is_some_and(|b| !b.is_ascii_whitespace()) {
+                            return None;
+                        }
+                        pos += 1;
+                        break;
+                    },
+                    Some(other) => arg.push(other),
+                }
+            } else {
+                match c {
+                    None => break,
+                    Some(b) if b.is_ascii_whitespace() => break,
+                    Some(b'"') => in_double = true,
+                    Some(b'\'') => in_single = true,
+                    Some(other) => arg.push(other),
+                }
+            }
+            pos += 1;
         }
+
+        args.push(Bytes::from(arg));
+    }
+}
+
+/// Decodes a single RESP value starting at `pos`, returning it together with
+/// the position just past its last byte.
+fn decode_value(buf: &[u8], pos: usize) -> Result<Option<(RedisValue, usize)>> {
+    let Some((line, next)) = read_line(buf, pos)? else {
+        return Ok(None);
+    };
+    let Some((&kind, payload)) = line.split_first() else {
+        return Err(ProtocolError::Protocol("empty type line".to_string()));
+    };
+
+    let value = match kind {
+        b'+' => RedisValue::String(String::from_utf8_lossy(payload).into_owned()),
+        b'-' => RedisValue::Error(String::from_utf8_lossy(payload).into_owned()),
+        b':' => RedisValue::Integer(
+            parse_int(payload).ok_or_else(|| ProtocolError::Protocol("invalid integer".to_string()))?,
+        ),
+        b'$' => {
+            let len = parse_int(payload)
+                .filter(|n| *n >= -1 && *n <= MAX_BULK_LEN as i64)
+                .ok_or_else(|| ProtocolError::Protocol("invalid bulk length".to_string()))?;
+            if len < 0 {
+                return Ok(Some((RedisValue::Nil, next)));
+            }
+
+            let end = next + len as usize;
+            if buf.len() < end + 2 {
+                return Ok(None);
+            }
+            return Ok(Some((RedisValue::Bytes(Bytes::copy_from_slice(&buf[next..end])), end + 2)));
+        },
+        b'*' => {
+            let count = parse_int(payload)
+                .filter(|n| *n >= -1 && *n <= MAX_MULTIBULK_LEN as i64)
+                .ok_or_else(|| ProtocolError::Protocol("invalid multibulk length".to_string()))?;
+            if count < 0 {
+                return Ok(Some((RedisValue::Nil, next)));
+            }
+
+            let mut items = Vec::with_capacity(count.min(1024) as usize);
+            let mut pos = next;
+            for _ in 0..count {
+                let Some((item, after)) = decode_value(buf, pos)? else {
+                    return Ok(None);
+                };
+                items.push(item);
+                pos = after;
+            }
+            return Ok(Some((RedisValue::Array(items), pos)));
+        },
+        b'_' => RedisValue::Null,
+        b'#' => match payload {
+            b"t" => RedisValue::Boolean(true),
+            b"f" => RedisValue::Boolean(false),
+            _ => return Err(ProtocolError::Protocol("invalid boolean".to_string())),
+        },
+        b',' => RedisValue::Double(
+            std::str::from_utf8(payload)
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .ok_or_else(|| ProtocolError::Protocol("invalid double".to_string()))?,
+        ),
+        b'(' => RedisValue::BigNumber(String::from_utf8_lossy(payload).into_owned()),
+        b'!' | b'=' => {
+            let len = parse_int(payload)
+                .filter(|n| (0..=MAX_BULK_LEN as i64).contains(n))
+                .ok_or_else(|| ProtocolError::Protocol("invalid bulk length".to_string()))?;
+
+            let end = next + len as usize;
+            if buf.len() < end + 2 {
+                return Ok(None);
+            }
+
+            let data = String::from_utf8_lossy(&buf[next..end]).into_owned();
+            let value = if kind == b'!' {
+                RedisValue::Error(data)
+            } else {
+                // Verbatim strings carry a three byte format prefix such as "txt:"
+                match data.split_once(':') {
+                    Some((format, text)) => RedisValue::Verbatim {
+                        format: format.to_string(),
+                        text: text.to_string(),
+                    },
+                    None => return Err(ProtocolError::Protocol("invalid verbatim string".to_string())),
+                }
+            };
+            return Ok(Some((value, end + 2)));
+        },
+        b'%' | b'~' | b'>' | b'|' => {
+            let count = parse_int(payload)
+                .filter(|n| (0..=MAX_MULTIBULK_LEN as i64).contains(n))
+                .ok_or_else(|| ProtocolError::Protocol("invalid aggregate length".to_string()))?;
+            let elements = if matches!(kind, b'%' | b'|') { count * 2 } else { count };
+
+            let mut items = Vec::with_capacity(elements.min(1024) as usize);
+            let mut pos = next;
+            for _ in 0..elements {
+                let Some((item, after)) = decode_value(buf, pos)? else {
+                    return Ok(None);
+                };
+                items.push(item);
+                pos = after;
+            }
+
+            let value = match kind {
+                b'~' => RedisValue::Set(items),
+                b'>' => RedisValue::Push(items),
+                b'%' => {
+                    let mut pairs = Vec::with_capacity(items.len() / 2);
+                    let mut items = items.into_iter();
+                    while let (Some(key), Some(value)) = (items.next(), items.next()) {
+                        pairs.push((key, value));
+                    }
+                    RedisValue::Map(pairs)
+                },
+                // Attributes are out-of-band metadata for the value that follows
+                _ => return decode_value(buf, pos),
+            };
+            return Ok(Some((value, pos)));
+        },
+        other => {
+            return Err(ProtocolError::Protocol(format!("unexpected type byte '{}'", other as char)));
+        },
+    };
+
+    Ok(Some((value, next)))
+}
+
+/// Finds the CRLF-terminated line starting at `pos`, returning its contents
+/// (without the terminator) and the position of the following byte.
+fn read_line(buf: &[u8], pos: usize) -> Result<Option<(&[u8], usize)>> {
+    match buf[pos..].windows(2).position(|window| window == b"\r\n") {
+        Some(len) => Ok(Some((&buf[pos..pos + len], pos + len + 2))),
+        None if buf.len() - pos > MAX_INLINE_LEN => {
+            Err(ProtocolError::Protocol("too big header line".to_string()))
+        },
+        None => Ok(None),
+    }
+}
+
+fn parse_int(bytes: &[u8]) -> Option<i64> {
+    std::str::from_utf8(bytes).ok()?.parse().ok()
+}
+
+/// Keys and patterns are stored as `String`, so they must be valid UTF-8 even
+/// though values may hold arbitrary bytes.
+fn string_arg(arg: &Bytes) -> Result<String> {
+    String::from_utf8(arg.to_vec()).map_err(|_| ProtocolError::InvalidFormat)
+}
+
+/// Parses an argument that must be a signed 64-bit integer.
+fn int_arg(arg: &Bytes) -> Result<i64> {
+    parse_int(arg).ok_or(ProtocolError::NotInteger)
+}
+
+/// Validates a positive expire amount and converts it to milliseconds,
+/// rejecting values that would overflow the way Redis does.
+fn expire_millis(amount: i64, in_seconds: bool, command: &'static str) -> Result<u64> {
+    let millis = if in_seconds { amount.checked_mul(1000) } else { Some(amount) };
+    match millis {
+        Some(millis) if amount > 0 => Ok(millis as u64),
+        _ => Err(ProtocolError::InvalidExpireTime(command)),
+    }
+}
+
+/// Parses the amount after a SET-style `EX`, `PX`, `EXAT` or `PXAT` option.
+fn expiration_arg(unit: &[u8], amount: Option<&Bytes>, command: &'static str) -> Result<Expiration> {
+    let amount = int_arg(amount.ok_or(ProtocolError::Syntax)?)?;
+    let millis = expire_millis(amount, matches!(unit, b"EX" | b"EXAT"), command)?;
+    
+    Ok(if unit.ends_with(b"AT") {
+        Expiration::AtUnixMillis(millis)
     } else {
-        // Simple text protocol
-        let parts: Vec<&str> = cmd_str.trim().split_whitespace().collect();
-        println!("Parsed simple parts: {:?}", parts);
-        
-        if parts.is_empty() {
-            return Err(ProtocolError::InvalidFormat);
-        }
-        
-        // Parse command
-        match parts[0].to_uppercase().as_str() {
-            "KEYS" => {
-                if parts.len() < 2 {
-                    return Err(ProtocolError::InvalidFormat);
-                }
-                println!("Processing KEYS command with pattern: {}", parts[1]);
-                Ok(Some(RedisCommand::Keys {
-                    pattern: parts[1].to_string()
-                }))
-            },
-            "GET" => {
-                if parts.len() < 2 {
-                    return Err(ProtocolError::InvalidFormat);
-                }
-                Ok(Some(RedisCommand::Get { 
-                    key: parts[1].to_string() 
-                }))
-            },
-            "SET" => {
-                if parts.len() < 3 {
-                    return Err(ProtocolError::InvalidFormat);
-                }
-                
-                let mut ttl = None;
-                if parts.len() > 4 && parts[3].to_uppercase() == "EX" {
-                    ttl = parts[4].parse::<u64>().ok();
-                }
-                
-                Ok(Some(RedisCommand::Set { 
-                    key: parts[1].to_string(),
-                    value: Bytes::from(parts[2].as_bytes().to_vec()),
-                    ttl,
-                }))
-            },
-            "DEL" => {
-                if parts.len() < 2 {
-                    return Err(ProtocolError::InvalidFormat);
-                }
-                Ok(Some(RedisCommand::Delete { 
-                    key: parts[1].to_string() 
-                }))
-            },
-            "POP" => Ok(Some(RedisCommand::Pop)),
-            "PING" => Ok(Some(RedisCommand::Ping)),
-            "INFO" => Ok(Some(RedisCommand::Info)),
-            _ => {
-                println!("Unknown command: {}", parts[0]);
-                Err(ProtocolError::InvalidCommand)
-            },
+        Expiration::After(Duration::from_millis(millis))
+    })
+}
+
+/// Parses an argument that must be a floating point number. Infinities are
+/// accepted here, as Redis does, and rejected by the commands that care.
+fn float_arg(arg: &Bytes) -> Result<f64> {
+    std::str::from_utf8(arg).ok()
+        .and_then(|arg| arg.parse::<f64>().ok())
+        .filter(|value| !value.is_nan())
+        .ok_or(ProtocolError::NotFloat)
+}
+
+/// Parses the `FIELDS numfields field...` block that ends HEXPIRE, HTTL and
+/// HPERSIST.
+fn fields_arg(args: &[Bytes]) -> Result<Vec<Bytes>> {
+    let [keyword, numfields, fields @ ..] = args else {
+        return Err(ProtocolError::MissingFields);
+    };
+    if !keyword.eq_ignore_ascii_case(b"FIELDS") {
+        return Err(ProtocolError::MissingFields);
+    }
+    match parse_int(numfields) {
+        Some(numfields) if numfields > 0 && numfields as usize == fields.len() => Ok(fields.to_vec()),
+        Some(numfields) if numfields > 0 => Err(ProtocolError::NumFieldsMismatch),
+        _ => Err(ProtocolError::InvalidNumFields),
+    }
+}
+
+/// Parses the `LEFT` / `RIGHT` argument of LMOVE and friends.
+fn list_end_arg(arg: &Bytes) -> Result<ListEnd> {
+    match arg.to_ascii_uppercase().as_slice() {
+        b"LEFT" => Ok(ListEnd::Left),
+        b"RIGHT" => Ok(ListEnd::Right),
+        _ => Err(ProtocolError::Syntax),
+    }
+}
+
+/// The set operation behind SINTER, SUNION, SDIFF and their STORE variants.
+fn set_op(name: &str) -> SetOp {
+    match name.trim_end_matches("store") {
+        "sinter" => SetOp::Inter,
+        "sunion" => SetOp::Union,
+        _ => SetOp::Diff,
+    }
+}
+
+/// Parses one end of a score range, a float optionally prefixed by `(` to
+/// exclude it.
+fn score_bound_arg(arg: &Bytes) -> Result<ScoreBound> {
+    let (exclusive, number) = match arg.strip_prefix(b"(") {
+        Some(number) => (true, number),
+        None => (false, &arg[..]),
+    };
+    let score = std::str::from_utf8(number).ok()
+        .and_then(|number| number.parse::<f64>().ok())
+        .filter(|score| !score.is_nan())
+        .ok_or(ProtocolError::InvalidScoreRange)?;
+    Ok(if exclusive { ScoreBound::Exclusive(score) } else { ScoreBound::Inclusive(score) })
+}
+
+/// Parses one end of a lexicographic range: `-`, `+`, or a member prefixed by
+/// `[` to include it or `(` to exclude it.
+fn lex_bound_arg(arg: &Bytes) -> Result<LexBound> {
+    match arg.first() {
+        Some(b'-') if arg.len() == 1 => Ok(LexBound::Min),
+        Some(b'+') if arg.len() == 1 => Ok(LexBound::Max),
+        Some(b'[') => Ok(LexBound::Inclusive(arg.slice(1..))),
+        Some(b'(') => Ok(LexBound::Exclusive(arg.slice(1..))),
+        _ => Err(ProtocolError::InvalidLexRange),
+    }
+}
+
+/// Parses the timeout of a blocking command, given in seconds with an optional
+/// fractional part. Zero means block forever.
+fn timeout_arg(arg: &Bytes) -> Result<Option<Duration>> {
+    let seconds: f64 = std::str::from_utf8(arg).ok()
+        .and_then(|arg| arg.parse().ok())
+        .filter(|seconds: &f64| seconds.is_finite())
+        .ok_or(ProtocolError::InvalidTimeout)?;
+
+    if seconds < 0.0 {
+        return Err(ProtocolError::NegativeTimeout);
+    }
+    if seconds == 0.0 {
+        return Ok(None);
+    }
+    Duration::try_from_secs_f64(seconds).map(Some).map_err(|_| ProtocolError::InvalidTimeout)
+}
+
+/// Parses the `BLOCK` milliseconds of XREAD and XREADGROUP. Zero means block
+/// forever.
+fn block_arg(arg: &Bytes) -> Result<Option<Duration>> {
+    match parse_int(arg) {
+        Some(millis) if millis < 0 => Err(ProtocolError::NegativeTimeout),
+        Some(millis) => Ok((millis > 0).then(|| Duration::from_millis(millis as u64))),
+        None => Err(ProtocolError::TimeoutNotInteger),
+    }
+}
+
+/// Parses a stream ID, `ms-seq` or just `ms`, in which case the sequence
+/// number is `missing_seq`.
+fn stream_id_arg(arg: &[u8], missing_seq: u64) -> Result<StreamId> {
+    let arg = std::str::from_utf8(arg).map_err(|_| ProtocolError::InvalidStreamId)?;
+    let (ms, seq) = match arg.split_once('-') {
+        Some((ms, seq)) => (ms, seq.parse().ok()),
+        None => (arg, Some(missing_seq)),
+    };
+    match (ms.parse(), seq) {
+        (Ok(ms), Some(seq)) => Ok(StreamId { ms, seq }),
+        _ => Err(ProtocolError::InvalidStreamId),
+    }
+}
+
+/// Parses one end of a stream range: `-`, `+`, or an ID prefixed by `(` to
+/// exclude it. An ID without a sequence number covers its whole millisecond.
+fn stream_bound_arg(arg: &Bytes, start: bool) -> Result<Bound<StreamId>> {
+    let missing_seq = if start { 0 } else { u64::MAX };
+    match &arg[..] {
+        b"-" => Ok(Bound::Included(StreamId::MIN)),
+        b"+" => Ok(Bound::Included(StreamId::MAX)),
+        [b'(', id @ ..] => Ok(Bound::Excluded(stream_id_arg(id, missing_seq)?)),
+        id => Ok(Bound::Included(stream_id_arg(id, missing_seq)?)),
+    }
+}
+
+/// Parses the ID given to XGROUP CREATE and SETID, where `$` stands for the
+/// stream's last ID.
+fn group_id_arg(arg: &Bytes) -> Result<Option<StreamId>> {
+    match &arg[..] {
+        b"$" => Ok(None),
+        id => stream_id_arg(id, 0).map(Some),
+    }
+}
+
+/// Parses a millisecond argument of XCLAIM and friends, where Redis treats
+/// negative values as zero.
+fn millis_arg(arg: &Bytes) -> Result<u64> {
+    Ok(int_arg(arg)?.max(0) as u64)
+}
+
+/// Parses the optional `COUNT n` suffix of QPOP and QPEEK.
+fn count_option(args: &[Bytes]) -> Result<Option<usize>> {
+    match args {
+        [] => Ok(None),
+        [option, count] if option.eq_ignore_ascii_case(b"COUNT") => match int_arg(count)? {
+            count if count > 0 => Ok(Some(count as usize)),
+            _ => Err(ProtocolError::NotPositive),
+        },
+        _ => Err(ProtocolError::Syntax),
+    }
+}
+
+/// Parses the ids given to QACK, QNACK, QREJECT and QCANCEL.
+fn delivery_args(args: &[Bytes]) -> Result<Vec<u64>> {
+    args.iter()
+        .map(|arg| u64::try_from(int_arg(arg)?).map_err(|_| ProtocolError::NotInteger))
+        .collect()
+}
+
+/// Builds the unknown command error, quoting the leading arguments the way
+/// Redis does so the reply identifies the request.
+fn unknown_command(args: &[Bytes]) -> ProtocolError {
+    let mut quoted = String::new();
+    for arg in &args[1..] {
+        if quoted.len() >= 128 {
+            break;
         }
+        let arg = String::from_utf8_lossy(arg);
+        let arg: String = arg.chars().take(128 - quoted.len()).collect();
+        quoted.push_str(&format!("'{}' ", arg));
+    }
+
+    ProtocolError::UnknownCommand {
+        name: String::from_utf8_lossy(&args[0]).chars().take(128).collect(),
+        args: quoted,
     }
 }
 
-pub fn serialize_response(value: RedisValue) -> Bytes {
+fn command_from_args(args: Vec<Bytes>) -> Result<RedisCommand> {
+    let spec = command::lookup(&args[0]).ok_or_else(|| unknown_command(&args))?;
+    if !spec.accepts(args.len()) {
+        return Err(ProtocolError::WrongArity(spec.name));
+    }
+
+    match spec.name {
+        "keys" => Ok(RedisCommand::Keys {
+            pattern: string_arg(&args[1])?,
+        }),
+        "get" => Ok(RedisCommand::Get {
+            key: string_arg(&args[1])?,
+        }),
+        "getdel" => Ok(RedisCommand::GetDel {
+            key: string_arg(&args[1])?,
+        }),
+        "getex" => {
+            let mut expiration = None;
+            let mut persist = false;
+
+            let mut i = 2;
+            while i < args.len() {
+                match args[i].to_ascii_uppercase().as_slice() {
+                    b"PERSIST" if expiration.is_none() && !persist => persist = true,
+                    unit @ (b"EX" | b"PX" | b"EXAT" | b"PXAT") if expiration.is_none() && !persist => {
+                        i += 1;
+                        expiration = Some(expiration_arg(unit, args.get(i), "getex")?);
+                    },
+                    _ => return Err(ProtocolError::Syntax),
+                }
+                i += 1;
+            }
+
+            Ok(RedisCommand::GetEx { key: string_arg(&args[1])?, expiration, persist })
+        },
+        "getrange" => Ok(RedisCommand::GetRange {
+            key: string_arg(&args[1])?,
+            start: int_arg(&args[2])?,
+            end: int_arg(&args[3])?,
+        }),
+        "getset" => Ok(RedisCommand::Set {
+            key: string_arg(&args[1])?,
+            value: args[2].clone(),
+            expiration: None,
+            condition: None,
+            get: true,
+        }),
+        "append" => Ok(RedisCommand::Append {
+            key: string_arg(&args[1])?,
+            value: args[2].clone(),
+        }),
+        "strlen" => Ok(RedisCommand::StrLen {
+            key: string_arg(&args[1])?,
+        }),
+        "incr" | "decr" => Ok(RedisCommand::IncrBy {
+            key: string_arg(&args[1])?,
+            increment: if spec.name == "incr" { 1 } else { -1 },
+        }),
+        "incrby" => Ok(RedisCommand::IncrBy {
+            key: string_arg(&args[1])?,
+            increment: int_arg(&args[2])?,
+        }),
+        "decrby" => Ok(RedisCommand::IncrBy {
+            key: string_arg(&args[1])?,
+            increment: int_arg(&args[2])?.checked_neg().ok_or(ProtocolError::DecrementOverflow)?,
+        }),
+        "incrbyfloat" => Ok(RedisCommand::IncrByFloat {
+            key: string_arg(&args[1])?,
+            increment: float_arg(&args[2])?,
+        }),
+        "set" => {
+            let mut expiration = None;
+            let mut condition = None;
+            let mut get = false;
+
+            let mut i = 3;
+            while i < args.len() {
+                match args[i].to_ascii_uppercase().as_slice() {
+                    b"NX" if condition.is_none() => condition = Some(SetCondition::NotExists),
+                    b"XX" if condition.is_none() => condition = Some(SetCondition::Exists),
+                    b"GET" => get = true,
+                    b"KEEPTTL" if expiration.is_none() => expiration = Some(SetExpiration::KeepTtl),
+                    unit @ (b"EX" | b"PX" | b"EXAT" | b"PXAT") if expiration.is_none() => {
+                        i += 1;
+                        expiration = Some(SetExpiration::Expire(expiration_arg(unit, args.get(i), "set")?));
+                    },
+                    _ => return Err(ProtocolError::Syntax),
+                }
+                i += 1;
+            }
+
+            Ok(RedisCommand::Set {
+                key: string_arg(&args[1])?,
+                value: args[2].clone(),
+                expiration,
+                condition,
+                get,
+            })
+        },
+        "setex" | "psetex" => {
+            let amount = int_arg(&args[2])?;
+            let millis = expire_millis(amount, spec.name == "setex", spec.name)?;
+
+            Ok(RedisCommand::Set {
+                key: string_arg(&args[1])?,
+                value: args[3].clone(),
+                expiration: Some(SetExpiration::Expire(Expiration::After(Duration::from_millis(millis)))),
+                condition: None,
+                get: false,
+            })
+        },
+        "setnx" => Ok(RedisCommand::SetNx {
+            key: string_arg(&args[1])?,
+            value: args[2].clone(),
+        }),
+        "setrange" => Ok(RedisCommand::SetRange {
+            key: string_arg(&args[1])?,
+            offset: usize::try_from(int_arg(&args[2])?).map_err(|_| ProtocolError::OffsetOutOfRange)?,
+            value: args[3].clone(),
+        }),
+        "expire" | "pexpire" | "expireat" | "pexpireat" => {
+            let amount = int_arg(&args[2])?;
+            let millis = if matches!(spec.name, "expire" | "expireat") {
+                amount.checked_mul(1000).ok_or(ProtocolError::InvalidExpireTime(spec.name))?
+            } else {
+                amount
+            };
+
+            // Non-positive TTLs and past timestamps delete the key right away
+            let expiration = if spec.name.ends_with("at") || millis <= 0 {
+                Expiration::AtUnixMillis(millis.max(0) as u64)
+            } else {
+                Expiration::After(Duration::from_millis(millis as u64))
+            };
+
+            let mut condition = ExpireCondition::default();
+            for option in &args[3..] {
+                match option.to_ascii_uppercase().as_slice() {
+                    b"NX" => condition.nx = true,
+                    b"XX" => condition.xx = true,
+                    b"GT" => condition.gt = true,
+                    b"LT" => condition.lt = true,
+                    _ => return Err(ProtocolError::UnsupportedOption(String::from_utf8_lossy(option).into_owned())),
+                }
+            }
+            if condition.nx && (condition.xx || condition.gt || condition.lt) {
+                return Err(ProtocolError::IncompatibleOptions("NX and XX, GT or LT"));
+            }
+            if condition.gt && condition.lt {
+                return Err(ProtocolError::IncompatibleOptions("GT and LT"));
+            }
+
+            Ok(RedisCommand::Expire {
+                key: string_arg(&args[1])?,
+                expiration,
+                condition,
+            })
+        },
+        "ttl" | "pttl" => Ok(RedisCommand::Ttl {
+            key: string_arg(&args[1])?,
+            millis: spec.name == "pttl",
+        }),
+        "expiretime" | "pexpiretime" => Ok(RedisCommand::ExpireTime {
+            key: string_arg(&args[1])?,
+            millis: spec.name == "pexpiretime",
+        }),
+        "persist" => Ok(RedisCommand::Persist {
+            key: string_arg(&args[1])?,
+        }),
+        "del" => Ok(RedisCommand::Delete {
+            keys: args[1..].iter().map(string_arg).collect::<Result<_>>()?,
+        }),
+        "hello" => {
+            let mut protover = None;
+            let mut auth = None;
+            let mut setname = None;
+
+            if args.len() > 1 {
+                protover = Some(int_arg(&args[1])?);
+            }
+
+            let mut i = 2;
+            while i < args.len() {
+                if args[i].eq_ignore_ascii_case(b"AUTH") && i + 2 < args.len() {
+                    auth = Some((string_arg(&args[i + 1])?, args[i + 2].clone()));
+                    i += 3;
+                } else if args[i].eq_ignore_ascii_case(b"SETNAME") && i + 1 < args.len() {
+                    setname = Some(string_arg(&args[i + 1])?);
+                    i += 2;
+                } else {
+                    return Err(ProtocolError::Syntax);
+                }
+            }
+
+            Ok(RedisCommand::Hello { protover, auth, setname })
+        },
+        "command" => {
+            let Some(subcommand) = args.get(1) else {
+                return Ok(RedisCommand::Command { subcommand: None });
+            };
+            let names = || args[2..].iter().map(string_arg).collect::<Result<Vec<_>>>();
+
+            let subcommand = match subcommand.to_ascii_uppercase().as_slice() {
+                b"COUNT" if args.len() == 2 => CommandSubcommand::Count,
+                b"COUNT" => return Err(ProtocolError::WrongArity("command|count")),
+                b"INFO" => CommandSubcommand::Info { names: names()? },
+                b"DOCS" => CommandSubcommand::Docs { names: names()? },
+                _ => return Err(ProtocolError::UnknownSubcommand {
+                    command: "COMMAND",
+                    subcommand: String::from_utf8_lossy(subcommand).into_owned(),
+                }),
+            };
+            Ok(RedisCommand::Command { subcommand: Some(subcommand) })
+        },
+        "lpush" | "rpush" => Ok(RedisCommand::ListPush {
+            key: string_arg(&args[1])?,
+            end: if spec.name == "lpush" { ListEnd::Left } else { ListEnd::Right },
+            values: args[2..].to_vec(),
+        }),
+        "lpop" | "rpop" => {
+            let count = match args.get(2) {
+                Some(count) => match int_arg(count)? {
+                    count if count >= 0 => Some(count as usize),
+                    _ => return Err(ProtocolError::NotPositive),
+                },
+                None => None,
+            };
+
+            Ok(RedisCommand::ListPop {
+                key: string_arg(&args[1])?,
+                end: if spec.name == "lpop" { ListEnd::Left } else { ListEnd::Right },
+                count,
+            })
+        },
+        "lrange" => Ok(RedisCommand::ListRange {
+            key: string_arg(&args[1])?,
+            start: int_arg(&args[2])?,
+            stop: int_arg(&args[3])?,
+        }),
+        "llen" => Ok(RedisCommand::ListLen {
+            key: string_arg(&args[1])?,
+        }),
+        "lindex" => Ok(RedisCommand::ListIndex {
+            key: string_arg(&args[1])?,
+            index: int_arg(&args[2])?,
+        }),
+        "lset" => Ok(RedisCommand::ListSet {
+            key: string_arg(&args[1])?,
+            index: int_arg(&args[2])?,
+            value: args[3].clone(),
+        }),
+        "lrem" => Ok(RedisCommand::ListRem {
+            key: string_arg(&args[1])?,
+            count: int_arg(&args[2])?,
+            value: args[3].clone(),
+        }),
+        "ltrim" => Ok(RedisCommand::ListTrim {
+            key: string_arg(&args[1])?,
+            start: int_arg(&args[2])?,
+            stop: int_arg(&args[3])?,
+        }),
+        "linsert" => {
+            let before = match args[2].to_ascii_uppercase().as_slice() {
+                b"BEFORE" => true,
+                b"AFTER" => false,
+                _ => return Err(ProtocolError::Syntax),
+            };
+
+            Ok(RedisCommand::ListInsert {
+                key: string_arg(&args[1])?,
+                before,
+                pivot: args[3].clone(),
+                value: args[4].clone(),
+            })
+        },
+        "lmove" => Ok(RedisCommand::ListMove {
+            source: string_arg(&args[1])?,
+            destination: string_arg(&args[2])?,
+            from: list_end_arg(&args[3])?,
+            to: list_end_arg(&args[4])?,
+        }),
+        "blpop" | "brpop" => Ok(RedisCommand::BlockingListPop {
+            keys: args[1..args.len() - 1].iter().map(string_arg).collect::<Result<_>>()?,
+            end: if spec.name == "blpop" { ListEnd::Left } else { ListEnd::Right },
+            timeout: timeout_arg(&args[args.len() - 1])?,
+        }),
+        "blmove" => Ok(RedisCommand::BlockingListMove {
+            source: string_arg(&args[1])?,
+            destination: string_arg(&args[2])?,
+            from: list_end_arg(&args[3])?,
+            to: list_end_arg(&args[4])?,
+            timeout: timeout_arg(&args[5])?,
+        }),
+        "blmpop" => {
+            let timeout = timeout_arg(&args[1])?;
+            let numkeys = match int_arg(&args[2])? {
+                numkeys if numkeys > 0 => numkeys as usize,
+                _ => return Err(ProtocolError::NotGreaterThanZero("numkeys")),
+            };
+            let Some((keys, rest)) = args[3..].split_at_checked(numkeys) else {
+                return Err(ProtocolError::Syntax);
+            };
+
+            let (end, count) = match rest {
+                [end] => (list_end_arg(end)?, 1),
+                [end, option, count] if option.eq_ignore_ascii_case(b"COUNT") => match int_arg(count)? {
+                    count if count > 0 => (list_end_arg(end)?, count as usize),
+                    _ => return Err(ProtocolError::NotGreaterThanZero("count")),
+                },
+                _ => return Err(ProtocolError::Syntax),
+            };
+
+            Ok(RedisCommand::BlockingListMPop {
+                keys: keys.iter().map(string_arg).collect::<Result<_>>()?,
+                end,
+                count,
+                timeout,
+            })
+        },
+        "hset" => {
+            if !args.len().is_multiple_of(2) {
+                return Err(ProtocolError::WrongArity(spec.name));
+            }
+
+            Ok(RedisCommand::HashSet {
+                key: string_arg(&args[1])?,
+                pairs: args[2..].chunks(2).map(|pair| (pair[0].clone(), pair[1].clone())).collect(),
+            })
+        },
+        "hget" => Ok(RedisCommand::HashGet {
+            key: string_arg(&args[1])?,
+            field: args[2].clone(),
+        }),
+        "hmget" => Ok(RedisCommand::HashMGet {
+            key: string_arg(&args[1])?,
+            fields: args[2..].to_vec(),
+        }),
+        "hdel" => Ok(RedisCommand::HashDel {
+            key: string_arg(&args[1])?,
+            fields: args[2..].to_vec(),
+        }),
+        "hgetall" => Ok(RedisCommand::HashGetAll {
+            key: string_arg(&args[1])?,
+        }),
+        "hincrby" => Ok(RedisCommand::HashIncrBy {
+            key: string_arg(&args[1])?,
+            field: args[2].clone(),
+            increment: int_arg(&args[3])?,
+        }),
+        "hincrbyfloat" => {
+            let increment = float_arg(&args[3])?;
+            if increment.is_infinite() {
+                return Err(ProtocolError::NaNOrInfinity);
+            }
+
+            Ok(RedisCommand::HashIncrByFloat {
+                key: string_arg(&args[1])?,
+                field: args[2].clone(),
+                increment,
+            })
+        },
+        "hexists" => Ok(RedisCommand::HashExists {
+            key: string_arg(&args[1])?,
+            field: args[2].clone(),
+        }),
+        "hlen" => Ok(RedisCommand::HashLen {
+            key: string_arg(&args[1])?,
+        }),
+        "hkeys" => Ok(RedisCommand::HashKeys {
+            key: string_arg(&args[1])?,
+        }),
+        "hvals" => Ok(RedisCommand::HashVals {
+            key: string_arg(&args[1])?,
+        }),
+        "hexpire" | "hpexpire" => {
+            let amount = int_arg(&args[2])?;
+            if amount < 0 {
+                return Err(ProtocolError::NegativeExpireTime);
+            }
+            let millis = if spec.name == "hexpire" {
+                amount.checked_mul(1000).ok_or(ProtocolError::InvalidExpireTime(spec.name))?
+            } else {
+                amount
+            };
+
+            // Unlike EXPIRE, only one condition may be given
+            let mut condition = ExpireCondition::default();
+            let flag = match args[3].to_ascii_uppercase().as_slice() {
+                b"NX" => Some(&mut condition.nx),
+                b"XX" => Some(&mut condition.xx),
+                b"GT" => Some(&mut condition.gt),
+                b"LT" => Some(&mut condition.lt),
+                _ => None,
+            };
+            let fields_at = match flag {
+                Some(flag) => {
+                    *flag = true;
+                    4
+                },
+                None => 3,
+            };
+
+            Ok(RedisCommand::HashExpire {
+                key: string_arg(&args[1])?,
+                expiration: if millis == 0 {
+                    Expiration::AtUnixMillis(0)
+                } else {
+                    Expiration::After(Duration::from_millis(millis as u64))
+                },
+                condition,
+                fields: fields_arg(&args[fields_at..])?,
+            })
+        },
+        "httl" => Ok(RedisCommand::HashTtl {
+            key: string_arg(&args[1])?,
+            fields: fields_arg(&args[2..])?,
+        }),
+        "hpersist" => Ok(RedisCommand::HashPersist {
+            key: string_arg(&args[1])?,
+            fields: fields_arg(&args[2..])?,
+        }),
+        "hscan" => {
+            let cursor = std::str::from_utf8(&args[2]).ok()
+                .and_then(|cursor| cursor.parse().ok())
+                .ok_or(ProtocolError::InvalidCursor)?;
+            let mut pattern = None;
+            let mut count = DEFAULT_SCAN_COUNT;
+            let mut novalues = false;
+
+            let mut i = 3;
+            while i < args.len() {
+                match args[i].to_ascii_uppercase().as_slice() {
+                    b"MATCH" if i + 1 < args.len() => {
+                        pattern = Some(args[i + 1].clone());
+                        i += 1;
+                    },
+                    b"COUNT" if i + 1 < args.len() => {
+                        count = match int_arg(&args[i + 1])? {
+                            count if count > 0 => count as usize,
+                            _ => return Err(ProtocolError::Syntax),
+                        };
+                        i += 1;
+                    },
+                    b"NOVALUES" => novalues = true,
+                    _ => return Err(ProtocolError::Syntax),
+                }
+                i += 1;
+            }
+
+            Ok(RedisCommand::HashScan {
+                key: string_arg(&args[1])?,
+                cursor,
+                pattern,
+                count,
+                novalues,
+            })
+        },
+        "sadd" => Ok(RedisCommand::SetAdd {
+            key: string_arg(&args[1])?,
+            members: args[2..].to_vec(),
+        }),
+        "srem" => Ok(RedisCommand::SetRem {
+            key: string_arg(&args[1])?,
+            members: args[2..].to_vec(),
+        }),
+        "smembers" => Ok(RedisCommand::SetMembers {
+            key: string_arg(&args[1])?,
+        }),
+        "sismember" => Ok(RedisCommand::SetIsMember {
+            key: string_arg(&args[1])?,
+            member: args[2].clone(),
+        }),
+        "smismember" => Ok(RedisCommand::SetMIsMember {
+            key: string_arg(&args[1])?,
+            members: args[2..].to_vec(),
+        }),
+        "scard" => Ok(RedisCommand::SetCard {
+            key: string_arg(&args[1])?,
+        }),
+        "spop" => {
+            let count = match args.get(2) {
+                Some(count) => match int_arg(count)? {
+                    count if count >= 0 => Some(count as usize),
+                    _ => return Err(ProtocolError::NotPositive),
+                },
+                None => None,
+            };
+            if args.len() > 3 {
+                return Err(ProtocolError::Syntax);
+            }
+
+            Ok(RedisCommand::SetPop {
+                key: string_arg(&args[1])?,
+                count,
+            })
+        },
+        "srandmember" => {
+            if args.len() > 3 {
+                return Err(ProtocolError::Syntax);
+            }
+
+            // Like Redis, bound how many repeated picks can be asked for; a
+            // positive count is at most the whole set anyway
+            let count = args.get(2).map(int_arg).transpose()?;
+            if count.is_some_and(|count| count < -(i64::MAX / 2)) {
+                return Err(ProtocolError::ValueOutOfRange);
+            }
+
+            Ok(RedisCommand::SetRandMember { key: string_arg(&args[1])?, count })
+        },
+        "sinter" | "sunion" | "sdiff" => Ok(RedisCommand::SetCombine {
+            keys: args[1..].iter().map(string_arg).collect::<Result<_>>()?,
+            op: set_op(spec.name),
+        }),
+        "sinterstore" | "sunionstore" | "sdiffstore" => Ok(RedisCommand::SetCombineStore {
+            destination: string_arg(&args[1])?,
+            keys: args[2..].iter().map(string_arg).collect::<Result<_>>()?,
+            op: set_op(spec.name),
+        }),
+        "sintercard" => {
+            let numkeys = match int_arg(&args[1])? {
+                numkeys if numkeys > 0 => numkeys as usize,
+                _ => return Err(ProtocolError::NotGreaterThanZero("numkeys")),
+            };
+            let Some((keys, rest)) = args[2..].split_at_checked(numkeys) else {
+                return Err(ProtocolError::TooManyKeys);
+            };
+
+            let limit = match rest {
+                [] => 0,
+                [option, limit] if option.eq_ignore_ascii_case(b"LIMIT") => match int_arg(limit)? {
+                    limit if limit >= 0 => limit as usize,
+                    _ => return Err(ProtocolError::NegativeLimit),
+                },
+                _ => return Err(ProtocolError::Syntax),
+            };
+
+            Ok(RedisCommand::SetInterCard {
+                keys: keys.iter().map(string_arg).collect::<Result<_>>()?,
+                limit,
+            })
+        },
+        "zadd" => {
+            let mut options = ZAddOptions::default();
+            let mut increment = false;
+            let (mut nx, mut xx, mut gt, mut lt) = (false, false, false, false);
+            let mut i = 2;
+            while let Some(arg) = args.get(i) {
+                match arg.to_ascii_uppercase().as_slice() {
+                    b"NX" => nx = true,
+                    b"XX" => xx = true,
+                    b"GT" => gt = true,
+                    b"LT" => lt = true,
+                    b"CH" => options.changed = true,
+                    b"INCR" => increment = true,
+                    _ => break,
+                }
+                i += 1;
+            }
+
+            let pairs = &args[i..];
+            if pairs.is_empty() || !pairs.len().is_multiple_of(2) {
+                return Err(ProtocolError::Syntax);
+            }
+            if nx && xx {
+                return Err(ProtocolError::IncompatibleOptions("XX and NX"));
+            }
+            if [nx, gt, lt].into_iter().filter(|&flag| flag).count() > 1 {
+                return Err(ProtocolError::IncompatibleOptions("GT, LT, and/or NX"));
+            }
+            if increment && pairs.len() > 2 {
+                return Err(ProtocolError::IncrSinglePair);
+            }
+            options.condition = match (nx, xx) {
+                (true, _) => Some(SetCondition::NotExists),
+                (_, true) => Some(SetCondition::Exists),
+                _ => None,
+            };
+            options.only_if = match (gt, lt) {
+                (true, _) => Some(std::cmp::Ordering::Greater),
+                (_, true) => Some(std::cmp::Ordering::Less),
+                _ => None,
+            };
+
+            let members = pairs.chunks(2)
+                .map(|pair| Ok((float_arg(&pair[0])?, pair[1].clone())))
+                .collect::<Result<_>>()?;
+
+            Ok(RedisCommand::ZAdd {
+                key: string_arg(&args[1])?,
+                options,
+                increment,
+                members,
+            })
+        },
+        "zcard" => Ok(RedisCommand::ZCard {
+            key: string_arg(&args[1])?,
+        }),
+        "zcount" => Ok(RedisCommand::ZCount {
+            key: string_arg(&args[1])?,
+            by: RangeBy::Score(score_bound_arg(&args[2])?, score_bound_arg(&args[3])?),
+        }),
+        "zincrby" => Ok(RedisCommand::ZIncrBy {
+            key: string_arg(&args[1])?,
+            increment: float_arg(&args[2])?,
+            member: args[3].clone(),
+        }),
+        "zrange" => {
+            let (mut by_score, mut by_lex, mut rev, mut with_scores) = (false, false, false, false);
+            let mut limit = None;
+            let mut i = 4;
+            while i < args.len() {
+                match args[i].to_ascii_uppercase().as_slice() {
+                    b"BYSCORE" => by_score = true,
+                    b"BYLEX" => by_lex = true,
+                    b"REV" => rev = true,
+                    b"WITHSCORES" => with_scores = true,
+                    b"LIMIT" if i + 2 < args.len() => {
+                        let (offset, count) = (int_arg(&args[i + 1])?, int_arg(&args[i + 2])?);
+                        // A negative offset selects nothing, a negative count everything
+                        limit = Some(match usize::try_from(offset) {
+                            Ok(offset) => Limit { offset, count: usize::try_from(count).ok() },
+                            Err(_) => Limit { offset: 0, count: Some(0) },
+                        });
+                        i += 2;
+                    },
+                    _ => return Err(ProtocolError::Syntax),
+                }
+                i += 1;
+            }
+
+            // With REV the bounds of a score or lex range come highest first
+            let (min, max) = if rev { (&args[3], &args[2]) } else { (&args[2], &args[3]) };
+            let by = match (by_score, by_lex) {
+                (true, true) => return Err(ProtocolError::Syntax),
+                (true, false) => RangeBy::Score(score_bound_arg(min)?, score_bound_arg(max)?),
+                (false, true) if with_scores => return Err(ProtocolError::WithScoresByLex),
+                (false, true) => RangeBy::Lex(lex_bound_arg(min)?, lex_bound_arg(max)?),
+                (false, false) if limit.is_some() => return Err(ProtocolError::LimitWithoutBy),
+                (false, false) => RangeBy::Rank(int_arg(&args[2])?, int_arg(&args[3])?),
+            };
+
+            Ok(RedisCommand::ZRange {
+                key: string_arg(&args[1])?,
+                by,
+                rev,
+                limit: limit.unwrap_or_default(),
+                with_scores,
+            })
+        },
+        "zrank" | "zrevrank" => Ok(RedisCommand::ZRank {
+            key: string_arg(&args[1])?,
+            member: args[2].clone(),
+            rev: spec.name == "zrevrank",
+        }),
+        "zscore" => Ok(RedisCommand::ZScore {
+            key: string_arg(&args[1])?,
+            member: args[2].clone(),
+        }),
+        "zmscore" => Ok(RedisCommand::ZMScore {
+            key: string_arg(&args[1])?,
+            members: args[2..].to_vec(),
+        }),
+        "zrem" => Ok(RedisCommand::ZRem {
+            key: string_arg(&args[1])?,
+            members: args[2..].to_vec(),
+        }),
+        "zremrangebyrank" => Ok(RedisCommand::ZRemRange {
+            key: string_arg(&args[1])?,
+            by: RangeBy::Rank(int_arg(&args[2])?, int_arg(&args[3])?),
+        }),
+        "zremrangebyscore" => Ok(RedisCommand::ZRemRange {
+            key: string_arg(&args[1])?,
+            by: RangeBy::Score(score_bound_arg(&args[2])?, score_bound_arg(&args[3])?),
+        }),
+        "zremrangebylex" => Ok(RedisCommand::ZRemRange {
+            key: string_arg(&args[1])?,
+            by: RangeBy::Lex(lex_bound_arg(&args[2])?, lex_bound_arg(&args[3])?),
+        }),
+        "zpopmin" | "zpopmax" => {
+            let count = match args.get(2) {
+                Some(count) => match int_arg(count)? {
+                    count if count >= 0 => Some(count as usize),
+                    _ => return Err(ProtocolError::NotPositive),
+                },
+                None => None,
+            };
+            if args.len() > 3 {
+                return Err(ProtocolError::Syntax);
+            }
+
+            Ok(RedisCommand::ZPop {
+                key: string_arg(&args[1])?,
+                max: spec.name == "zpopmax",
+                count,
+            })
+        },
+        "zunionstore" | "zinterstore" => {
+            let numkeys = match int_arg(&args[2])? {
+                numkeys if numkeys > 0 => numkeys as usize,
+                _ => return Err(ProtocolError::NoInputKeys(spec.name)),
+            };
+            let Some((keys, mut rest)) = args[3..].split_at_checked(numkeys) else {
+                return Err(ProtocolError::Syntax);
+            };
+
+            let mut weights = Vec::new();
+            let mut aggregate = Aggregate::default();
+            while let [option, tail @ ..] = rest {
+                match option.to_ascii_uppercase().as_slice() {
+                    b"WEIGHTS" if tail.len() >= numkeys => {
+                        weights = tail[..numkeys].iter()
+                            .map(|weight| float_arg(weight).map_err(|_| ProtocolError::InvalidWeight))
+                            .collect::<Result<_>>()?;
+                        rest = &tail[numkeys..];
+                    },
+                    b"AGGREGATE" if !tail.is_empty() => {
+                        aggregate = match tail[0].to_ascii_uppercase().as_slice() {
+                            b"SUM" => Aggregate::Sum,
+                            b"MIN" => Aggregate::Min,
+                            b"MAX" => Aggregate::Max,
+                            _ => return Err(ProtocolError::Syntax),
+                        };
+                        rest = &tail[1..];
+                    },
+                    _ => return Err(ProtocolError::Syntax),
+                }
+            }
+
+            Ok(RedisCommand::ZCombineStore {
+                destination: string_arg(&args[1])?,
+                keys: keys.iter().map(string_arg).collect::<Result<_>>()?,
+                weights,
+                aggregate,
+                op: if spec.name == "zunionstore" { SetOp::Union } else { SetOp::Inter },
+            })
+        },
+        "xadd" => {
+            let mut options = StreamAddOptions::default();
+            let mut i = 2;
+            while let Some(option) = args.get(i) {
+                let option = option.to_ascii_uppercase();
+                match option.as_slice() {
+                    b"NOMKSTREAM" => {
+                        options.no_create = true;
+                        i += 1;
+                        continue;
+                    },
+                    b"MAXLEN" | b"MINID" => i += 1,
+                    _ => break,
+                }
+
+                let approximate = match args.get(i).map(|arg| &arg[..]) {
+                    Some(b"~") => {
+                        i += 1;
+                        true
+                    },
+                    Some(b"=") => {
+                        i += 1;
+                        false
+                    },
+                    _ => false,
+                };
+                let Some(threshold) = args.get(i) else {
+                    return Err(ProtocolError::Syntax);
+                };
+                options.trim = Some(if option == b"MAXLEN" {
+                    match int_arg(threshold)? {
+                        max if max >= 0 => Trim::MaxLen(max as usize),
+                        _ => return Err(ProtocolError::NegativeMaxLen),
+                    }
+                } else {
+                    Trim::MinId(stream_id_arg(threshold, 0)?)
+                });
+                i += 1;
+
+                if let Some(option) = args.get(i) && option.eq_ignore_ascii_case(b"LIMIT") {
+                    let Some(limit) = args.get(i + 1) else {
+                        return Err(ProtocolError::Syntax);
+                    };
+                    if !approximate {
+                        return Err(ProtocolError::LimitWithoutApprox);
+                    }
+                    options.trim_limit = match int_arg(limit)? {
+                        0 => None,
+                        limit if limit > 0 => Some(limit as usize),
+                        _ => return Err(ProtocolError::NegativeLimit),
+                    };
+                    i += 2;
+                }
+            }
+
+            let Some((id, fields)) = args.get(i..).and_then(|rest| rest.split_first()) else {
+                return Err(ProtocolError::WrongArity(spec.name));
+            };
+            if fields.is_empty() || !fields.len().is_multiple_of(2) {
+                return Err(ProtocolError::WrongArity(spec.name));
+            }
+            let id = match &id[..] {
+                b"*" => NewId::Auto,
+                [ms @ .., b'-', b'*'] => match std::str::from_utf8(ms).ok().and_then(|ms| ms.parse().ok()) {
+                    Some(ms) => NewId::AutoSeq(ms),
+                    None => return Err(ProtocolError::InvalidStreamId),
+                },
+                id => match stream_id_arg(id, 0)? {
+                    StreamId::MIN => return Err(ProtocolError::StreamIdZero),
+                    id => NewId::Explicit(id),
+                },
+            };
+
+            Ok(RedisCommand::XAdd {
+                key: string_arg(&args[1])?,
+                id,
+                fields: fields.chunks(2).map(|pair| (pair[0].clone(), pair[1].clone())).collect(),
+                options,
+            })
+        },
+        "xlen" => Ok(RedisCommand::XLen {
+            key: string_arg(&args[1])?,
+        }),
+        "xrange" | "xrevrange" => {
+            let rev = spec.name == "xrevrange";
+            let (start, end) = if rev { (&args[3], &args[2]) } else { (&args[2], &args[3]) };
+            let count = match &args[4..] {
+                [] => None,
+                [option, count] if option.eq_ignore_ascii_case(b"COUNT") => Some(int_arg(count)?.max(0) as usize),
+                _ => return Err(ProtocolError::Syntax),
+            };
+
+            Ok(RedisCommand::XRange {
+                key: string_arg(&args[1])?,
+                start: stream_bound_arg(start, true)?,
+                end: stream_bound_arg(end, false)?,
+                count,
+                rev,
+            })
+        },
+        "xdel" => Ok(RedisCommand::XDel {
+            key: string_arg(&args[1])?,
+            ids: args[2..].iter().map(|id| stream_id_arg(id, 0)).collect::<Result<_>>()?,
+        }),
+        "xread" | "xreadgroup" => {
+            let reads_group = spec.name == "xreadgroup";
+            let (mut count, mut block, mut group, mut noack) = (None, None, None, false);
+            let mut i = 1;
+            let streams = loop {
+                let Some(option) = args.get(i) else {
+                    return Err(ProtocolError::Syntax);
+                };
+                match (option.to_ascii_uppercase().as_slice(), args.get(i + 1)) {
+                    (b"STREAMS", _) => break &args[i + 1..],
+                    (b"COUNT", Some(value)) => {
+                        // Like Redis, a count that is not positive means no limit
+                        count = Some(int_arg(value)?).filter(|&count| count > 0).map(|count| count as usize);
+                        i += 2;
+                    },
+                    (b"BLOCK", Some(value)) => {
+                        block = Some(block_arg(value)?);
+                        i += 2;
+                    },
+                    (b"GROUP", Some(name)) if reads_group && i + 2 < args.len() => {
+                        group = Some((name.clone(), args[i + 2].clone()));
+                        i += 3;
+                    },
+                    (b"NOACK", _) if reads_group => {
+                        noack = true;
+                        i += 1;
+                    },
+                    _ => return Err(ProtocolError::Syntax),
+                }
+            };
+
+            if streams.is_empty() || !streams.len().is_multiple_of(2) {
+                return Err(ProtocolError::UnbalancedStreams {
+                    command: spec.name,
+                    id: if reads_group { ">" } else { "$" },
+                });
+            }
+            let (keys, ids) = streams.split_at(streams.len() / 2);
+            let keys = keys.iter().map(string_arg).collect::<Result<Vec<_>>>()?;
+
+            if !reads_group {
+                let ids = ids.iter()
+                    .map(|id| match &id[..] {
+                        b"$" => Ok(None),
+                        id => stream_id_arg(id, 0).map(Some),
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                return Ok(RedisCommand::XRead { streams: keys.into_iter().zip(ids).collect(), count, block });
+            }
+
+            let Some((group, consumer)) = group else {
+                return Err(ProtocolError::Syntax);
+            };
+            let ids = ids.iter()
+                .map(|id| match &id[..] {
+                    b">" => Ok(GroupRead::New),
+                    id => stream_id_arg(id, 0).map(GroupRead::Pending),
+                })
+                .collect::<Result<Vec<_>>>()?;
+            Ok(RedisCommand::XReadGroup {
+                group,
+                consumer,
+                streams: keys.into_iter().zip(ids).collect(),
+                count,
+                block,
+                noack,
+            })
+        },
+        "xgroup" => {
+            let subcommand = match (args[1].to_ascii_uppercase().as_slice(), args.len()) {
+                (b"CREATE", 5..) => XGroupSubcommand::Create {
+                    id: group_id_arg(&args[4])?,
+                    mkstream: match &args[5..] {
+                        [] => false,
+                        [option] if option.eq_ignore_ascii_case(b"MKSTREAM") => true,
+                        _ => return Err(ProtocolError::Syntax),
+                    },
+                },
+                (b"CREATE", _) => return Err(ProtocolError::WrongArity("xgroup|create")),
+                (b"SETID", 5) => XGroupSubcommand::SetId { id: group_id_arg(&args[4])? },
+                (b"SETID", _) => return Err(ProtocolError::WrongArity("xgroup|setid")),
+                (b"DESTROY", 4) => XGroupSubcommand::Destroy,
+                (b"DESTROY", _) => return Err(ProtocolError::WrongArity("xgroup|destroy")),
+                (b"CREATECONSUMER", 5) => XGroupSubcommand::CreateConsumer { consumer: args[4].clone() },
+                (b"CREATECONSUMER", _) => return Err(ProtocolError::WrongArity("xgroup|createconsumer")),
+                (b"DELCONSUMER", 5) => XGroupSubcommand::DelConsumer { consumer: args[4].clone() },
+                (b"DELCONSUMER", _) => return Err(ProtocolError::WrongArity("xgroup|delconsumer")),
+                _ => return Err(ProtocolError::UnknownSubcommand {
+                    command: "XGROUP",
+                    subcommand: String::from_utf8_lossy(&args[1]).into_owned(),
+                }),
+            };
+
+            Ok(RedisCommand::XGroup {
+                key: string_arg(&args[2])?,
+                group: args[3].clone(),
+                subcommand,
+            })
+        },
+        "xack" => Ok(RedisCommand::XAck {
+            key: string_arg(&args[1])?,
+            group: args[2].clone(),
+            ids: args[3..].iter().map(|id| stream_id_arg(id, 0)).collect::<Result<_>>()?,
+        }),
+        "xpending" => {
+            let filter = match &args[3..] {
+                [] => None,
+                rest => {
+                    let (min_idle, rest) = match rest {
+                        [option, idle, rest @ ..] if option.eq_ignore_ascii_case(b"IDLE") => (millis_arg(idle)?, rest),
+                        rest => (0, rest),
+                    };
+                    let [start, end, count, consumer @ ..] = rest else {
+                        return Err(ProtocolError::Syntax);
+                    };
+                    if consumer.len() > 1 {
+                        return Err(ProtocolError::Syntax);
+                    }
+                    Some(PendingFilter {
+                        start: stream_bound_arg(start, true)?,
+                        end: stream_bound_arg(end, false)?,
+                        count: int_arg(count)?.max(0) as usize,
+                        min_idle,
+                        consumer: consumer.first().cloned(),
+                    })
+                },
+            };
+
+            Ok(RedisCommand::XPending {
+                key: string_arg(&args[1])?,
+                group: args[2].clone(),
+                filter,
+            })
+        },
+        "xclaim" => {
+            let mut options = ClaimOptions { min_idle: millis_arg(&args[4])?, ..ClaimOptions::default() };
+
+            // IDs run until the first argument that is not one; options follow
+            let mut ids = Vec::new();
+            let mut rest = &args[5..];
+            while let [id, tail @ ..] = rest && let Ok(id) = stream_id_arg(id, 0) {
+                ids.push(id);
+                rest = tail;
+            }
+            while let [option, tail @ ..] = rest {
+                rest = match (option.to_ascii_uppercase().as_slice(), tail) {
+                    (b"FORCE", tail) => {
+                        options.force = true;
+                        tail
+                    },
+                    (b"JUSTID", tail) => {
+                        options.just_id = true;
+                        tail
+                    },
+                    (b"IDLE", [idle, tail @ ..]) => {
+                        options.idle = Some(millis_arg(idle)?);
+                        tail
+                    },
+                    (b"TIME", [time, tail @ ..]) => {
+                        options.time = Some(millis_arg(time)?);
+                        tail
+                    },
+                    (b"RETRYCOUNT", [count, tail @ ..]) => {
+                        options.retry_count = Some(millis_arg(count)?);
+                        tail
+                    },
+                    (b"LASTID", [id, tail @ ..]) => {
+                        options.last_id = Some(stream_id_arg(id, 0)?);
+                        tail
+                    },
+                    _ => return Err(ProtocolError::Syntax),
+                };
+            }
+
+            Ok(RedisCommand::XClaim {
+                key: string_arg(&args[1])?,
+                group: args[2].clone(),
+                consumer: args[3].clone(),
+                ids,
+                options,
+            })
+        },
+        "xautoclaim" => {
+            let mut options = ClaimOptions { min_idle: millis_arg(&args[4])?, ..ClaimOptions::default() };
+            let mut count = 100;
+            let mut rest = &args[6..];
+            while let [option, tail @ ..] = rest {
+                rest = match (option.to_ascii_uppercase().as_slice(), tail) {
+                    (b"COUNT", [value, tail @ ..]) => {
+                        // Like Redis, refuse counts whose ten-fold scan
+                        // budget would not fit
+                        count = match int_arg(value)? {
+                            count if count > 0 && count <= i64::MAX / 10 => count as usize,
+                            _ => return Err(ProtocolError::NotGreaterThanZero("COUNT")),
+                        };
+                        tail
+                    },
+                    (b"JUSTID", tail) => {
+                        options.just_id = true;
+                        tail
+                    },
+                    _ => return Err(ProtocolError::Syntax),
+                };
+            }
+
+            Ok(RedisCommand::XAutoClaim {
+                key: string_arg(&args[1])?,
+                group: args[2].clone(),
+                consumer: args[3].clone(),
+                start: stream_id_arg(&args[5], 0)?,
+                count,
+                options,
+            })
+        },
+        "xinfo" => {
+            let subcommand = match (args[1].to_ascii_uppercase().as_slice(), args.len()) {
+                (b"STREAM", 3) => XInfoSubcommand::Stream,
+                (b"STREAM", 4..) => return Err(ProtocolError::Syntax),
+                (b"STREAM", _) => return Err(ProtocolError::WrongArity("xinfo|stream")),
+                (b"GROUPS", 3) => XInfoSubcommand::Groups,
+                (b"GROUPS", _) => return Err(ProtocolError::WrongArity("xinfo|groups")),
+                (b"CONSUMERS", 4) => XInfoSubcommand::Consumers { group: args[3].clone() },
+                (b"CONSUMERS", _) => return Err(ProtocolError::WrongArity("xinfo|consumers")),
+                _ => return Err(ProtocolError::UnknownSubcommand {
+                    command: "XINFO",
+                    subcommand: String::from_utf8_lossy(&args[1]).into_owned(),
+                }),
+            };
+
+            Ok(RedisCommand::XInfo {
+                key: string_arg(&args[2])?,
+                subcommand,
+            })
+        },
+        "pop" => Ok(RedisCommand::Pop),
+        "bpop" => Ok(RedisCommand::BPop {
+            timeout: timeout_arg(&args[1])?,
+        }),
+        "qpush" => {
+            let mut expiration = None;
+            let mut delay = None;
+            let mut priority = None;
+
+            for option in args[3..].chunks(2) {
+                let [name, amount] = option else {
+                    return Err(ProtocolError::Syntax);
+                };
+                let amount = int_arg(amount)?;
+
+                match name.to_ascii_uppercase().as_slice() {
+                    unit @ (b"EX" | b"PX") if expiration.is_none() => {
+                        let millis = expire_millis(amount, unit == b"EX", "qpush")?;
+                        expiration = Some(Expiration::After(Duration::from_millis(millis)));
+                    },
+                    unit @ (b"DELAY" | b"DELAYAT") if delay.is_none() => {
+                        let millis = u64::try_from(amount).map_err(|_| ProtocolError::InvalidExpireTime("qpush"))?;
+                        delay = Some(if unit == b"DELAYAT" {
+                            Expiration::AtUnixMillis(millis)
+                        } else {
+                            Expiration::After(Duration::from_millis(millis))
+                        });
+                    },
+                    b"PRIORITY" if priority.is_none() => priority = Some(amount),
+                    _ => return Err(ProtocolError::Syntax),
+                }
+            }
+
+            Ok(RedisCommand::QPush {
+                queue: string_arg(&args[1])?,
+                value: args[2].clone(),
+                expiration,
+                delay,
+                priority: priority.unwrap_or(0),
+            })
+        },
+        "qpop" => Ok(RedisCommand::QPop {
+            queue: string_arg(&args[1])?,
+            count: count_option(&args[2..])?,
+        }),
+        "qpeek" => Ok(RedisCommand::QPeek {
+            queue: string_arg(&args[1])?,
+            count: count_option(&args[2..])?,
+        }),
+        "qlen" => Ok(RedisCommand::QLen {
+            queue: string_arg(&args[1])?,
+        }),
+        "qdepth" => Ok(RedisCommand::QDepth {
+            queue: string_arg(&args[1])?,
+        }),
+        "qreserve" => {
+            let visibility = match &args[2..] {
+                [] => DEFAULT_VISIBILITY,
+                [option, millis] if option.eq_ignore_ascii_case(b"VISIBILITY") => match int_arg(millis)? {
+                    millis if millis > 0 => Duration::from_millis(millis as u64),
+                    _ => return Err(ProtocolError::NotPositive),
+                },
+                _ => return Err(ProtocolError::Syntax),
+            };
+
+            Ok(RedisCommand::QReserve {
+                queue: string_arg(&args[1])?,
+                visibility,
+            })
+        },
+        "qack" => Ok(RedisCommand::QAck {
+            queue: string_arg(&args[1])?,
+            deliveries: delivery_args(&args[2..])?,
+        }),
+        "qnack" => Ok(RedisCommand::QNack {
+            queue: string_arg(&args[1])?,
+            deliveries: delivery_args(&args[2..])?,
+        }),
+        "qscheduled" => Ok(RedisCommand::QScheduled {
+            queue: string_arg(&args[1])?,
+            count: count_option(&args[2..])?,
+        }),
+        "qcancel" => Ok(RedisCommand::QCancel {
+            queue: string_arg(&args[1])?,
+            ids: delivery_args(&args[2..])?,
+        }),
+        "qreject" => Ok(RedisCommand::QReject {
+            queue: string_arg(&args[1])?,
+            deliveries: delivery_args(&args[2..])?,
+        }),
+        "qdlq" => {
+            let subcommand = match args[1].to_ascii_uppercase().as_slice() {
+                b"LIST" => DlqSubcommand::List { count: count_option(&args[3..])? },
+                b"REPLAY" => DlqSubcommand::Replay { count: count_option(&args[3..])? },
+                b"LEN" if args.len() == 3 => DlqSubcommand::Len,
+                b"PURGE" if args.len() == 3 => DlqSubcommand::Purge,
+                b"LEN" | b"PURGE" => return Err(ProtocolError::Syntax),
+                _ => return Err(ProtocolError::UnknownSubcommand {
+                    command: "QDLQ",
+                    subcommand: String::from_utf8_lossy(&args[1]).into_owned(),
+                }),
+            };
+
+            Ok(RedisCommand::QDlq {
+                queue: string_arg(&args[2])?,
+                subcommand,
+            })
+        },
+        "ping" => match args.len() {
+            1 => Ok(RedisCommand::Ping { message: None }),
+            2 => Ok(RedisCommand::Ping { message: Some(args[1].clone()) }),
+            _ => Err(ProtocolError::WrongArity(spec.name)),
+        },
+        "info" => Ok(RedisCommand::Info),
+        _ => Err(unknown_command(&args)),
+    }
+}
+
+/// Encodes `value` for a connection speaking `version`. RESP3-only types are
+/// downgraded to their closest RESP2 shape for RESP2 connections.
+pub fn serialize_response(value: RedisValue, version: ProtocolVersion) -> Bytes {
     let mut buf = BytesMut::new();
-    
+    write_value(&mut buf, value, version);
+    buf.freeze()
+}
+
+/// Formats a double the way Redis replies with it, e.g. `1.5`, `3`, `inf`:
+/// the shortest form that reads back as the same double.
+pub fn format_double(value: f64) -> String {
+    if value.is_nan() {
+        "nan".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+fn write_value(buf: &mut BytesMut, value: RedisValue, version: ProtocolVersion) {
+    let resp3 = version == ProtocolVersion::Resp3;
+
     match value {
         RedisValue::String(s) => {
             buf.put_u8(b'+');
@@ -209,31 +2110,73 @@ pub fn serialize_response(value: RedisValue) -> Bytes {
             buf.put_slice(i.to_string().as_bytes());
             buf.put_slice(b"\r\n");
         },
-        RedisValue::Bytes(b) => {
-            buf.put_u8(b'$');
-            buf.put_slice(b.len().to_string().as_bytes());
-            buf.put_slice(b"\r\n");
-            buf.put_slice(&b);
-            buf.put_slice(b"\r\n");
+        RedisValue::Bytes(b) => write_blob(buf, b'$', &b),
+        RedisValue::Nil | RedisValue::Null if resp3 => {
+            buf.put_slice(b"_\r\n");
         },
         RedisValue::Nil => {
             buf.put_slice(b"$-1\r\n");
         },
+        RedisValue::Null => {
+            buf.put_slice(b"*-1\r\n");
+        },
         RedisValue::Error(e) => {
             buf.put_u8(b'-');
             buf.put_slice(e.as_bytes());
             buf.put_slice(b"\r\n");
         },
-        RedisValue::Array(arr) => {
-            buf.put_u8(b'*');
-            buf.put_slice(arr.len().to_string().as_bytes());
+        RedisValue::Array(arr) => write_aggregate(buf, b'*', arr, version),
+        RedisValue::Set(arr) => write_aggregate(buf, if resp3 { b'~' } else { b'*' }, arr, version),
+        RedisValue::Push(arr) => write_aggregate(buf, if resp3 { b'>' } else { b'*' }, arr, version),
+        RedisValue::Map(pairs) => {
+            // RESP2 has no map type, so pairs are flattened into one array
+            let (marker, len) = if resp3 { (b'%', pairs.len()) } else { (b'*', pairs.len() * 2) };
+            buf.put_u8(marker);
+            buf.put_slice(len.to_string().as_bytes());
             buf.put_slice(b"\r\n");
-            for item in arr {
-                let serialized = serialize_response(item);
-                buf.put_slice(&serialized);
+            for (key, value) in pairs {
+                write_value(buf, key, version);
+                write_value(buf, value, version);
             }
-        }
+        },
+        RedisValue::Double(d) if resp3 => {
+            buf.put_u8(b',');
+            buf.put_slice(format_double(d).as_bytes());
+            buf.put_slice(b"\r\n");
+        },
+        RedisValue::Double(d) => write_blob(buf, b'$', format_double(d).as_bytes()),
+        RedisValue::Boolean(b) if resp3 => {
+            buf.put_slice(if b { b"#t\r\n" } else { b"#f\r\n" });
+        },
+        RedisValue::Boolean(b) => {
+            buf.put_slice(if b { b":1\r\n" } else { b":0\r\n" });
+        },
+        RedisValue::BigNumber(n) if resp3 => {
+            buf.put_u8(b'(');
+            buf.put_slice(n.as_bytes());
+            buf.put_slice(b"\r\n");
+        },
+        RedisValue::BigNumber(n) => write_blob(buf, b'$', n.as_bytes()),
+        RedisValue::Verbatim { format, text } if resp3 => {
+            write_blob(buf, b'=', format!("{}:{}", format, text).as_bytes());
+        },
+        RedisValue::Verbatim { text, .. } => write_blob(buf, b'$', text.as_bytes()),
     }
-    
-    buf.freeze()
-}
\ No newline at end of file
+}
+
+fn write_blob(buf: &mut BytesMut, marker: u8, data: &[u8]) {
+    buf.put_u8(marker);
+    buf.put_slice(data.len().to_string().as_bytes());
+    buf.put_slice(b"\r\n");
+    buf.put_slice(data);
+    buf.put_slice(b"\r\n");
+}
+
+fn write_aggregate(buf: &mut BytesMut, marker: u8, items: Vec<RedisValue>, version: ProtocolVersion) {
+    buf.put_u8(marker);
+    buf.put_slice(items.len().to_string().as_bytes());
+    buf.put_slice(b"\r\n");
+    for item in items {
+        write_value(buf, item, version);
+    }
+}
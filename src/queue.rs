@@ -0,0 +1,345 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::Instant;
+use bytes::Bytes;
+
+use crate::storage::Expiry;
+
+/// A value waiting in a named queue.
+#[derive(Debug)]
+pub struct QueueItem {
+    pub data: Bytes,
+    pub expiry: Option<Expiry>,
+    /// How many times the item has been handed out by QRESERVE.
+    pub deliveries: u64,
+    /// Higher priorities are served first.
+    pub priority: i64,
+    /// How many times a consumer has rejected the item.
+    pub failures: u64,
+    /// When a scheduled item becomes visible; `None` once it is.
+    pub visible_at: Option<Expiry>,
+    /// The delivery currently holding the item, if it is reserved.
+    delivery: Option<u64>,
+    /// Position in the ready order, assigned when the item becomes visible.
+    order: u64,
+}
+
+impl QueueItem {
+    pub fn new(data: Bytes, expiry: Option<Expiry>) -> Self {
+        Self { data, expiry, priority: 0, deliveries: 0, failures: 0, visible_at: None, delivery: None, order: 0 }
+    }
+
+    pub fn with_priority(mut self, priority: i64) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    pub fn is_expired(&self, now: Instant) -> bool {
+        self.expiry.is_some_and(|expiry| now >= expiry.at)
+    }
+
+    fn ready_key(&self, seq: u64) -> ReadyKey {
+        (Reverse(self.priority), self.order, seq)
+    }
+}
+
+/// Orders ready items by descending priority, then by position.
+type ReadyKey = (Reverse<i64>, u64, u64);
+
+/// What QRESERVE hands to a consumer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Delivery {
+    /// Identifies this delivery to ACK and NACK; a redelivery gets a new id.
+    pub id: u64,
+    pub data: Bytes,
+    /// How many times the item has been delivered, this delivery included.
+    pub deliveries: u64,
+}
+
+/// An item handed out by QRESERVE and hidden until `deadline`.
+#[derive(Debug)]
+struct Reservation {
+    seq: u64,
+    deadline: Instant,
+}
+
+/// A named FIFO queue, independent from the key/value namespace.
+///
+/// Each item is identified by a per-queue sequence number, which also names it
+/// in the expiry index while it has a TTL. An item is either scheduled to
+/// become visible later, ready and visible to QPOP, QPEEK and QLEN, reserved
+/// by a delivery, or dead-lettered after being rejected too many times.
+///
+/// Ready items are served highest priority first and, within a priority, by
+/// the position they were given on becoming visible, so a scheduled item joins
+/// the back of the queue when it is due while a requeued or replayed item goes
+/// back to its original place.
+#[derive(Debug, Default)]
+pub struct Queue {
+    items: BTreeMap<u64, QueueItem>,
+    ready: BTreeSet<ReadyKey>,
+    /// Number of ready items at each priority.
+    depths: BTreeMap<i64, usize>,
+    reserved: BTreeMap<u64, Reservation>,
+    /// Reservations ordered by visibility deadline, as `(deadline, delivery)`.
+    deadlines: BTreeSet<(Instant, u64)>,
+    /// The dead-letter queue.
+    dead: BTreeSet<u64>,
+    /// Scheduled items as `(visible at, seq)`.
+    scheduled: BTreeSet<(Instant, u64)>,
+    next_seq: u64,
+    next_order: u64,
+    next_delivery: u64,
+    /// Items carrying a TTL as `(deadline, seq)`, so expired ones are found
+    /// without scanning the queue.
+    expiring: BTreeSet<(Instant, u64)>,
+}
+
+impl Queue {
+    /// Appends an item to the back of the queue, returning its sequence number.
+    pub fn push(&mut self, item: QueueItem) -> u64 {
+        let seq = self.insert(item);
+        self.make_ready(seq);
+        seq
+    }
+
+    /// Adds an item that stays hidden until `at`, then joins the back of the
+    /// queue. Returns its sequence number.
+    pub fn schedule(&mut self, mut item: QueueItem, at: Expiry) -> u64 {
+        item.visible_at = Some(at);
+        let seq = self.insert(item);
+        self.scheduled.insert((at.at, seq));
+        seq
+    }
+
+    fn insert(&mut self, item: QueueItem) -> u64 {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+
+        if let Some(expiry) = item.expiry {
+            self.expiring.insert((expiry.at, seq));
+        }
+        self.items.insert(seq, item);
+        seq
+    }
+
+    /// Puts `seq` at the back of the ready order for its priority.
+    fn make_ready(&mut self, seq: u64) {
+        let order = self.next_order;
+        self.next_order += 1;
+        self.items.get_mut(&seq).expect("item exists").order = order;
+        self.restore_ready(seq);
+    }
+
+    /// Puts `seq` back at the place in the ready order it had before.
+    fn restore_ready(&mut self, seq: u64) {
+        let item = &self.items[&seq];
+        self.ready.insert(item.ready_key(seq));
+        *self.depths.entry(item.priority).or_default() += 1;
+    }
+
+    /// Takes an item out of the ready order, returning whether it was ready.
+    fn remove_ready(&mut self, key: ReadyKey) -> bool {
+        if !self.ready.remove(&key) {
+            return false;
+        }
+        let (Reverse(priority), _, _) = key;
+        if let Some(depth) = self.depths.get_mut(&priority) {
+            *depth -= 1;
+            if *depth == 0 {
+                self.depths.remove(&priority);
+            }
+        }
+        true
+    }
+
+    /// Sequence number of the first ready item, expired or not.
+    pub fn front(&self) -> Option<u64> {
+        self.ready.first().map(|&(_, _, seq)| seq)
+    }
+
+    /// Removes the first ready item, expired or not.
+    pub fn pop_front(&mut self) -> Option<(u64, QueueItem)> {
+        let seq = self.front()?;
+        self.remove(seq).map(|item| (seq, item))
+    }
+
+    /// Removes an item whatever its state.
+    pub fn remove(&mut self, seq: u64) -> Option<QueueItem> {
+        let item = self.items.remove(&seq)?;
+        if let Some(delivery) = item.delivery {
+            let reservation = self.reserved.remove(&delivery).expect("reserved item has a reservation");
+            self.deadlines.remove(&(reservation.deadline, delivery));
+        } else if let Some(at) = item.visible_at {
+            self.scheduled.remove(&(at.at, seq));
+        } else if !self.remove_ready(item.ready_key(seq)) {
+            self.dead.remove(&seq);
+        }
+
+        if let Some(expiry) = item.expiry {
+            self.expiring.remove(&(expiry.at, seq));
+        }
+        Some(item)
+    }
+
+    pub fn get(&self, seq: u64) -> Option<&QueueItem> {
+        self.items.get(&seq)
+    }
+
+    /// Hides the ready item `seq` until `deadline` and returns the id of the
+    /// new delivery, which ACK and NACK refer to.
+    pub fn reserve(&mut self, seq: u64, deadline: Instant) -> Option<(u64, &QueueItem)> {
+        let key = self.items.get(&seq)?.ready_key(seq);
+        if !self.remove_ready(key) {
+            return None;
+        }
+
+        let delivery = self.next_delivery;
+        self.next_delivery += 1;
+        self.reserved.insert(delivery, Reservation { seq, deadline });
+        self.deadlines.insert((deadline, delivery));
+
+        let item = self.items.get_mut(&seq).expect("ready item exists");
+        item.deliveries += 1;
+        item.delivery = Some(delivery);
+        Some((delivery, item))
+    }
+
+    /// Sequence number of the item held by `delivery`, if it still holds it.
+    pub fn reserved_seq(&self, delivery: u64) -> Option<u64> {
+        self.reserved.get(&delivery).map(|reservation| reservation.seq)
+    }
+
+    /// Makes the item held by `delivery` ready again, at its original place.
+    pub fn release(&mut self, delivery: u64) -> bool {
+        let Some(reservation) = self.reserved.remove(&delivery) else {
+            return false;
+        };
+        self.deadlines.remove(&(reservation.deadline, delivery));
+
+        if let Some(item) = self.items.get_mut(&reservation.seq) {
+            item.delivery = None;
+        }
+        self.restore_ready(reservation.seq);
+        true
+    }
+
+    /// Records a failure for the item held by `delivery` and requeues it, or
+    /// moves it to the dead-letter queue once it has failed `max_failures`
+    /// times. Returns whether it was dead-lettered, or `None` if `delivery`
+    /// no longer holds an item.
+    pub fn reject(&mut self, delivery: u64, max_failures: u64) -> Option<bool> {
+        let seq = self.reserved_seq(delivery)?;
+        self.release(delivery);
+
+        let item = self.items.get_mut(&seq).expect("reserved item exists");
+        item.failures += 1;
+        let dead = item.failures >= max_failures;
+        if dead {
+            let key = item.ready_key(seq);
+            self.remove_ready(key);
+            self.dead.insert(seq);
+        }
+        Some(dead)
+    }
+
+    /// Sequence number of the oldest dead-lettered item, expired or not.
+    pub fn first_dead(&self) -> Option<u64> {
+        self.dead.first().copied()
+    }
+
+    /// Moves a dead-lettered item back to its place in the queue, with its
+    /// failure count reset.
+    pub fn revive(&mut self, seq: u64) -> bool {
+        if !self.dead.remove(&seq) {
+            return false;
+        }
+        if let Some(item) = self.items.get_mut(&seq) {
+            item.failures = 0;
+        }
+        self.restore_ready(seq);
+        true
+    }
+
+    /// Live dead-lettered items, oldest first.
+    pub fn iter_dead(&self, now: Instant) -> impl Iterator<Item = &QueueItem> {
+        self.dead.iter()
+            .map(|seq| &self.items[seq])
+            .filter(move |item| !item.is_expired(now))
+    }
+
+    /// Releases every reservation whose visibility deadline has passed, so
+    /// the items are delivered again.
+    pub fn release_overdue(&mut self, now: Instant) {
+        while let Some(&(deadline, delivery)) = self.deadlines.first() {
+            if deadline > now {
+                break;
+            }
+            self.release(delivery);
+        }
+    }
+
+    /// Makes every scheduled item that is due visible, in the order they
+    /// became due.
+    pub fn promote_due(&mut self, now: Instant) {
+        while let Some(&(at, seq)) = self.scheduled.first() {
+            if at > now {
+                break;
+            }
+            self.scheduled.pop_first();
+            self.items.get_mut(&seq).expect("scheduled item exists").visible_at = None;
+            self.make_ready(seq);
+        }
+    }
+
+    /// Removes every item whose TTL has passed, whatever its state, and
+    /// returns them with their sequence numbers.
+    pub fn remove_expired(&mut self, now: Instant) -> Vec<(u64, QueueItem)> {
+        let mut removed = Vec::new();
+        while let Some(&(at, seq)) = self.expiring.first() {
+            if at > now {
+                break;
+            }
+            removed.extend(self.remove(seq).map(|item| (seq, item)));
+        }
+        removed
+    }
+
+    /// Removes a scheduled item before it becomes visible.
+    pub fn cancel(&mut self, seq: u64) -> Option<QueueItem> {
+        self.items.get(&seq)?.visible_at?;
+        self.remove(seq)
+    }
+
+    /// Live scheduled items with their sequence numbers, soonest first.
+    pub fn iter_scheduled(&self, now: Instant) -> impl Iterator<Item = (u64, &QueueItem)> {
+        self.scheduled.iter()
+            .map(|&(_, seq)| (seq, &self.items[&seq]))
+            .filter(move |(_, item)| !item.is_expired(now))
+    }
+
+    /// Live ready items from front to back.
+    pub fn iter_live(&self, now: Instant) -> impl Iterator<Item = &QueueItem> {
+        self.ready.iter()
+            .map(|(_, _, seq)| &self.items[seq])
+            .filter(move |item| !item.is_expired(now))
+    }
+
+    /// Number of ready items at each priority, highest first. Expired items
+    /// count until `remove_expired` drops them.
+    pub fn depths(&self) -> Vec<(i64, usize)> {
+        self.depths.iter().rev().map(|(&priority, &depth)| (priority, depth)).collect()
+    }
+
+    /// Number of ready items. Expired items count until `remove_expired`
+    /// drops them.
+    pub fn len(&self) -> usize {
+        self.ready.len()
+    }
+
+    /// Whether the queue holds no items at all, expired, reserved or
+    /// dead-lettered ones included.
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+}
@@ -1,12 +1,44 @@
 use tokio::net::{TcpListener, TcpStream};
-use tokio::io::{AsyncReadExt, AsyncWriteExt, BufWriter};
-use bytes::{BytesMut, Bytes};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt, BufWriter};
+use bytes::{Bytes, BytesMut};
 use std::sync::Arc;
-use std::time::Duration;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
 use log::{info, error, debug};
 
-use crate::storage::{Storage, StorageError};
-use crate::protocol::{parse_command, serialize_response, RedisCommand, RedisValue};
+use crate::blocking::WaitGuard;
+use crate::command::{self, COMMANDS};
+use crate::storage::{Expiry, FieldExpire, PushOptions, SetCondition, SetOptions, Storage, StorageError, ZAddOptions};
+use crate::stream::{Fields, GroupRead, StreamEntry, StreamId};
+use crate::protocol::{
+    parse_command, serialize_response, CommandSubcommand, DlqSubcommand, Expiration, ProtocolError, ProtocolVersion,
+    RedisCommand, RedisValue, SetExpiration, XGroupSubcommand, XInfoSubcommand,
+};
+
+/// How often the active expiry cycle runs (Redis' default `hz` of 10).
+const EXPIRE_CYCLE_INTERVAL: Duration = Duration::from_millis(100);
+/// Upper bound on the time a single active expiry cycle may take.
+const EXPIRE_CYCLE_BUDGET: Duration = Duration::from_millis(25);
+
+/// Source of the ids reported by HELLO.
+static NEXT_CLIENT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Per-connection state that commands such as HELLO can change.
+pub(crate) struct Connection {
+    id: u64,
+    protocol: ProtocolVersion,
+    name: Option<String>,
+}
+
+impl Connection {
+    pub(crate) fn new() -> Self {
+        Self {
+            id: NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed),
+            protocol: ProtocolVersion::default(),
+            name: None,
+        }
+    }
+}
 
 pub struct Server {
     storage: Arc<Storage>,
@@ -21,6 +53,13 @@ impl Server {
         }
     }
     
+    /// Sets how many times a queue item may be rejected before it is moved to
+    /// the queue's dead-letter queue.
+    pub fn with_max_failures(self, max_failures: u64) -> Self {
+        self.storage.set_max_failures(max_failures);
+        self
+    }
+    
     pub async fn run(&self) -> Result<(), Box<dyn std::error::Error>> {
         let listener = TcpListener::bind(&self.addr).await?;
         info!("Rudis server listening on {}", self.addr);
@@ -29,8 +68,15 @@ impl Server {
         let storage_clone = Arc::clone(&self.storage);
         tokio::spawn(async move {
             loop {
-                tokio::time::sleep(Duration::from_secs(1)).await;
-                let removed = storage_clone.cleanup_expired();
+                // Run again straight away while a cycle was cut short by its
+                // budget, otherwise wait for the next tick
+                if storage_clone.has_due_expirations() {
+                    tokio::task::yield_now().await;
+                } else {
+                    tokio::time::sleep(EXPIRE_CYCLE_INTERVAL).await;
+                }
+                
+                let removed = storage_clone.cleanup_expired(EXPIRE_CYCLE_BUDGET);
                 if removed > 0 {
                     debug!("Removed {} expired keys", removed);
                 }
@@ -51,7 +97,7 @@ impl Server {
     }
 }
 
-async fn handle_client(
+pub(crate) async fn handle_client(
     mut socket: TcpStream, 
     storage: Arc<Storage>
 ) -> Result<(), Box<dyn std::error::Error>> {
@@ -59,6 +105,7 @@ async fn handle_client(
     let mut reader = tokio::io::BufReader::new(reader);
     let mut writer = BufWriter::new(writer);
     let mut buffer = BytesMut::with_capacity(4096);
+    let mut conn = Connection::new();
     
     loop {
         let bytes_read = reader.read_buf(&mut buffer).await?;
@@ -67,77 +114,1016 @@ async fn handle_client(
             break;
         }
         
-        match parse_command(&mut buffer) {
-            Ok(Some(cmd)) => {
-                let response = execute_command(cmd, &storage).await;
-                let serialized = serialize_response(response);
-                writer.write_all(&serialized).await?;
-                writer.flush().await?;
-                
-                // Clear the buffer for the next command
-                buffer.clear();
-            },
-            Ok(None) => {
-                // Incomplete command, continue reading
-                continue;
-            },
-            Err(e) => {
-                let error_response = serialize_response(RedisValue::Error(format!("Error: {}", e)));
-                writer.write_all(&error_response).await?;
-                writer.flush().await?;
-                buffer.clear();
+        // Drain every complete command from the buffer, batching the replies
+        // so a pipelined burst costs a single flush.
+        loop {
+            match parse_command(&mut buffer) {
+                Ok(Some(cmd)) if is_blocking(&cmd) => {
+                    // Deliver the replies to earlier pipelined commands before parking
+                    writer.flush().await?;
+                    
+                    let blocked = execute_blocking(cmd, &storage, conn.protocol, &mut reader, &mut buffer).await?;
+                    let Some(response) = blocked else {
+                        // Client disconnected while blocked
+                        return Ok(());
+                    };
+                    writer.write_all(&serialize_response(response, conn.protocol)).await?;
+                },
+                Ok(Some(cmd)) => {
+                    let response = execute_command(cmd, &storage, &mut conn).await;
+                    writer.write_all(&serialize_response(response, conn.protocol)).await?;
+                },
+                Ok(None) => {
+                    // Incomplete command, wait for more data
+                    break;
+                },
+                Err(e) => {
+                    let error_response = serialize_response(RedisValue::Error(e.to_string()), conn.protocol);
+                    writer.write_all(&error_response).await?;
+                    
+                    // A malformed frame leaves the stream out of sync, so give up on it
+                    if matches!(e, ProtocolError::Protocol(_)) {
+                        writer.flush().await?;
+                        return Ok(());
+                    }
+                }
             }
         }
+        
+        writer.flush().await?;
     }
     
     Ok(())
 }
 
-async fn execute_command(cmd: RedisCommand, storage: &Storage) -> RedisValue {
+/// Whether `cmd` may park the connection, which only `handle_client` can do.
+fn is_blocking(cmd: &RedisCommand) -> bool {
+    matches!(
+        cmd,
+        RedisCommand::BPop { .. }
+            | RedisCommand::BlockingListPop { .. }
+            | RedisCommand::BlockingListMove { .. }
+            | RedisCommand::BlockingListMPop { .. }
+            | RedisCommand::XRead { block: Some(_), .. }
+            | RedisCommand::XReadGroup { block: Some(_), .. }
+    )
+}
+
+/// Runs a blocking command, retrying it each time a push may have made it
+/// possible until it succeeds or times out. Waiting clients are served in the
+/// order they blocked.
+///
+/// Anything the client sends meanwhile is appended to `buffer` to be run once
+/// the command completes. Returns `None` if the client disconnects while
+/// waiting.
+async fn execute_blocking<R: AsyncRead + Unpin>(
+    cmd: RedisCommand,
+    storage: &Storage,
+    protocol: ProtocolVersion,
+    reader: &mut R,
+    buffer: &mut BytesMut,
+) -> std::io::Result<Option<RedisValue>> {
+    match cmd {
+        RedisCommand::BPop { timeout } => {
+            let waiter = storage.wait_for_fifo();
+            block_on(waiter, timeout, reader, buffer, || {
+                let (key, value) = storage.pop_fifo().ok()?;
+                Some(RedisValue::Array(vec![RedisValue::String(key), RedisValue::Bytes(value)]))
+            }).await
+        },
+        RedisCommand::BlockingListPop { keys, end, timeout } => {
+            let waiter = storage.wait_for_lists(&keys);
+            block_on(waiter, timeout, reader, buffer, || {
+                for key in &keys {
+                    match storage.list_pop(key, end, 1) {
+                        Ok(Some(mut popped)) => return Some(RedisValue::Array(vec![
+                            RedisValue::Bytes(key.clone().into()),
+                            RedisValue::Bytes(popped.pop()?),
+                        ])),
+                        Ok(None) => {},
+                        Err(e) => return Some(RedisValue::Error(e.to_string())),
+                    }
+                }
+                None
+            }).await
+        },
+        RedisCommand::BlockingListMove { source, destination, from, to, timeout } => {
+            let waiter = storage.wait_for_lists(std::slice::from_ref(&source));
+            block_on(waiter, timeout, reader, buffer, || {
+                match storage.list_move(&source, &destination, from, to) {
+                    Ok(moved) => moved.map(RedisValue::Bytes),
+                    Err(e) => Some(RedisValue::Error(e.to_string())),
+                }
+            }).await
+        },
+        RedisCommand::BlockingListMPop { keys, end, count, timeout } => {
+            let waiter = storage.wait_for_lists(&keys);
+            block_on(waiter, timeout, reader, buffer, || {
+                for key in &keys {
+                    match storage.list_pop(key, end, count) {
+                        Ok(Some(popped)) => return Some(RedisValue::Array(vec![
+                            RedisValue::Bytes(key.clone().into()),
+                            RedisValue::Array(popped.into_iter().map(RedisValue::Bytes).collect()),
+                        ])),
+                        Ok(None) => {},
+                        Err(e) => return Some(RedisValue::Error(e.to_string())),
+                    }
+                }
+                None
+            }).await
+        },
+        RedisCommand::XRead { streams, count, block: Some(timeout) } => {
+            // `$` means entries added from now on, so resolve it before waiting
+            let streams = match resolve_stream_ids(storage, streams) {
+                Ok(streams) => streams,
+                Err(e) => return Ok(Some(RedisValue::Error(e.to_string()))),
+            };
+            let keys: Vec<String> = streams.iter().map(|(key, _)| key.clone()).collect();
+            let waiter = storage.wait_for_streams(&keys);
+            block_on(waiter, timeout, reader, buffer, || read_streams(storage, &streams, count, protocol)).await
+        },
+        RedisCommand::XReadGroup { group, consumer, streams, count, block: Some(timeout), noack } => {
+            let keys: Vec<String> = streams.iter().map(|(key, _)| key.clone()).collect();
+            let waiter = storage.wait_for_streams(&keys);
+            block_on(waiter, timeout, reader, buffer, || {
+                read_group(storage, &group, &consumer, &streams, count, noack, protocol)
+            }).await
+        },
+        _ => unreachable!("not a blocking command"),
+    }
+}
+
+/// Calls `attempt` until it produces a reply, waiting on `waiter` between
+/// tries. Replies with a null array once `timeout` passes.
+async fn block_on<R: AsyncRead + Unpin>(
+    waiter: WaitGuard<'_>,
+    timeout: Option<Duration>,
+    reader: &mut R,
+    buffer: &mut BytesMut,
+    mut attempt: impl FnMut() -> Option<RedisValue>,
+) -> std::io::Result<Option<RedisValue>> {
+    // A deadline too far off to represent is as good as none
+    let deadline = timeout.and_then(|timeout| tokio::time::Instant::now().checked_add(timeout));
+    
+    loop {
+        if let Some(response) = attempt() {
+            return Ok(Some(response));
+        }
+        
+        tokio::select! {
+            _ = waiter.wait() => {},
+            _ = tokio::time::sleep_until(deadline.unwrap_or_else(tokio::time::Instant::now)), if deadline.is_some() => {
+                return Ok(Some(RedisValue::Null));
+            },
+            read = reader.read_buf(buffer) => {
+                if read? == 0 {
+                    return Ok(None);
+                }
+            },
+        }
+    }
+}
+
+async fn execute_command(cmd: RedisCommand, storage: &Storage, conn: &mut Connection) -> RedisValue {
     match cmd {
         RedisCommand::Get { key } => {
             match storage.get(&key) {
                 Ok(value) => RedisValue::Bytes(value),
-                Err(StorageError::KeyNotFound) => RedisValue::Nil,
-                Err(StorageError::KeyExpired) => RedisValue::Nil,
-                Err(e) => RedisValue::Error(format!("Get error: {}", e)),
+                Err(StorageError::KeyNotFound | StorageError::KeyExpired) => RedisValue::Nil,
+                Err(e) => RedisValue::Error(e.to_string()),
+            }
+        },
+        RedisCommand::GetDel { key } => match storage.get_delete(&key) {
+            Ok(value) => RedisValue::Bytes(value),
+            Err(StorageError::KeyNotFound | StorageError::KeyExpired) => RedisValue::Nil,
+            Err(e) => RedisValue::Error(e.to_string()),
+        },
+        RedisCommand::GetEx { key, expiration, persist } => {
+            let ttl = if persist { Some(None) } else { expiration.map(|expiration| Some(expiry(expiration))) };
+            match storage.get_expire(&key, ttl) {
+                Ok(value) => RedisValue::Bytes(value),
+                Err(StorageError::KeyNotFound | StorageError::KeyExpired) => RedisValue::Nil,
+                Err(e) => RedisValue::Error(e.to_string()),
+            }
+        },
+        RedisCommand::GetRange { key, start, end } => match storage.get_range(&key, start, end) {
+            Ok(value) => RedisValue::Bytes(value),
+            Err(e) => RedisValue::Error(e.to_string()),
+        },
+        RedisCommand::Append { key, value } => match storage.append(&key, &value) {
+            Ok(len) => RedisValue::Integer(len as i64),
+            Err(e) => RedisValue::Error(e.to_string()),
+        },
+        RedisCommand::StrLen { key } => match storage.string_len(&key) {
+            Ok(len) => RedisValue::Integer(len as i64),
+            Err(e) => RedisValue::Error(e.to_string()),
+        },
+        RedisCommand::IncrBy { key, increment } => match storage.incr_by(&key, increment) {
+            Ok(value) => RedisValue::Integer(value),
+            Err(e) => RedisValue::Error(e.to_string()),
+        },
+        RedisCommand::IncrByFloat { key, increment } => match storage.incr_by_float(&key, increment) {
+            Ok(value) => RedisValue::Bytes(value),
+            Err(e) => RedisValue::Error(e.to_string()),
+        },
+        RedisCommand::Set { key, value, expiration, condition, get } => {
+            let mut options = SetOptions { condition, get, ..SetOptions::default() };
+            match expiration {
+                Some(SetExpiration::KeepTtl) => options.keep_ttl = true,
+                Some(SetExpiration::Expire(expiration)) => options.expiry = Some(expiry(expiration)),
+                None => {},
+            }
+            
+            match storage.set(key, value, options) {
+                Ok(outcome) if get => outcome.previous.map_or(RedisValue::Nil, RedisValue::Bytes),
+                Ok(outcome) if outcome.written => RedisValue::String("OK".to_string()),
+                Ok(_) => RedisValue::Nil,
+                Err(e) => RedisValue::Error(e.to_string()),
+            }
+        },
+        RedisCommand::SetNx { key, value } => {
+            let options = SetOptions { condition: Some(SetCondition::NotExists), ..SetOptions::default() };
+            match storage.set(key, value, options) {
+                Ok(outcome) => RedisValue::Integer(outcome.written as i64),
+                Err(e) => RedisValue::Error(e.to_string()),
+            }
+        },
+        RedisCommand::SetRange { key, offset, value } => match storage.set_range(&key, offset, &value) {
+            Ok(len) => RedisValue::Integer(len as i64),
+            Err(e) => RedisValue::Error(e.to_string()),
+        },
+        RedisCommand::Delete { keys } => {
+            let mut deleted = 0;
+            for key in keys {
+                match storage.delete(&key) {
+                    Ok(_) => deleted += 1,
+                    Err(StorageError::KeyNotFound) => {},
+                    Err(e) => return RedisValue::Error(e.to_string()),
+                }
+            }
+            RedisValue::Integer(deleted)
+        },
+        RedisCommand::Expire { key, expiration, condition } => {
+            match storage.expire(&key, expiry(expiration), condition) {
+                Ok(applied) => RedisValue::Integer(applied as i64),
+                Err(StorageError::KeyNotFound | StorageError::KeyExpired) => RedisValue::Integer(0),
+                Err(e) => RedisValue::Error(e.to_string()),
+            }
+        },
+        RedisCommand::Ttl { key, millis } => {
+            match storage.expiry(&key) {
+                Ok(Some(expiry)) => {
+                    let remaining = expiry.remaining(Instant::now()).as_millis() as u64;
+                    RedisValue::Integer(if millis { remaining as i64 } else { round_to_secs(remaining) })
+                },
+                Ok(None) => RedisValue::Integer(-1),
+                Err(StorageError::KeyNotFound | StorageError::KeyExpired) => RedisValue::Integer(-2),
+                Err(e) => RedisValue::Error(e.to_string()),
             }
         },
-        RedisCommand::Set { key, value, ttl } => {
-            let ttl = ttl.map(Duration::from_secs);
-            match storage.set(key, value, ttl) {
-                Ok(_) => RedisValue::String("OK".to_string()),
-                Err(e) => RedisValue::Error(format!("Set error: {}", e)),
+        RedisCommand::ExpireTime { key, millis } => {
+            match storage.expiry(&key) {
+                Ok(Some(expiry)) if millis => RedisValue::Integer(expiry.unix_millis as i64),
+                Ok(Some(expiry)) => RedisValue::Integer(round_to_secs(expiry.unix_millis)),
+                Ok(None) => RedisValue::Integer(-1),
+                Err(StorageError::KeyNotFound | StorageError::KeyExpired) => RedisValue::Integer(-2),
+                Err(e) => RedisValue::Error(e.to_string()),
             }
         },
-        RedisCommand::Delete { key } => {
-            match storage.delete(&key) {
-                Ok(_) => RedisValue::Integer(1),
-                Err(StorageError::KeyNotFound) => RedisValue::Integer(0),
-                Err(e) => RedisValue::Error(format!("Delete error: {}", e)),
+        RedisCommand::Persist { key } => {
+            match storage.persist(&key) {
+                Ok(removed) => RedisValue::Integer(removed as i64),
+                Err(StorageError::KeyNotFound | StorageError::KeyExpired) => RedisValue::Integer(0),
+                Err(e) => RedisValue::Error(e.to_string()),
             }
         },
         RedisCommand::Keys { pattern } => {
             let keys = storage.keys(&pattern);
             let values = keys.into_iter()
-                .map(|k| RedisValue::String(k))
+                .map(RedisValue::String)
                 .collect();
             RedisValue::Array(values)
         },
+        RedisCommand::ListPush { key, end, values } => match storage.list_push(&key, end, values) {
+            Ok(len) => RedisValue::Integer(len as i64),
+            Err(e) => RedisValue::Error(e.to_string()),
+        },
+        RedisCommand::ListPop { key, end, count } => match storage.list_pop(&key, end, count.unwrap_or(1)) {
+            Ok(Some(popped)) if count.is_some() => {
+                RedisValue::Array(popped.into_iter().map(RedisValue::Bytes).collect())
+            },
+            Ok(Some(popped)) => popped.into_iter().next().map_or(RedisValue::Nil, RedisValue::Bytes),
+            Ok(None) if count.is_some() => RedisValue::Null,
+            Ok(None) => RedisValue::Nil,
+            Err(e) => RedisValue::Error(e.to_string()),
+        },
+        RedisCommand::ListRange { key, start, stop } => match storage.list_range(&key, start, stop) {
+            Ok(elements) => RedisValue::Array(elements.into_iter().map(RedisValue::Bytes).collect()),
+            Err(e) => RedisValue::Error(e.to_string()),
+        },
+        RedisCommand::ListLen { key } => match storage.list_len(&key) {
+            Ok(len) => RedisValue::Integer(len as i64),
+            Err(e) => RedisValue::Error(e.to_string()),
+        },
+        RedisCommand::ListIndex { key, index } => match storage.list_index(&key, index) {
+            Ok(element) => element.map_or(RedisValue::Nil, RedisValue::Bytes),
+            Err(e) => RedisValue::Error(e.to_string()),
+        },
+        RedisCommand::ListSet { key, index, value } => match storage.list_set(&key, index, value) {
+            Ok(()) => RedisValue::String("OK".to_string()),
+            Err(e) => RedisValue::Error(e.to_string()),
+        },
+        RedisCommand::ListRem { key, count, value } => match storage.list_remove(&key, count, &value) {
+            Ok(removed) => RedisValue::Integer(removed as i64),
+            Err(e) => RedisValue::Error(e.to_string()),
+        },
+        RedisCommand::ListTrim { key, start, stop } => match storage.list_trim(&key, start, stop) {
+            Ok(()) => RedisValue::String("OK".to_string()),
+            Err(e) => RedisValue::Error(e.to_string()),
+        },
+        RedisCommand::ListInsert { key, before, pivot, value } => {
+            match storage.list_insert(&key, before, &pivot, value) {
+                Ok(len) => RedisValue::Integer(len),
+                Err(e) => RedisValue::Error(e.to_string()),
+            }
+        },
+        RedisCommand::ListMove { source, destination, from, to } => {
+            match storage.list_move(&source, &destination, from, to) {
+                Ok(moved) => moved.map_or(RedisValue::Nil, RedisValue::Bytes),
+                Err(e) => RedisValue::Error(e.to_string()),
+            }
+        },
+        RedisCommand::HashSet { key, pairs } => match storage.hash_set(&key, pairs) {
+            Ok(added) => RedisValue::Integer(added as i64),
+            Err(e) => RedisValue::Error(e.to_string()),
+        },
+        RedisCommand::HashGet { key, field } => match storage.hash_get(&key, &field) {
+            Ok(value) => value.map_or(RedisValue::Nil, RedisValue::Bytes),
+            Err(e) => RedisValue::Error(e.to_string()),
+        },
+        RedisCommand::HashMGet { key, fields } => match storage.hash_get_many(&key, &fields) {
+            Ok(values) => RedisValue::Array(
+                values.into_iter().map(|value| value.map_or(RedisValue::Nil, RedisValue::Bytes)).collect(),
+            ),
+            Err(e) => RedisValue::Error(e.to_string()),
+        },
+        RedisCommand::HashDel { key, fields } => match storage.hash_delete(&key, &fields) {
+            Ok(removed) => RedisValue::Integer(removed as i64),
+            Err(e) => RedisValue::Error(e.to_string()),
+        },
+        RedisCommand::HashGetAll { key } => match storage.hash_get_all(&key) {
+            Ok(pairs) => RedisValue::Map(
+                pairs.into_iter().map(|(field, value)| (RedisValue::Bytes(field), RedisValue::Bytes(value))).collect(),
+            ),
+            Err(e) => RedisValue::Error(e.to_string()),
+        },
+        RedisCommand::HashIncrBy { key, field, increment } => match storage.hash_incr_by(&key, field, increment) {
+            Ok(value) => RedisValue::Integer(value),
+            Err(e) => RedisValue::Error(e.to_string()),
+        },
+        RedisCommand::HashIncrByFloat { key, field, increment } => {
+            match storage.hash_incr_by_float(&key, field, increment) {
+                Ok(value) => RedisValue::Bytes(value),
+                Err(e) => RedisValue::Error(e.to_string()),
+            }
+        },
+        RedisCommand::HashExists { key, field } => match storage.hash_exists(&key, &field) {
+            Ok(exists) => RedisValue::Integer(exists as i64),
+            Err(e) => RedisValue::Error(e.to_string()),
+        },
+        RedisCommand::HashLen { key } => match storage.hash_len(&key) {
+            Ok(len) => RedisValue::Integer(len as i64),
+            Err(e) => RedisValue::Error(e.to_string()),
+        },
+        RedisCommand::HashKeys { key } => match storage.hash_keys(&key) {
+            Ok(fields) => RedisValue::Array(fields.into_iter().map(RedisValue::Bytes).collect()),
+            Err(e) => RedisValue::Error(e.to_string()),
+        },
+        RedisCommand::HashVals { key } => match storage.hash_values(&key) {
+            Ok(values) => RedisValue::Array(values.into_iter().map(RedisValue::Bytes).collect()),
+            Err(e) => RedisValue::Error(e.to_string()),
+        },
+        RedisCommand::HashExpire { key, expiration, condition, fields } => {
+            match storage.hash_expire(&key, &fields, expiry(expiration), condition) {
+                Ok(outcomes) => RedisValue::Array(
+                    outcomes.into_iter()
+                        .map(|outcome| RedisValue::Integer(match outcome {
+                            FieldExpire::NoSuchField => -2,
+                            FieldExpire::NotApplied => 0,
+                            FieldExpire::Applied => 1,
+                            FieldExpire::Deleted => 2,
+                        }))
+                        .collect(),
+                ),
+                Err(e) => RedisValue::Error(e.to_string()),
+            }
+        },
+        RedisCommand::HashTtl { key, fields } => match storage.hash_field_expiry(&key, &fields) {
+            Ok(expiries) => {
+                let now = Instant::now();
+                RedisValue::Array(
+                    expiries.into_iter()
+                        .map(|expiry| RedisValue::Integer(match expiry {
+                            Some(Some(expiry)) => round_to_secs(expiry.remaining(now).as_millis() as u64),
+                            Some(None) => -1,
+                            None => -2,
+                        }))
+                        .collect(),
+                )
+            },
+            Err(e) => RedisValue::Error(e.to_string()),
+        },
+        RedisCommand::HashPersist { key, fields } => match storage.hash_persist(&key, &fields) {
+            Ok(persisted) => RedisValue::Array(
+                persisted.into_iter()
+                    .map(|persisted| RedisValue::Integer(match persisted {
+                        Some(true) => 1,
+                        Some(false) => -1,
+                        None => -2,
+                    }))
+                    .collect(),
+            ),
+            Err(e) => RedisValue::Error(e.to_string()),
+        },
+        RedisCommand::HashScan { key, cursor, pattern, count, novalues } => {
+            match storage.hash_scan(&key, cursor, pattern.as_deref(), count) {
+                Ok((next, pairs)) => {
+                    let mut elements = Vec::new();
+                    for (field, value) in pairs {
+                        elements.push(RedisValue::Bytes(field));
+                        if !novalues {
+                            elements.push(RedisValue::Bytes(value));
+                        }
+                    }
+                    RedisValue::Array(vec![
+                        RedisValue::Bytes(next.to_string().into()),
+                        RedisValue::Array(elements),
+                    ])
+                },
+                Err(e) => RedisValue::Error(e.to_string()),
+            }
+        },
+        RedisCommand::SetAdd { key, members } => match storage.set_add(&key, members) {
+            Ok(added) => RedisValue::Integer(added as i64),
+            Err(e) => RedisValue::Error(e.to_string()),
+        },
+        RedisCommand::SetRem { key, members } => match storage.set_remove(&key, &members) {
+            Ok(removed) => RedisValue::Integer(removed as i64),
+            Err(e) => RedisValue::Error(e.to_string()),
+        },
+        RedisCommand::SetMembers { key } => match storage.set_members(&key) {
+            Ok(members) => RedisValue::Set(members.into_iter().map(RedisValue::Bytes).collect()),
+            Err(e) => RedisValue::Error(e.to_string()),
+        },
+        RedisCommand::SetIsMember { key, member } => match storage.set_contains(&key, &member) {
+            Ok(found) => RedisValue::Integer(found as i64),
+            Err(e) => RedisValue::Error(e.to_string()),
+        },
+        RedisCommand::SetMIsMember { key, members } => match storage.set_contains_many(&key, &members) {
+            Ok(found) => RedisValue::Array(found.into_iter().map(|found| RedisValue::Integer(found as i64)).collect()),
+            Err(e) => RedisValue::Error(e.to_string()),
+        },
+        RedisCommand::SetCard { key } => match storage.set_len(&key) {
+            Ok(len) => RedisValue::Integer(len as i64),
+            Err(e) => RedisValue::Error(e.to_string()),
+        },
+        RedisCommand::SetPop { key, count } => match storage.set_pop(&key, count.unwrap_or(1)) {
+            Ok(members) if count.is_some() => RedisValue::Set(members.into_iter().map(RedisValue::Bytes).collect()),
+            Ok(members) => members.into_iter().next().map_or(RedisValue::Nil, RedisValue::Bytes),
+            Err(e) => RedisValue::Error(e.to_string()),
+        },
+        RedisCommand::SetRandMember { key, count } => match storage.set_random(&key, count.unwrap_or(1)) {
+            Ok(members) if count.is_some() => RedisValue::Array(members.into_iter().map(RedisValue::Bytes).collect()),
+            Ok(members) => members.into_iter().next().map_or(RedisValue::Nil, RedisValue::Bytes),
+            Err(e) => RedisValue::Error(e.to_string()),
+        },
+        RedisCommand::SetCombine { keys, op } => match storage.set_combine(&keys, op) {
+            Ok(members) => RedisValue::Set(members.into_iter().map(RedisValue::Bytes).collect()),
+            Err(e) => RedisValue::Error(e.to_string()),
+        },
+        RedisCommand::SetCombineStore { destination, keys, op } => {
+            match storage.set_combine_store(&destination, &keys, op) {
+                Ok(len) => RedisValue::Integer(len as i64),
+                Err(e) => RedisValue::Error(e.to_string()),
+            }
+        },
+        RedisCommand::SetInterCard { keys, limit } => {
+            let limit = if limit == 0 { usize::MAX } else { limit };
+            match storage.set_intersection_len(&keys, limit) {
+                Ok(len) => RedisValue::Integer(len as i64),
+                Err(e) => RedisValue::Error(e.to_string()),
+            }
+        },
+        RedisCommand::ZAdd { key, options, increment: true, members } => {
+            let (increment, member) = members.into_iter().next().expect("ZADD INCR takes one member");
+            match storage.zset_incr(&key, member, increment, options) {
+                Ok(score) => score.map_or(RedisValue::Nil, RedisValue::Double),
+                Err(e) => RedisValue::Error(e.to_string()),
+            }
+        },
+        RedisCommand::ZAdd { key, options, increment: false, members } => {
+            match storage.zset_add(&key, members, options) {
+                Ok(count) => RedisValue::Integer(count as i64),
+                Err(e) => RedisValue::Error(e.to_string()),
+            }
+        },
+        RedisCommand::ZCard { key } => match storage.zset_len(&key) {
+            Ok(len) => RedisValue::Integer(len as i64),
+            Err(e) => RedisValue::Error(e.to_string()),
+        },
+        RedisCommand::ZCount { key, by } => match storage.zset_count(&key, &by) {
+            Ok(count) => RedisValue::Integer(count as i64),
+            Err(e) => RedisValue::Error(e.to_string()),
+        },
+        RedisCommand::ZIncrBy { key, increment, member } => {
+            match storage.zset_incr(&key, member, increment, ZAddOptions::default()) {
+                Ok(score) => score.map_or(RedisValue::Nil, RedisValue::Double),
+                Err(e) => RedisValue::Error(e.to_string()),
+            }
+        },
+        RedisCommand::ZRange { key, by, rev, limit, with_scores } => match storage.zset_range(&key, &by, rev, limit) {
+            Ok(members) if with_scores => scored_reply(members, conn.protocol),
+            Ok(members) => RedisValue::Array(
+                members.into_iter().map(|(member, _)| RedisValue::Bytes(member)).collect(),
+            ),
+            Err(e) => RedisValue::Error(e.to_string()),
+        },
+        RedisCommand::ZRank { key, member, rev } => match storage.zset_rank(&key, &member, rev) {
+            Ok(rank) => rank.map_or(RedisValue::Nil, |rank| RedisValue::Integer(rank as i64)),
+            Err(e) => RedisValue::Error(e.to_string()),
+        },
+        RedisCommand::ZScore { key, member } => match storage.zset_score(&key, &member) {
+            Ok(score) => score.map_or(RedisValue::Nil, RedisValue::Double),
+            Err(e) => RedisValue::Error(e.to_string()),
+        },
+        RedisCommand::ZMScore { key, members } => match storage.zset_scores(&key, &members) {
+            Ok(scores) => RedisValue::Array(
+                scores.into_iter().map(|score| score.map_or(RedisValue::Nil, RedisValue::Double)).collect(),
+            ),
+            Err(e) => RedisValue::Error(e.to_string()),
+        },
+        RedisCommand::ZRem { key, members } => match storage.zset_remove(&key, &members) {
+            Ok(removed) => RedisValue::Integer(removed as i64),
+            Err(e) => RedisValue::Error(e.to_string()),
+        },
+        RedisCommand::ZRemRange { key, by } => match storage.zset_remove_range(&key, &by) {
+            Ok(removed) => RedisValue::Integer(removed as i64),
+            Err(e) => RedisValue::Error(e.to_string()),
+        },
+        RedisCommand::ZPop { key, max, count } => match storage.zset_pop(&key, count.unwrap_or(1), max) {
+            Ok(members) if count.is_some() => scored_reply(members, conn.protocol),
+            // A single pop is a flat pair in either protocol
+            Ok(members) => scored_reply(members, ProtocolVersion::Resp2),
+            Err(e) => RedisValue::Error(e.to_string()),
+        },
+        RedisCommand::ZCombineStore { destination, keys, weights, aggregate, op } => {
+            match storage.zset_combine_store(&destination, &keys, &weights, aggregate, op) {
+                Ok(len) => RedisValue::Integer(len as i64),
+                Err(e) => RedisValue::Error(e.to_string()),
+            }
+        },
+        RedisCommand::XAdd { key, id, fields, options } => match storage.stream_add(&key, id, fields, options) {
+            Ok(id) => id.map_or(RedisValue::Nil, stream_id_reply),
+            Err(e) => RedisValue::Error(e.to_string()),
+        },
+        RedisCommand::XLen { key } => match storage.stream_len(&key) {
+            Ok(len) => RedisValue::Integer(len as i64),
+            Err(e) => RedisValue::Error(e.to_string()),
+        },
+        RedisCommand::XRange { key, start, end, count, rev } => {
+            match storage.stream_range(&key, start, end, count.unwrap_or(usize::MAX), rev) {
+                Ok(entries) => entries_reply(entries),
+                Err(e) => RedisValue::Error(e.to_string()),
+            }
+        },
+        RedisCommand::XDel { key, ids } => match storage.stream_delete(&key, &ids) {
+            Ok(deleted) => RedisValue::Integer(deleted as i64),
+            Err(e) => RedisValue::Error(e.to_string()),
+        },
+        RedisCommand::XRead { streams, count, .. } => match resolve_stream_ids(storage, streams) {
+            Ok(streams) => read_streams(storage, &streams, count, conn.protocol).unwrap_or(RedisValue::Null),
+            Err(e) => RedisValue::Error(e.to_string()),
+        },
+        RedisCommand::XReadGroup { group, consumer, streams, count, noack, .. } => {
+            read_group(storage, &group, &consumer, &streams, count, noack, conn.protocol).unwrap_or(RedisValue::Null)
+        },
+        RedisCommand::XGroup { key, group, subcommand } => {
+            let ok = || RedisValue::String("OK".to_string());
+            let result = match subcommand {
+                XGroupSubcommand::Create { id, mkstream } => {
+                    storage.stream_group_create(&key, group, id, mkstream).map(|()| ok())
+                },
+                XGroupSubcommand::SetId { id } => storage.stream_group_set_id(&key, &group, id).map(|()| ok()),
+                XGroupSubcommand::Destroy => {
+                    storage.stream_group_destroy(&key, &group).map(|destroyed| RedisValue::Integer(destroyed as i64))
+                },
+                XGroupSubcommand::CreateConsumer { consumer } => {
+                    storage.stream_create_consumer(&key, &group, &consumer).map(|new| RedisValue::Integer(new as i64))
+                },
+                XGroupSubcommand::DelConsumer { consumer } => {
+                    storage.stream_delete_consumer(&key, &group, &consumer).map(|pending| RedisValue::Integer(pending as i64))
+                },
+            };
+            result.unwrap_or_else(|e| RedisValue::Error(e.to_string()))
+        },
+        RedisCommand::XAck { key, group, ids } => match storage.stream_ack(&key, &group, &ids) {
+            Ok(acked) => RedisValue::Integer(acked as i64),
+            Err(e) => RedisValue::Error(e.to_string()),
+        },
+        RedisCommand::XPending { key, group, filter: None } => match storage.stream_pending_summary(&key, &group) {
+            Ok(summary) => {
+                let consumers = summary.consumers.into_iter()
+                    .map(|(name, count)| RedisValue::Array(vec![
+                        RedisValue::Bytes(name),
+                        RedisValue::Bytes(count.to_string().into()),
+                    ]))
+                    .collect::<Vec<_>>();
+                RedisValue::Array(vec![
+                    RedisValue::Integer(summary.count as i64),
+                    summary.first.map_or(RedisValue::Nil, stream_id_reply),
+                    summary.last.map_or(RedisValue::Nil, stream_id_reply),
+                    if consumers.is_empty() { RedisValue::Null } else { RedisValue::Array(consumers) },
+                ])
+            },
+            Err(e) => RedisValue::Error(e.to_string()),
+        },
+        RedisCommand::XPending { key, group, filter: Some(filter) } => {
+            match storage.stream_pending(&key, &group, &filter) {
+                Ok(entries) => RedisValue::Array(
+                    entries.into_iter()
+                        .map(|entry| RedisValue::Array(vec![
+                            stream_id_reply(entry.id),
+                            RedisValue::Bytes(entry.consumer),
+                            RedisValue::Integer(entry.idle as i64),
+                            RedisValue::Integer(entry.deliveries as i64),
+                        ]))
+                        .collect(),
+                ),
+                Err(e) => RedisValue::Error(e.to_string()),
+            }
+        },
+        RedisCommand::XClaim { key, group, consumer, ids, options } => {
+            match storage.stream_claim(&key, &group, &consumer, &ids, &options) {
+                Ok(claimed) if options.just_id => {
+                    RedisValue::Array(claimed.into_iter().map(|(id, _)| stream_id_reply(id)).collect())
+                },
+                Ok(claimed) => entries_reply(claimed),
+                Err(e) => RedisValue::Error(e.to_string()),
+            }
+        },
+        RedisCommand::XAutoClaim { key, group, consumer, start, count, options } => {
+            match storage.stream_auto_claim(&key, &group, &consumer, start, count, &options) {
+                Ok(result) => RedisValue::Array(vec![
+                    stream_id_reply(result.next),
+                    if options.just_id {
+                        RedisValue::Array(result.claimed.into_iter().map(|(id, _)| stream_id_reply(id)).collect())
+                    } else {
+                        entries_reply(result.claimed)
+                    },
+                    RedisValue::Array(result.deleted.into_iter().map(stream_id_reply).collect()),
+                ]),
+                Err(e) => RedisValue::Error(e.to_string()),
+            }
+        },
+        RedisCommand::XInfo { key, subcommand: XInfoSubcommand::Stream } => match storage.stream_info(&key) {
+            Ok(info) => {
+                let entry = |entry: Option<StreamEntry>| {
+                    entry.map_or(RedisValue::Nil, |(id, fields)| entry_reply(id, Some(fields)))
+                };
+                RedisValue::Map(vec![
+                    (RedisValue::Bytes("length".into()), RedisValue::Integer(info.length as i64)),
+                    (RedisValue::Bytes("last-generated-id".into()), stream_id_reply(info.last_id)),
+                    (RedisValue::Bytes("max-deleted-entry-id".into()), stream_id_reply(info.max_deleted_id)),
+                    (RedisValue::Bytes("entries-added".into()), RedisValue::Integer(info.entries_added as i64)),
+                    (
+                        RedisValue::Bytes("recorded-first-entry-id".into()),
+                        stream_id_reply(info.first.as_ref().map_or(StreamId::MIN, |(id, _)| *id)),
+                    ),
+                    (RedisValue::Bytes("groups".into()), RedisValue::Integer(info.groups as i64)),
+                    (RedisValue::Bytes("first-entry".into()), entry(info.first)),
+                    (RedisValue::Bytes("last-entry".into()), entry(info.last)),
+                ])
+            },
+            Err(e) => RedisValue::Error(e.to_string()),
+        },
+        RedisCommand::XInfo { key, subcommand: XInfoSubcommand::Groups } => match storage.stream_groups(&key) {
+            Ok(groups) => RedisValue::Array(
+                groups.into_iter()
+                    .map(|group| RedisValue::Map(vec![
+                        (RedisValue::Bytes("name".into()), RedisValue::Bytes(group.name)),
+                        (RedisValue::Bytes("consumers".into()), RedisValue::Integer(group.consumers as i64)),
+                        (RedisValue::Bytes("pending".into()), RedisValue::Integer(group.pending as i64)),
+                        (RedisValue::Bytes("last-delivered-id".into()), stream_id_reply(group.last_delivered)),
+                    ]))
+                    .collect(),
+            ),
+            Err(e) => RedisValue::Error(e.to_string()),
+        },
+        RedisCommand::XInfo { key, subcommand: XInfoSubcommand::Consumers { group } } => {
+            match storage.stream_consumers(&key, &group) {
+                Ok(consumers) => RedisValue::Array(
+                    consumers.into_iter()
+                        .map(|consumer| RedisValue::Map(vec![
+                            (RedisValue::Bytes("name".into()), RedisValue::Bytes(consumer.name)),
+                            (RedisValue::Bytes("pending".into()), RedisValue::Integer(consumer.pending as i64)),
+                            (RedisValue::Bytes("idle".into()), RedisValue::Integer(consumer.idle as i64)),
+                            (
+                                RedisValue::Bytes("inactive".into()),
+                                RedisValue::Integer(consumer.inactive.map_or(-1, |inactive| inactive as i64)),
+                            ),
+                        ]))
+                        .collect(),
+                ),
+                Err(e) => RedisValue::Error(e.to_string()),
+            }
+        },
         RedisCommand::Pop => {
             match storage.pop_fifo() {
                 Ok((key, value)) => RedisValue::Array(vec![
                     RedisValue::String(key),
                     RedisValue::Bytes(value),
                 ]),
-                Err(StorageError::KeyNotFound) => RedisValue::Nil,
-                Err(e) => RedisValue::Error(format!("Pop error: {}", e)),
+                Err(StorageError::KeyNotFound | StorageError::KeyExpired) => RedisValue::Nil,
+                Err(e) => RedisValue::Error(e.to_string()),
             }
         },
-        RedisCommand::Ping => RedisValue::String("PONG".to_string()),
+        RedisCommand::BPop { .. }
+        | RedisCommand::BlockingListPop { .. }
+        | RedisCommand::BlockingListMove { .. }
+        | RedisCommand::BlockingListMPop { .. } => unreachable!("blocking commands are run by handle_client"),
+        RedisCommand::QPush { queue, value, expiration, delay, priority } => {
+            let options = PushOptions {
+                expiry: expiration.map(expiry),
+                visible_at: delay.map(expiry),
+                priority,
+            };
+            RedisValue::Integer(storage.queue_push(&queue, value, options) as i64)
+        },
+        RedisCommand::QPop { queue, count } => {
+            queue_reply(storage.queue_pop(&queue, count.unwrap_or(1)), count.is_some())
+        },
+        RedisCommand::QPeek { queue, count } => {
+            queue_reply(storage.queue_peek(&queue, count.unwrap_or(1)), count.is_some())
+        },
+        RedisCommand::QLen { queue } => RedisValue::Integer(storage.queue_len(&queue) as i64),
+        RedisCommand::QDepth { queue } => RedisValue::Map(
+            storage.queue_depths(&queue)
+                .into_iter()
+                .map(|(priority, depth)| (RedisValue::Integer(priority), RedisValue::Integer(depth as i64)))
+                .collect(),
+        ),
+        RedisCommand::QReserve { queue, visibility } => match storage.queue_reserve(&queue, visibility) {
+            Some(delivery) => RedisValue::Array(vec![
+                RedisValue::Integer(delivery.id as i64),
+                RedisValue::Bytes(delivery.data),
+                RedisValue::Integer(delivery.deliveries as i64),
+            ]),
+            None => RedisValue::Null,
+        },
+        RedisCommand::QAck { queue, deliveries } => {
+            RedisValue::Integer(storage.queue_ack(&queue, &deliveries) as i64)
+        },
+        RedisCommand::QNack { queue, deliveries } => {
+            RedisValue::Integer(storage.queue_nack(&queue, &deliveries) as i64)
+        },
+        RedisCommand::QReject { queue, deliveries } => {
+            RedisValue::Integer(storage.queue_reject(&queue, &deliveries) as i64)
+        },
+        RedisCommand::QDlq { queue, subcommand } => match subcommand {
+            DlqSubcommand::List { count } => RedisValue::Array(
+                storage.queue_dead(&queue, count.unwrap_or(usize::MAX))
+                    .into_iter()
+                    .map(|(data, failures)| RedisValue::Array(vec![
+                        RedisValue::Bytes(data),
+                        RedisValue::Integer(failures as i64),
+                    ]))
+                    .collect(),
+            ),
+            DlqSubcommand::Len => RedisValue::Integer(storage.queue_dead_len(&queue) as i64),
+            DlqSubcommand::Replay { count } => {
+                RedisValue::Integer(storage.queue_replay(&queue, count.unwrap_or(usize::MAX)) as i64)
+            },
+            DlqSubcommand::Purge => RedisValue::Integer(storage.queue_purge_dead(&queue) as i64),
+        },
+        RedisCommand::QScheduled { queue, count } => RedisValue::Array(
+            storage.queue_scheduled(&queue, count.unwrap_or(usize::MAX))
+                .into_iter()
+                .map(|(id, data, visible_at)| RedisValue::Array(vec![
+                    RedisValue::Integer(id as i64),
+                    RedisValue::Bytes(data),
+                    RedisValue::Integer(visible_at.unix_millis as i64),
+                ]))
+                .collect(),
+        ),
+        RedisCommand::QCancel { queue, ids } => RedisValue::Integer(storage.queue_cancel(&queue, &ids) as i64),
+        RedisCommand::Ping { message: None } => RedisValue::String("PONG".to_string()),
+        RedisCommand::Ping { message: Some(message) } => RedisValue::Bytes(message),
+        RedisCommand::Command { subcommand: None } => {
+            RedisValue::Array(COMMANDS.iter().map(|spec| spec.info()).collect())
+        },
+        RedisCommand::Command { subcommand: Some(CommandSubcommand::Count) } => {
+            RedisValue::Integer(COMMANDS.len() as i64)
+        },
+        RedisCommand::Command { subcommand: Some(CommandSubcommand::Info { names }) } => {
+            if names.is_empty() {
+                return RedisValue::Array(COMMANDS.iter().map(|spec| spec.info()).collect());
+            }
+            RedisValue::Array(
+                names.iter()
+                    .map(|name| command::lookup(name.as_bytes()).map_or(RedisValue::Nil, |spec| spec.info()))
+                    .collect(),
+            )
+        },
+        RedisCommand::Command { subcommand: Some(CommandSubcommand::Docs { names }) } => {
+            let specs: Vec<_> = if names.is_empty() {
+                COMMANDS.iter().collect()
+            } else {
+                names.iter().filter_map(|name| command::lookup(name.as_bytes())).collect()
+            };
+            RedisValue::Map(
+                specs.into_iter()
+                    .map(|spec| (RedisValue::Bytes(spec.name.into()), spec.docs()))
+                    .collect(),
+            )
+        },
         RedisCommand::Info => {
-            let info = "# Rudis\r\nversion:0.1.0\r\nrust_version:1.68.0\r\n";
-            RedisValue::String(info.to_string())
+            let stats = storage.stats();
+            let mut info = "# Rudis\r\nversion:0.1.0\r\nrust_version:1.68.0\r\n".to_string();
+            info.push_str(&format!(
+                "\r\n# Stats\r\nexpired_keys:{}\r\nexpired_subkeys:{}\r\nexpired_time_cap_reached_count:{}\r\n",
+                stats.expired_keys, stats.expired_fields, stats.expire_cycle_cap_reached,
+            ));
+            info.push_str(&format!(
+                "\r\n# Keyspace\r\ndb0:keys={},expires={}\r\nqueues:{}\r\n",
+                stats.keys, stats.expires, stats.queues,
+            ));
+            RedisValue::Verbatim {
+                format: "txt".to_string(),
+                text: info,
+            }
         },
+        RedisCommand::Hello { protover, auth, setname } => {
+            let protocol = match protover {
+                None => conn.protocol,
+                Some(2) => ProtocolVersion::Resp2,
+                Some(3) => ProtocolVersion::Resp3,
+                Some(_) => return RedisValue::Error("NOPROTO unsupported protocol version".to_string()),
+            };
+            
+            // There is no ACL support, so only the passwordless default user exists
+            if let Some((username, _)) = auth
+                && username != "default" {
+                return RedisValue::Error(
+                    "WRONGPASS invalid username-password pair or user is disabled.".to_string(),
+                );
+            }
+            
+            conn.protocol = protocol;
+            if setname.is_some() {
+                conn.name = setname;
+            }
+            
+            let proto = match protocol {
+                ProtocolVersion::Resp2 => 2,
+                ProtocolVersion::Resp3 => 3,
+            };
+            RedisValue::Map(vec![
+                (RedisValue::Bytes("server".into()), RedisValue::Bytes("rudis".into())),
+                (RedisValue::Bytes("version".into()), RedisValue::Bytes(env!("CARGO_PKG_VERSION").into())),
+                (RedisValue::Bytes("proto".into()), RedisValue::Integer(proto)),
+                (RedisValue::Bytes("id".into()), RedisValue::Integer(conn.id as i64)),
+                (RedisValue::Bytes("mode".into()), RedisValue::Bytes("standalone".into())),
+                (RedisValue::Bytes("role".into()), RedisValue::Bytes("master".into())),
+                (RedisValue::Bytes("modules".into()), RedisValue::Array(Vec::new())),
+            ])
+        },
+    }
+}
+
+/// Shapes the items taken from a queue like LPOP does: a single bulk string
+/// (or nil) without COUNT, an array (or a null array) with it.
+fn queue_reply(items: Vec<Bytes>, with_count: bool) -> RedisValue {
+    if with_count {
+        if items.is_empty() {
+            RedisValue::Null
+        } else {
+            RedisValue::Array(items.into_iter().map(RedisValue::Bytes).collect())
+        }
+    } else {
+        items.into_iter().next().map_or(RedisValue::Nil, RedisValue::Bytes)
+    }
+}
+
+/// Shapes members with their scores like ZRANGE WITHSCORES: a flat array in
+/// RESP2, an array of `[member, score]` pairs in RESP3.
+fn scored_reply(members: Vec<(Bytes, f64)>, protocol: ProtocolVersion) -> RedisValue {
+    let pairs = members.into_iter().map(|(member, score)| [RedisValue::Bytes(member), RedisValue::Double(score)]);
+    match protocol {
+        ProtocolVersion::Resp3 => RedisValue::Array(pairs.map(|pair| RedisValue::Array(pair.to_vec())).collect()),
+        _ => RedisValue::Array(pairs.flatten().collect()),
     }
-}
\ No newline at end of file
+}
+
+/// Stream IDs are sent as bulk strings.
+fn stream_id_reply(id: StreamId) -> RedisValue {
+    RedisValue::Bytes(id.to_string().into())
+}
+
+/// A stream entry as `[id, [field, value, ...]]`, with a null array in place
+/// of the fields of an entry deleted since it was delivered.
+fn entry_reply(id: StreamId, fields: Option<Fields>) -> RedisValue {
+    let fields = fields.map_or(RedisValue::Null, |fields| {
+        RedisValue::Array(
+            fields.into_iter()
+                .flat_map(|(field, value)| [RedisValue::Bytes(field), RedisValue::Bytes(value)])
+                .collect(),
+        )
+    });
+    RedisValue::Array(vec![stream_id_reply(id), fields])
+}
+
+fn entries_reply(entries: Vec<StreamEntry>) -> RedisValue {
+    RedisValue::Array(entries.into_iter().map(|(id, fields)| entry_reply(id, Some(fields))).collect())
+}
+
+/// Entries read from several streams, keyed by stream: a map in RESP3 and
+/// `[key, entries]` pairs in RESP2.
+fn streams_reply(streams: Vec<(String, RedisValue)>, protocol: ProtocolVersion) -> RedisValue {
+    let pairs = streams.into_iter().map(|(key, entries)| (RedisValue::Bytes(key.into()), entries));
+    match protocol {
+        ProtocolVersion::Resp3 => RedisValue::Map(pairs.collect()),
+        _ => RedisValue::Array(pairs.map(|(key, entries)| RedisValue::Array(vec![key, entries])).collect()),
+    }
+}
+
+/// Replaces the `$` IDs of XREAD with the last IDs of their streams.
+fn resolve_stream_ids(
+    storage: &Storage,
+    streams: Vec<(String, Option<StreamId>)>,
+) -> Result<Vec<(String, StreamId)>, StorageError> {
+    streams.into_iter()
+        .map(|(key, id)| {
+            let id = match id {
+                Some(id) => id,
+                None => storage.stream_last_id(&key)?,
+            };
+            Ok((key, id))
+        })
+        .collect()
+}
+
+/// Runs XREAD, or returns `None` if no stream has anything to read yet.
+fn read_streams(
+    storage: &Storage,
+    streams: &[(String, StreamId)],
+    count: Option<usize>,
+    protocol: ProtocolVersion,
+) -> Option<RedisValue> {
+    match storage.stream_read(streams, count.unwrap_or(usize::MAX)) {
+        Ok(read) if read.is_empty() => None,
+        Ok(read) => Some(streams_reply(
+            read.into_iter().map(|(key, entries)| (key, entries_reply(entries))).collect(),
+            protocol,
+        )),
+        Err(e) => Some(RedisValue::Error(e.to_string())),
+    }
+}
+
+/// Runs XREADGROUP, or returns `None` if it only asks for new entries and
+/// there are none yet.
+fn read_group(
+    storage: &Storage,
+    group: &[u8],
+    consumer: &Bytes,
+    streams: &[(String, GroupRead)],
+    count: Option<usize>,
+    noack: bool,
+    protocol: ProtocolVersion,
+) -> Option<RedisValue> {
+    match storage.stream_read_group(group, consumer, streams, count.unwrap_or(usize::MAX), noack) {
+        Ok(read) if read.is_empty() => None,
+        Ok(read) => {
+            let read = read.into_iter()
+                .map(|(key, entries)| {
+                    let entries = entries.into_iter().map(|(id, fields)| entry_reply(id, fields)).collect();
+                    (key, RedisValue::Array(entries))
+                })
+                .collect();
+            Some(streams_reply(read, protocol))
+        },
+        Err(e) => Some(RedisValue::Error(e.to_string())),
+    }
+}
+
+/// Converts a requested expiration into a storage deadline.
+fn expiry(expiration: Expiration) -> Expiry {
+    match expiration {
+        Expiration::After(ttl) => Expiry::after(ttl),
+        Expiration::AtUnixMillis(millis) => Expiry::at_unix_millis(millis),
+    }
+}
+
+/// Rounds milliseconds to the nearest second, as TTL and EXPIRETIME do.
+fn round_to_secs(millis: u64) -> i64 {
+    millis.saturating_add(500) as i64 / 1000
+}
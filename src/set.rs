@@ -0,0 +1,262 @@
+use std::collections::{HashMap, HashSet};
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use bytes::Bytes;
+
+/// Sets with more members than this are stored as a table.
+const MAX_INTSET_MEMBERS: usize = 512;
+
+/// How SINTER, SUNION and SDIFF combine their sets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetOp {
+    Inter,
+    Union,
+    /// The members of the first set that are in none of the others.
+    Diff,
+}
+
+/// The members of a set value.
+///
+/// Small sets whose members are all integers are kept as a sorted array of
+/// numbers, which takes a fraction of the memory of a table. As in Redis, a
+/// set is converted to a table for good once it gets a member that is not an
+/// integer or outgrows the limit above.
+#[derive(Debug, Clone)]
+pub struct Set {
+    members: Members,
+}
+
+#[derive(Debug, Clone)]
+enum Members {
+    /// Sorted, without duplicates.
+    Ints(Vec<i64>),
+    Table(Table),
+}
+
+/// The members of a set stored as a table. They are also kept in a `Vec`,
+/// each knowing its place there, so that a random one can be picked in
+/// constant time.
+#[derive(Debug, Clone, Default)]
+struct Table {
+    members: Vec<Bytes>,
+    positions: HashMap<Bytes, usize>,
+}
+
+impl Table {
+    fn contains(&self, member: &[u8]) -> bool {
+        self.positions.contains_key(member)
+    }
+
+    fn insert(&mut self, member: Bytes) -> bool {
+        if self.contains(&member) {
+            return false;
+        }
+        self.positions.insert(member.clone(), self.members.len());
+        self.members.push(member);
+        true
+    }
+
+    /// Removes `member` by moving the last member into its place.
+    fn remove(&mut self, member: &[u8]) -> bool {
+        let Some(position) = self.positions.remove(member) else {
+            return false;
+        };
+        self.members.swap_remove(position);
+        if let Some(moved) = self.members.get(position) {
+            self.positions.insert(moved.clone(), position);
+        }
+        true
+    }
+}
+
+impl Default for Set {
+    fn default() -> Self {
+        Self { members: Members::Ints(Vec::new()) }
+    }
+}
+
+impl Set {
+    pub fn len(&self) -> usize {
+        match &self.members {
+            Members::Ints(ints) => ints.len(),
+            Members::Table(table) => table.members.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn contains(&self, member: &[u8]) -> bool {
+        match &self.members {
+            Members::Ints(ints) => as_int(member).is_some_and(|int| ints.binary_search(&int).is_ok()),
+            Members::Table(table) => table.contains(member),
+        }
+    }
+
+    /// Adds `member`, returning whether it is new.
+    pub fn insert(&mut self, member: Bytes) -> bool {
+        if let Members::Ints(ints) = &mut self.members {
+            if let Some(int) = as_int(&member) {
+                let Err(position) = ints.binary_search(&int) else {
+                    return false;
+                };
+                if ints.len() < MAX_INTSET_MEMBERS {
+                    ints.insert(position, int);
+                    return true;
+                }
+            }
+            let mut table = Table::default();
+            for int in ints.iter() {
+                table.insert(Bytes::from(int.to_string()));
+            }
+            self.members = Members::Table(table);
+        }
+
+        let Members::Table(table) = &mut self.members else {
+            unreachable!("converted above");
+        };
+        table.insert(member)
+    }
+
+    /// Removes `member`, returning whether it was there.
+    pub fn remove(&mut self, member: &[u8]) -> bool {
+        match &mut self.members {
+            Members::Ints(ints) => match as_int(member).map(|int| ints.binary_search(&int)) {
+                Some(Ok(position)) => {
+                    ints.remove(position);
+                    true
+                },
+                _ => false,
+            },
+            Members::Table(table) => table.remove(member),
+        }
+    }
+
+    /// The members, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = Bytes> + '_ {
+        let (ints, table) = match &self.members {
+            Members::Ints(ints) => (Some(ints), None),
+            Members::Table(table) => (None, Some(&table.members)),
+        };
+        ints.into_iter().flatten()
+            .map(|int| Bytes::from(int.to_string()))
+            .chain(table.into_iter().flatten().cloned())
+    }
+
+    /// The member at `index` in the order `iter` returns them.
+    fn get(&self, index: usize) -> Bytes {
+        match &self.members {
+            Members::Ints(ints) => Bytes::from(ints[index].to_string()),
+            Members::Table(table) => table.members[index].clone(),
+        }
+    }
+
+    /// `count` members picked at random, each pick independent of the others
+    /// so members may repeat.
+    pub fn random_repeated(&self, count: usize) -> Vec<Bytes> {
+        if self.is_empty() {
+            return Vec::new();
+        }
+
+        // Grow the reply as picks are made rather than reserving `count` up front
+        let mut picks = Vec::new();
+        for _ in 0..count {
+            picks.push(self.get(random_index(self.len())));
+        }
+        picks
+    }
+
+    /// Up to `count` distinct members picked at random.
+    pub fn random_distinct(&self, count: usize) -> Vec<Bytes> {
+        let len = self.len();
+        if count >= len {
+            return self.iter().collect();
+        }
+
+        // For a small share of the set, draw until there are enough distinct
+        // picks, which costs about `count` draws. Otherwise a partial
+        // Fisher-Yates shuffle of the positions brings `count` to the front.
+        if count * 3 <= len {
+            let mut picked = HashSet::new();
+            while picked.len() < count {
+                picked.insert(random_index(len));
+            }
+            return picked.into_iter().map(|index| self.get(index)).collect();
+        }
+        let mut positions: Vec<usize> = (0..len).collect();
+        for i in 0..count {
+            let j = i + random_index(len - i);
+            positions.swap(i, j);
+        }
+        positions[..count].iter().map(|&index| self.get(index)).collect()
+    }
+
+    /// Removes up to `count` members picked at random and returns them.
+    pub fn pop(&mut self, count: usize) -> Vec<Bytes> {
+        let popped = self.random_distinct(count);
+        for member in &popped {
+            self.remove(member);
+        }
+        popped
+    }
+
+    /// Combines `sets` as `op` says. A missing key counts as an empty set.
+    pub fn combine(sets: &[Option<Set>], op: SetOp) -> Set {
+        let mut result = Set::default();
+        let Some((first, rest)) = sets.split_first() else {
+            return result;
+        };
+
+        match op {
+            SetOp::Union => {
+                for member in sets.iter().flatten().flat_map(Set::iter) {
+                    result.insert(member);
+                }
+            },
+            SetOp::Inter => {
+                for member in intersection(sets) {
+                    result.insert(member);
+                }
+            },
+            SetOp::Diff => {
+                for member in first.iter().flat_map(Set::iter) {
+                    if !rest.iter().flatten().any(|set| set.contains(&member)) {
+                        result.insert(member);
+                    }
+                }
+            },
+        }
+        result
+    }
+
+    /// Size of the intersection of `sets`, counting no further than `limit`.
+    pub fn intersection_len(sets: &[Option<Set>], limit: usize) -> usize {
+        intersection(sets).take(limit).count()
+    }
+}
+
+/// Members of every one of `sets`, found by probing the others with the
+/// members of the smallest. A missing key makes the intersection empty.
+fn intersection(sets: &[Option<Set>]) -> impl Iterator<Item = Bytes> + '_ {
+    let smallest = match sets.iter().map(Option::as_ref).collect::<Option<Vec<&Set>>>() {
+        Some(sets) => sets.into_iter().min_by_key(|set| set.len()),
+        None => None,
+    };
+    smallest.into_iter()
+        .flat_map(Set::iter)
+        .filter(move |member| sets.iter().flatten().all(|set| set.contains(member)))
+}
+
+/// The member as an integer, if it is the canonical decimal form of one so
+/// that it reads back the same.
+fn as_int(member: &[u8]) -> Option<i64> {
+    let int: i64 = std::str::from_utf8(member).ok()?.parse().ok()?;
+    (int.to_string().as_bytes() == member).then_some(int)
+}
+
+/// A random position in `0..len`. Every `RandomState` hashes with different
+/// keys, which is random enough to pick members without pulling in a generator.
+fn random_index(len: usize) -> usize {
+    (RandomState::new().hash_one(0u8) % len as u64) as usize
+}
@@ -0,0 +1,480 @@
+use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::ops::Range;
+use bytes::Bytes;
+
+use crate::set::SetOp;
+
+/// Most levels a skiplist node can have, plenty for any set that fits in
+/// memory with each level kept at probability 1/4.
+const MAX_LEVEL: usize = 32;
+/// Index of the skiplist's header node, which holds no member.
+const HEAD: usize = 0;
+
+/// One end of a score range, as ZRANGE BYSCORE and ZCOUNT take them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScoreBound {
+    Inclusive(f64),
+    /// Written with a leading `(`.
+    Exclusive(f64),
+}
+
+/// One end of a lexicographic range, as ZRANGE BYLEX takes them.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LexBound {
+    /// `-`, before every member.
+    Min,
+    /// `+`, after every member.
+    Max,
+    Inclusive(Bytes),
+    Exclusive(Bytes),
+}
+
+/// Which members a ZRANGE-style command covers.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RangeBy {
+    /// Positions in score order; negative ones count from the end.
+    Rank(i64, i64),
+    /// Scores from the first bound up to the second.
+    Score(ScoreBound, ScoreBound),
+    /// Members from the first bound up to the second. As in Redis, this is
+    /// only meaningful when every member has the same score.
+    Lex(LexBound, LexBound),
+}
+
+/// The `LIMIT offset count` of ZRANGE: how many matching members to skip,
+/// then how many to return at most.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Limit {
+    pub offset: usize,
+    pub count: Option<usize>,
+}
+
+/// How ZUNIONSTORE and ZINTERSTORE combine the scores of a member found in
+/// several sets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Aggregate {
+    #[default]
+    Sum,
+    Min,
+    Max,
+}
+
+impl Aggregate {
+    fn apply(self, a: f64, b: f64) -> f64 {
+        match self {
+            // inf + -inf counts as 0, as in Redis
+            Aggregate::Sum => zero_if_nan(a + b),
+            Aggregate::Min => a.min(b),
+            Aggregate::Max => a.max(b),
+        }
+    }
+}
+
+/// The members of a sorted set value, ordered by score and then by member.
+///
+/// Scores are looked up in a table, and the order is kept in a skiplist whose
+/// links record how many positions they skip, so finding a member's rank or
+/// the member at a rank takes O(log n) like any other lookup.
+#[derive(Debug, Clone, Default)]
+pub struct SortedSet {
+    scores: HashMap<Bytes, f64>,
+    list: SkipList,
+}
+
+impl SortedSet {
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn score(&self, member: &[u8]) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    /// Sets the score of `member`, returning the score it had before.
+    pub fn insert(&mut self, member: Bytes, score: f64) -> Option<f64> {
+        let old = self.scores.insert(member.clone(), score);
+        match old {
+            Some(old) if old == score => {},
+            Some(old) => {
+                self.list.remove(old, &member);
+                self.list.insert(score, member);
+            },
+            None => self.list.insert(score, member),
+        }
+        old
+    }
+
+    /// Removes `member`, returning the score it had.
+    pub fn remove(&mut self, member: &[u8]) -> Option<f64> {
+        let score = self.scores.remove(member)?;
+        self.list.remove(score, member);
+        Some(score)
+    }
+
+    /// Position of `member` in score order, starting from 0.
+    pub fn rank(&self, member: &[u8]) -> Option<usize> {
+        let score = self.score(member)?;
+        Some(self.list.count_while(|node| node.precedes(score, member)))
+    }
+
+    /// The members `by` covers with their scores, in score order or in
+    /// reverse with `rev`, less those cut off by `limit`.
+    pub fn range(&self, by: &RangeBy, rev: bool, limit: Limit) -> Vec<(Bytes, f64)> {
+        let mut ranks = self.ranks(by);
+        if rev && let RangeBy::Rank(..) = by {
+            // Positions count from the highest score instead
+            ranks = self.len() - ranks.end..self.len() - ranks.start;
+        }
+
+        let count = limit.count.unwrap_or(usize::MAX).min(ranks.len().saturating_sub(limit.offset));
+        if count == 0 {
+            return Vec::new();
+        }
+        let first = if rev { ranks.end - 1 - limit.offset } else { ranks.start + limit.offset };
+        self.list.walk(first, rev).take(count).map(|node| (node.member.clone(), node.score)).collect()
+    }
+
+    /// How many members `by` covers.
+    pub fn count(&self, by: &RangeBy) -> usize {
+        self.ranks(by).len()
+    }
+
+    /// Removes the members `by` covers, returning how many there were.
+    pub fn remove_range(&mut self, by: &RangeBy) -> usize {
+        let ranks = self.ranks(by);
+        let members: Vec<Bytes> = self.list.walk(ranks.start, false)
+            .take(ranks.len())
+            .map(|node| node.member.clone())
+            .collect();
+        for member in &members {
+            self.remove(member);
+        }
+        members.len()
+    }
+
+    /// Removes up to `count` members with the lowest scores, or the highest
+    /// with `max`, and returns them lowest or highest first respectively.
+    pub fn pop(&mut self, count: usize, max: bool) -> Vec<(Bytes, f64)> {
+        let count = count.min(self.len());
+        if count == 0 {
+            return Vec::new();
+        }
+        let first = if max { self.len() - 1 } else { 0 };
+        let popped: Vec<(Bytes, f64)> = self.list.walk(first, max)
+            .take(count)
+            .map(|node| (node.member.clone(), node.score))
+            .collect();
+        for (member, _) in &popped {
+            self.remove(member);
+        }
+        popped
+    }
+
+    /// Members and their scores, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (&Bytes, f64)> {
+        self.scores.iter().map(|(member, &score)| (member, score))
+    }
+
+    /// Combines `sets` as `op` says, as ZUNIONSTORE and ZINTERSTORE do. Each
+    /// set's scores are first multiplied by its weight, 1 if none is given,
+    /// and `aggregate` merges the scores of members found in several sets.
+    /// A missing key counts as an empty set.
+    pub fn combine(sets: &[Option<SortedSet>], weights: &[f64], aggregate: Aggregate, op: SetOp) -> SortedSet {
+        let weighted = |i: usize, score: f64| zero_if_nan(score * weights.get(i).copied().unwrap_or(1.0));
+        let mut scores: HashMap<Bytes, f64> = HashMap::new();
+
+        match op {
+            SetOp::Union => {
+                for (i, set) in sets.iter().enumerate() {
+                    for (member, score) in set.iter().flat_map(SortedSet::iter) {
+                        let score = weighted(i, score);
+                        scores.entry(member.clone())
+                            .and_modify(|current| *current = aggregate.apply(*current, score))
+                            .or_insert(score);
+                    }
+                }
+            },
+            SetOp::Inter => {
+                let Some(sets) = sets.iter().map(Option::as_ref).collect::<Option<Vec<&SortedSet>>>() else {
+                    return SortedSet::default();
+                };
+                let Some(smallest) = sets.iter().min_by_key(|set| set.len()) else {
+                    return SortedSet::default();
+                };
+                for (member, _) in smallest.iter() {
+                    let combined = sets.iter().enumerate()
+                        .map(|(i, set)| set.score(member).map(|score| weighted(i, score)))
+                        .reduce(|a, b| Some(aggregate.apply(a?, b?)));
+                    if let Some(Some(score)) = combined {
+                        scores.insert(member.clone(), score);
+                    }
+                }
+            },
+            SetOp::Diff => {
+                if let Some((first, rest)) = sets.split_first() {
+                    for (member, score) in first.iter().flat_map(SortedSet::iter) {
+                        if !rest.iter().flatten().any(|set| set.score(member).is_some()) {
+                            scores.insert(member.clone(), weighted(0, score));
+                        }
+                    }
+                }
+            },
+        }
+        scores.into_iter().collect()
+    }
+
+    /// The ranks `by` covers, in ascending score order.
+    fn ranks(&self, by: &RangeBy) -> Range<usize> {
+        let (start, end) = match by {
+            RangeBy::Rank(start, stop) => {
+                let len = self.len() as i64;
+                let start = if *start < 0 { start + len } else { *start }.clamp(0, len);
+                let stop = if *stop < 0 { stop + len } else { (*stop).min(len - 1) };
+                (start as usize, (stop + 1).clamp(start, len) as usize)
+            },
+            RangeBy::Score(min, max) => (
+                self.list.count_while(|node| below_min(min, node.score)),
+                self.list.count_while(|node| !above_max(max, node.score)),
+            ),
+            RangeBy::Lex(min, max) => (
+                self.list.count_while(|node| below_lex_min(min, &node.member)),
+                self.list.count_while(|node| !above_lex_max(max, &node.member)),
+            ),
+        };
+        start..end.max(start)
+    }
+}
+
+impl FromIterator<(Bytes, f64)> for SortedSet {
+    fn from_iter<I: IntoIterator<Item = (Bytes, f64)>>(members: I) -> Self {
+        let mut set = SortedSet::default();
+        for (member, score) in members {
+            set.insert(member, score);
+        }
+        set
+    }
+}
+
+fn below_min(min: &ScoreBound, score: f64) -> bool {
+    match *min {
+        ScoreBound::Inclusive(min) => score < min,
+        ScoreBound::Exclusive(min) => score <= min,
+    }
+}
+
+fn above_max(max: &ScoreBound, score: f64) -> bool {
+    match *max {
+        ScoreBound::Inclusive(max) => score > max,
+        ScoreBound::Exclusive(max) => score >= max,
+    }
+}
+
+fn below_lex_min(min: &LexBound, member: &Bytes) -> bool {
+    match min {
+        LexBound::Min => false,
+        LexBound::Max => true,
+        LexBound::Inclusive(min) => member < min,
+        LexBound::Exclusive(min) => member <= min,
+    }
+}
+
+fn above_lex_max(max: &LexBound, member: &Bytes) -> bool {
+    match max {
+        LexBound::Min => true,
+        LexBound::Max => false,
+        LexBound::Inclusive(max) => member > max,
+        LexBound::Exclusive(max) => member >= max,
+    }
+}
+
+fn zero_if_nan(score: f64) -> f64 {
+    if score.is_nan() { 0.0 } else { score }
+}
+
+/// A skiplist of `(score, member)` pairs in ascending order. Nodes live in an
+/// arena and refer to each other by index; freed slots are reused.
+#[derive(Debug, Clone)]
+struct SkipList {
+    nodes: Vec<Node>,
+    free: Vec<usize>,
+    /// Levels in use, at least 1.
+    level: usize,
+    len: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    member: Bytes,
+    score: f64,
+    /// The previous node, `None` for the first.
+    backward: Option<usize>,
+    levels: Vec<Link>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Link {
+    forward: Option<usize>,
+    /// How many positions following the link advances; a link to nowhere
+    /// spans the rest of the list.
+    span: usize,
+}
+
+impl Default for SkipList {
+    fn default() -> Self {
+        let head = Node { member: Bytes::new(), score: 0.0, backward: None, levels: vec![Link::default(); MAX_LEVEL] };
+        Self { nodes: vec![head], free: Vec::new(), level: 1, len: 0 }
+    }
+}
+
+impl Node {
+    /// Whether this node comes before `(score, member)`.
+    fn precedes(&self, score: f64, member: &[u8]) -> bool {
+        self.score < score || (self.score == score && &self.member[..] < member)
+    }
+}
+
+impl SkipList {
+    /// Adds a pair, which must not already be in the list.
+    fn insert(&mut self, score: f64, member: Bytes) {
+        // The last node before the new one on each level, and its rank
+        let mut update = [HEAD; MAX_LEVEL];
+        let mut rank = [0; MAX_LEVEL];
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            rank[i] = rank.get(i + 1).copied().unwrap_or(0);
+            while let Some(next) = self.nodes[x].levels[i].forward
+                && self.nodes[next].precedes(score, &member) {
+                rank[i] += self.nodes[x].levels[i].span;
+                x = next;
+            }
+            update[i] = x;
+        }
+
+        let level = random_level();
+        for i in self.level..level {
+            self.nodes[HEAD].levels[i].span = self.len;
+        }
+        self.level = self.level.max(level);
+
+        let backward = (update[0] != HEAD).then_some(update[0]);
+        let node = self.alloc(Node { member, score, backward, levels: vec![Link::default(); level] });
+        for i in 0..level {
+            let before = self.nodes[update[i]].levels[i];
+            let offset = rank[0] - rank[i];
+            self.nodes[node].levels[i] = Link { forward: before.forward, span: before.span - offset };
+            self.nodes[update[i]].levels[i] = Link { forward: Some(node), span: offset + 1 };
+        }
+        for (i, &prev) in update.iter().enumerate().take(self.level).skip(level) {
+            self.nodes[prev].levels[i].span += 1;
+        }
+        if let Some(next) = self.nodes[node].levels[0].forward {
+            self.nodes[next].backward = Some(node);
+        }
+        self.len += 1;
+    }
+
+    /// Removes a pair, returning whether it was in the list.
+    fn remove(&mut self, score: f64, member: &[u8]) -> bool {
+        let mut update = [HEAD; MAX_LEVEL];
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.nodes[x].levels[i].forward
+                && self.nodes[next].precedes(score, member) {
+                x = next;
+            }
+            update[i] = x;
+        }
+        let Some(node) = self.nodes[x].levels[0].forward
+            .filter(|&node| self.nodes[node].score == score && self.nodes[node].member == member) else {
+            return false;
+        };
+
+        for (i, &prev) in update.iter().enumerate().take(self.level) {
+            let next = self.nodes[node].levels.get(i).copied();
+            let link = &mut self.nodes[prev].levels[i];
+            match next {
+                Some(next) if link.forward == Some(node) => {
+                    link.span = link.span + next.span - 1;
+                    link.forward = next.forward;
+                },
+                _ => link.span -= 1,
+            }
+        }
+        if let Some(next) = self.nodes[node].levels[0].forward {
+            self.nodes[next].backward = self.nodes[node].backward;
+        }
+        while self.level > 1 && self.nodes[HEAD].levels[self.level - 1].forward.is_none() {
+            self.level -= 1;
+        }
+
+        self.nodes[node] = Node { member: Bytes::new(), score: 0.0, backward: None, levels: Vec::new() };
+        self.free.push(node);
+        self.len -= 1;
+        true
+    }
+
+    /// How many nodes from the start satisfy `before`, which must hold for
+    /// some prefix of the list and for nothing after it.
+    fn count_while(&self, before: impl Fn(&Node) -> bool) -> usize {
+        let mut x = HEAD;
+        let mut rank = 0;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.nodes[x].levels[i].forward
+                && before(&self.nodes[next]) {
+                rank += self.nodes[x].levels[i].span;
+                x = next;
+            }
+        }
+        rank
+    }
+
+    /// The node at position `rank`, starting from 0.
+    fn node_at(&self, rank: usize) -> Option<usize> {
+        let mut x = HEAD;
+        let mut traversed = 0;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.nodes[x].levels[i].forward
+                && traversed + self.nodes[x].levels[i].span <= rank + 1 {
+                traversed += self.nodes[x].levels[i].span;
+                x = next;
+            }
+            if traversed == rank + 1 {
+                return Some(x);
+            }
+        }
+        None
+    }
+
+    /// The nodes from position `rank` onwards, or backwards with `rev`.
+    fn walk(&self, rank: usize, rev: bool) -> impl Iterator<Item = &Node> {
+        let step = move |&x: &usize| if rev { self.nodes[x].backward } else { self.nodes[x].levels[0].forward };
+        std::iter::successors(self.node_at(rank), step).map(|x| &self.nodes[x])
+    }
+
+    fn alloc(&mut self, node: Node) -> usize {
+        match self.free.pop() {
+            Some(x) => {
+                self.nodes[x] = node;
+                x
+            },
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            },
+        }
+    }
+}
+
+/// A level for a new node, each one past the first kept with probability
+/// 1/4. `RandomState` supplies fresh keys on every call, so hashing anything
+/// with it gives random bits.
+fn random_level() -> usize {
+    let bits = RandomState::new().hash_one(0u8);
+    (1 + bits.trailing_zeros() as usize / 2).min(MAX_LEVEL)
+}
@@ -1,155 +1,2300 @@
-use std::sync::Arc;
-use std::time::{Duration, Instant};
-use dashmap::DashMap;
-use bytes::Bytes;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::ops::Bound;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use dashmap::{DashMap, SharedValue};
+use dashmap::mapref::entry::Entry;
+use dashmap::mapref::one::{Ref, RefMut};
+use bytes::{Bytes, BytesMut};
 use thiserror::Error;
 
+use crate::blocking::{WaitGuard, WaitList, WaitRegistry};
+use crate::glob;
+use crate::hash::Hash;
+use crate::list::{self, ListEnd};
+use crate::queue::{Delivery, Queue, QueueItem};
+use crate::set::{Set, SetOp};
+use crate::sorted_set::{Aggregate, Limit, RangeBy, SortedSet};
+use crate::stream::{
+    AutoClaim, ClaimOptions, ConsumerInfo, Fields, GroupEntry, GroupInfo, GroupRead, NewId, PendingEntry, PendingFilter,
+    PendingSummary, Stream, StreamEntry, StreamId, StreamInfo, Trim,
+};
+
+/// Errors raised by storage operations. Commands usually turn the missing-key
+/// cases into nil replies; any other error is sent to the client verbatim, so
+/// messages carry their Redis error prefix.
 #[derive(Error, Debug)]
 pub enum StorageError {
-    #[error("key not found")]
+    #[error("ERR no such key")]
     KeyNotFound,
-    #[error("key expired")]
+    #[error("ERR no such key")]
     KeyExpired,
-    #[error("value deserialization failed")]
-    DeserializationError,
+    #[error("WRONGTYPE Operation against a key holding the wrong kind of value")]
+    WrongType,
+    #[error("ERR index out of range")]
+    IndexOutOfRange,
+    #[error("ERR hash value is not an integer")]
+    HashValueNotInteger,
+    #[error("ERR hash value is not a float")]
+    HashValueNotFloat,
+    #[error("ERR increment or decrement would overflow")]
+    Overflow,
+    #[error("ERR increment would produce NaN or Infinity")]
+    NaNOrInfinity,
+    #[error("ERR resulting score is not a number (NaN)")]
+    ScoreNaN,
+    #[error("ERR value is not an integer or out of range")]
+    NotInteger,
+    #[error("ERR value is not a valid float")]
+    NotFloat,
+    #[error("ERR string exceeds maximum allowed size (proto-max-bulk-len)")]
+    StringTooLong,
+    #[error("ERR The ID specified in XADD is equal or smaller than the target stream top item")]
+    StreamIdTooSmall,
+    #[error("ERR The stream has exhausted the last possible ID, unable to add more items")]
+    StreamExhausted,
+    #[error("NOGROUP No such key '{key}' or consumer group '{group}'")]
+    NoGroup { key: String, group: String },
+    #[error("BUSYGROUP Consumer Group name already exists")]
+    BusyGroup,
+    #[error(
+        "ERR The XGROUP subcommand requires the key to exist. Note that for CREATE you may want to use the \
+         MKSTREAM option to create an empty stream automatically."
+    )]
+    NoStream,
 }
 
 pub type Result<T> = std::result::Result<T, StorageError>;
 
+/// Largest string APPEND and SETRANGE may build (Redis' `proto-max-bulk-len`).
+const MAX_STRING_LEN: usize = 512 * 1024 * 1024;
+
+/// When a key expires.
+///
+/// Expiry checks use the monotonic `at`, but the wall-clock time the deadline
+/// was requested for is kept alongside it so that absolute expirations
+/// (EXPIREAT, EXPIRETIME) round-trip exactly instead of drifting through the
+/// `Instant` conversion.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Expiry {
+    pub at: Instant,
+    pub unix_millis: u64,
+}
+
+impl Expiry {
+    /// Expires `ttl` from now.
+    pub fn after(ttl: Duration) -> Self {
+        let now = Instant::now();
+        Self {
+            // Saturate absurdly distant deadlines instead of overflowing `Instant`
+            at: now.checked_add(ttl).unwrap_or(now + Duration::from_secs(u32::MAX as u64)),
+            unix_millis: unix_millis_now().saturating_add(ttl.as_millis() as u64),
+        }
+    }
+
+    /// Expires at the given Unix time in milliseconds, which may be in the past.
+    pub fn at_unix_millis(unix_millis: u64) -> Self {
+        let now = Instant::now();
+        let now_millis = unix_millis_now();
+        
+        let at = if unix_millis >= now_millis {
+            let ttl = Duration::from_millis(unix_millis - now_millis);
+            now.checked_add(ttl).unwrap_or(now + Duration::from_secs(u32::MAX as u64))
+        } else {
+            now.checked_sub(Duration::from_millis(now_millis - unix_millis)).unwrap_or(now)
+        };
+        Self { at, unix_millis }
+    }
+
+    /// Time left before the deadline, zero once it has passed.
+    pub fn remaining(&self, now: Instant) -> Duration {
+        self.at.saturating_duration_since(now)
+    }
+}
+
+fn unix_millis_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// A value of one of the supported types.
+#[derive(Debug)]
+enum Value {
+    String(Bytes),
+    /// Elements from head to tail.
+    List(VecDeque<Bytes>),
+    Hash(Hash),
+    Set(Set),
+    SortedSet(SortedSet),
+    Stream(Stream),
+}
+
+impl Value {
+    /// Whether the value is an empty collection, which Redis never keeps.
+    /// Streams are the exception: they keep their groups when emptied.
+    fn is_empty(&self) -> bool {
+        match self {
+            Value::String(_) => false,
+            Value::List(list) => list.is_empty(),
+            Value::Hash(hash) => hash.is_empty(),
+            Value::Set(set) => set.is_empty(),
+            Value::SortedSet(zset) => zset.is_empty(),
+            Value::Stream(_) => false,
+        }
+    }
+}
+
 struct ValueEntry {
-    data: Bytes,
-    expiry: Option<Instant>,
-    insertion_time: Instant,
+    value: Value,
+    expiry: Option<Expiry>,
+    /// Position of the key in the FIFO served by POP. Only keys written by SET
+    /// are queued there.
+    fifo_seq: Option<u64>,
+}
+
+impl ValueEntry {
+    fn new(value: Value) -> Self {
+        Self { value, expiry: None, fifo_seq: None }
+    }
+
+    fn is_expired(&self, now: Instant) -> bool {
+        self.expiry.is_some_and(|expiry| now >= expiry.at)
+    }
+}
+
+/// Existence precondition for a SET (`NX` / `XX`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetCondition {
+    NotExists,
+    Exists,
+}
+
+#[derive(Debug, Default)]
+pub struct SetOptions {
+    pub condition: Option<SetCondition>,
+    /// New expiry deadline. `None` clears any existing TTL unless `keep_ttl` is set.
+    pub expiry: Option<Expiry>,
+    pub keep_ttl: bool,
+    /// The previous value is wanted (`SET ... GET`), so a key holding another
+    /// type is an error rather than overwritten.
+    pub get: bool,
+}
+
+/// How QPUSH enqueues an item.
+#[derive(Debug, Default)]
+pub struct PushOptions {
+    pub expiry: Option<Expiry>,
+    /// Keeps the item hidden until then; it joins the back of the queue when due.
+    pub visible_at: Option<Expiry>,
+    /// Higher priorities are popped first.
+    pub priority: i64,
+}
+
+/// Preconditions for changing a key's TTL. Redis allows `XX` to be combined
+/// with `GT` or `LT`, so these are flags rather than a single choice. A key
+/// without a TTL counts as having an infinite one for `gt` and `lt`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ExpireCondition {
+    /// Only if the key has no TTL (`NX`).
+    pub nx: bool,
+    /// Only if the key has a TTL (`XX`).
+    pub xx: bool,
+    /// Only if the new deadline is later than the current one (`GT`).
+    pub gt: bool,
+    /// Only if the new deadline is earlier than the current one (`LT`).
+    pub lt: bool,
+}
+
+impl ExpireCondition {
+    fn allows(&self, current: Option<Expiry>, new: Expiry) -> bool {
+        let later = current.is_some_and(|current| new.at > current.at);
+        let earlier = current.is_none_or(|current| new.at < current.at);
+        
+        (!self.nx || current.is_none())
+            && (!self.xx || current.is_some())
+            && (!self.gt || later)
+            && (!self.lt || earlier)
+    }
+}
+
+/// How ZADD may change a sorted set.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ZAddOptions {
+    /// Only add new members (`NX`) or only update existing ones (`XX`).
+    pub condition: Option<SetCondition>,
+    /// Only update a score if the new one compares this way to the current
+    /// one, `Greater` for `GT` and `Less` for `LT`. New members are still added.
+    pub only_if: Option<std::cmp::Ordering>,
+    /// Count updated members along with added ones (`CH`).
+    pub changed: bool,
+}
+
+/// How XADD adds to a stream.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StreamAddOptions {
+    /// Leave a missing stream missing instead of creating it (`NOMKSTREAM`).
+    pub no_create: bool,
+    /// Trim the stream after adding (`MAXLEN` / `MINID`).
+    pub trim: Option<Trim>,
+    /// Most entries the trim may drop (`LIMIT`).
+    pub trim_limit: Option<usize>,
+}
+
+#[derive(Debug)]
+pub struct SetOutcome {
+    /// Whether the condition held and the value was stored.
+    pub written: bool,
+    /// The live value the key held before the call, if any.
+    pub previous: Option<Bytes>,
+}
+
+/// What HEXPIRE did to one field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldExpire {
+    NoSuchField,
+    /// The condition did not hold.
+    NotApplied,
+    Applied,
+    /// The deadline had already passed, so the field was deleted.
+    Deleted,
+}
+
+/// Something that can expire, as recorded in the expiry index.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum ExpiryTarget {
+    Key(String),
+    /// An item of a named queue, identified by its sequence number.
+    QueueItem(String, u64),
+    /// A field of the hash at a key.
+    HashField(String, Bytes),
+}
+
+impl ExpiryTarget {
+    fn is_key(&self) -> bool {
+        matches!(self, ExpiryTarget::Key(_))
+    }
+}
+
+/// Sets the score of `member` as one element of a ZADD, adding `score` to
+/// the current one with `increment`. Returns the new score, or `None` if
+/// `options` ruled the change out.
+fn zadd_member(
+    zset: &mut SortedSet,
+    member: Bytes,
+    score: f64,
+    increment: bool,
+    options: &ZAddOptions,
+) -> Result<Option<f64>> {
+    let current = zset.score(&member);
+    match (options.condition, current) {
+        (Some(SetCondition::NotExists), Some(_)) | (Some(SetCondition::Exists), None) => return Ok(None),
+        _ => {},
+    }
+
+    let score = match current {
+        Some(current) if increment => current + score,
+        _ => score,
+    };
+    if score.is_nan() {
+        return Err(StorageError::ScoreNaN);
+    }
+    if let (Some(current), Some(order)) = (current, options.only_if)
+        && score.partial_cmp(&current) != Some(order) {
+        return Ok(None);
+    }
+    zset.insert(member, score);
+    Ok(Some(score))
+}
+
+/// Parses a string value as an integer the way Redis does: only the canonical
+/// decimal form counts, so `+1`, `01` and `-0` are not integers.
+fn parse_integer(data: &[u8]) -> Option<i64> {
+    let int: i64 = std::str::from_utf8(data).ok()?.parse().ok()?;
+    (int.to_string().as_bytes() == data).then_some(int)
+}
+
+/// Significant digits INCRBYFLOAT keeps, as in Redis.
+const FLOAT_DIGITS: usize = 17;
+
+/// Adds two floats the way INCRBYFLOAT does and returns the sum as stored.
+///
+/// Redis adds in long double and prints 17 significant digits in fixed
+/// notation, so `0.1 + 0.2` is stored as `0.3` while small increments to a
+/// large value still register. Adding the shortest decimal forms of the
+/// operands exactly and rounding to the same digits gives the same text.
+fn add_floats(current: f64, increment: f64) -> Result<Bytes> {
+    if !(current + increment).is_finite() {
+        return Err(StorageError::NaNOrInfinity);
+    }
+    let sum = Decimal::from_f64(current).add(Decimal::from_f64(increment)).round(FLOAT_DIGITS).to_string();
+    // Rounding up can still carry past the largest double
+    if !sum.parse::<f64>().is_ok_and(f64::is_finite) {
+        return Err(StorageError::NaNOrInfinity);
+    }
+    Ok(Bytes::from(sum))
+}
+
+/// An exact decimal number: `digits`, most significant first, divided by
+/// `10^scale`.
+struct Decimal {
+    negative: bool,
+    digits: Vec<u8>,
+    scale: usize,
+}
+
+impl Decimal {
+    /// The shortest decimal that reads back as the finite `value`.
+    fn from_f64(value: f64) -> Self {
+        let text = value.abs().to_string();
+        let (int, fraction) = text.split_once('.').unwrap_or((&text, ""));
+        let digits = int.bytes().chain(fraction.bytes()).map(|digit| digit - b'0').collect();
+        Decimal { negative: value.is_sign_negative(), digits, scale: fraction.len() }
+    }
+
+    fn add(mut self, mut other: Decimal) -> Decimal {
+        // Line the digits up on the decimal point, with a spare leading zero
+        // for a carry
+        let scale = self.scale.max(other.scale);
+        let int_len = (self.digits.len() - self.scale).max(other.digits.len() - other.scale) + 1;
+        for decimal in [&mut self, &mut other] {
+            let (int_pad, fraction_pad) = (int_len + decimal.scale - decimal.digits.len(), scale - decimal.scale);
+            decimal.digits.splice(0..0, std::iter::repeat_n(0, int_pad));
+            decimal.digits.resize(decimal.digits.len() + fraction_pad, 0);
+            decimal.scale = scale;
+        }
+
+        if self.negative != other.negative && self.digits < other.digits {
+            std::mem::swap(&mut self, &mut other);
+        }
+        let subtract = self.negative != other.negative;
+        let mut carry = 0;
+        for (digit, other) in self.digits.iter_mut().zip(&other.digits).rev() {
+            let value = if subtract { *digit as i8 - *other as i8 - carry } else { (*digit + *other) as i8 + carry };
+            (*digit, carry) = match value {
+                ..0 => ((value + 10) as u8, 1),
+                10.. if !subtract => ((value - 10) as u8, 1),
+                _ => (value as u8, 0),
+            };
+        }
+        self
+    }
+
+    /// Rounds to `precision` significant digits, halves away from zero.
+    fn round(mut self, precision: usize) -> Decimal {
+        self.digits.insert(0, 0);
+        let first = self.digits.iter().position(|&digit| digit != 0).unwrap_or(self.digits.len());
+        let Some(&next) = self.digits.get(first + precision) else {
+            return self;
+        };
+        self.digits[first + precision..].fill(0);
+        if next >= 5 {
+            for digit in self.digits[..first + precision].iter_mut().rev() {
+                *digit = (*digit + 1) % 10;
+                if *digit != 0 {
+                    break;
+                }
+            }
+        }
+        self
+    }
+}
+
+impl std::fmt::Display for Decimal {
+    /// Fixed notation, without leading or trailing zeros.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (int, fraction) = self.digits.split_at(self.digits.len() - self.scale);
+        let int = &int[int.iter().position(|&digit| digit != 0).unwrap_or(int.len())..];
+        let fraction = &fraction[..fraction.iter().rposition(|&digit| digit != 0).map_or(0, |last| last + 1)];
+        if self.negative && !(int.is_empty() && fraction.is_empty()) {
+            f.write_str("-")?;
+        }
+        if int.is_empty() {
+            f.write_str("0")?;
+        }
+        for digit in int {
+            write!(f, "{}", digit)?;
+        }
+        if !fraction.is_empty() {
+            f.write_str(".")?;
+            for digit in fraction {
+                write!(f, "{}", digit)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn no_group(key: &str, group: &[u8]) -> StorageError {
+    StorageError::NoGroup { key: key.to_string(), group: String::from_utf8_lossy(group).into_owned() }
+}
+
+/// The fields of `value` that have a TTL, if it is a hash.
+fn field_expiries(value: &Value) -> Vec<(Bytes, Expiry)> {
+    match value {
+        Value::Hash(hash) => hash.expiries().map(|(field, expiry)| (field.clone(), expiry)).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Counters reported through INFO.
+#[derive(Debug, Clone, Copy)]
+pub struct StorageStats {
+    pub keys: usize,
+    pub expires: usize,
+    pub queues: usize,
+    /// Keys removed because their TTL passed, lazily or by the active cycle.
+    pub expired_keys: u64,
+    /// Hash fields removed because their TTL passed.
+    pub expired_fields: u64,
+    /// Active expiry cycles that stopped at their time budget with due keys left.
+    pub expire_cycle_cap_reached: u64,
 }
 
 pub struct Storage {
     map: Arc<DashMap<String, ValueEntry>>,
-    fifo_keys: Arc<DashMap<Instant, String>>,
+    /// Keys in the order they were SET, keyed by a monotonic sequence number
+    /// so that writes within the same `Instant` stay distinct. Every entry in
+    /// `map` appears here exactly once; lock order is shard, then FIFO.
+    fifo_keys: Mutex<BTreeMap<u64, String>>,
+    next_fifo_seq: AtomicU64,
+    /// Clients blocked in BPOP, woken as SET enqueues keys.
+    fifo_waiters: WaitList,
+    /// Clients blocked in BLPOP and friends, woken as elements are pushed.
+    list_waiters: WaitRegistry,
+    /// Clients blocked in XREAD and XREADGROUP, woken as entries are added.
+    stream_waiters: WaitRegistry,
+    /// Named queues, in a namespace of their own.
+    queues: DashMap<String, Queue>,
+    /// Keys and queue items with a TTL ordered by deadline, so the active
+    /// expiry cycle only visits what is actually due. Lock order is shard,
+    /// then index.
+    expires: Mutex<BTreeSet<(Instant, ExpiryTarget)>>,
+    /// Number of keys in `expires`, changed under its lock so INFO can read
+    /// it without walking the index.
+    expiring_keys: AtomicUsize,
+    /// Rejections after which a queue item is moved to its dead-letter queue.
+    max_failures: AtomicU64,
+    expired_keys: AtomicU64,
+    expired_fields: AtomicU64,
+    expire_cycle_cap_reached: AtomicU64,
 }
 
+/// Keys removed per index lock acquisition in `cleanup_expired`.
+const EXPIRE_BATCH: usize = 64;
+/// Default for `Storage::set_max_failures`.
+const DEFAULT_MAX_FAILURES: u64 = 5;
+
 impl Storage {
     pub fn new() -> Self {
         Self {
             map: Arc::new(DashMap::new()),
-            fifo_keys: Arc::new(DashMap::new()),
+            fifo_keys: Mutex::new(BTreeMap::new()),
+            next_fifo_seq: AtomicU64::new(0),
+            fifo_waiters: WaitList::default(),
+            list_waiters: WaitRegistry::default(),
+            stream_waiters: WaitRegistry::default(),
+            queues: DashMap::new(),
+            expires: Mutex::new(BTreeSet::new()),
+            expiring_keys: AtomicUsize::new(0),
+            max_failures: AtomicU64::new(DEFAULT_MAX_FAILURES),
+            expired_keys: AtomicU64::new(0),
+            expired_fields: AtomicU64::new(0),
+            expire_cycle_cap_reached: AtomicU64::new(0),
         }
     }
 
-    pub fn set(&self, key: String, value: Bytes, ttl: Option<Duration>) -> Result<()> {
+    /// Stores `value` if `options.condition` holds, atomically with respect to
+    /// other writers of the same key.
+    pub fn set(&self, key: String, value: Bytes, options: SetOptions) -> Result<SetOutcome> {
         let now = Instant::now();
-        let expiry = ttl.map(|duration| now + duration);
+        let entry = self.map.entry(key.clone());
+        
+        let (current, old) = match &entry {
+            Entry::Occupied(occupied) if !occupied.get().is_expired(now) => {
+                (Some(occupied.get()), Some((occupied.get().expiry, occupied.get().fifo_seq)))
+            },
+            Entry::Occupied(occupied) => (None, Some((occupied.get().expiry, occupied.get().fifo_seq))),
+            Entry::Vacant(_) => (None, None),
+        };
+        let old_expiry = old.and_then(|(expiry, _)| expiry);
+        let old_fields: Vec<(Bytes, Expiry)> = match &entry {
+            Entry::Occupied(occupied) => field_expiries(&occupied.get().value),
+            Entry::Vacant(_) => Vec::new(),
+        };
+        let previous = match current.map(|current| &current.value) {
+            Some(Value::String(data)) => Some(data.clone()),
+            Some(_) if options.get => return Err(StorageError::WrongType),
+            _ => None,
+        };
         
-        let entry = ValueEntry {
-            data: value,
-            expiry,
-            insertion_time: now,
+        let allowed = match options.condition {
+            None => true,
+            Some(SetCondition::NotExists) => current.is_none(),
+            Some(SetCondition::Exists) => current.is_some(),
         };
+        if !allowed {
+            return Ok(SetOutcome { written: false, previous });
+        }
         
-        // Store the value
-        self.map.insert(key.clone(), entry);
+        let expiry = match options.expiry {
+            Some(expiry) => Some(expiry),
+            None if options.keep_ttl => current.and_then(|current| current.expiry),
+            None => None,
+        };
         
-        // Add to FIFO queue
-        self.fifo_keys.insert(now, key);
+        if current.is_none() && old_expiry.is_some() {
+            self.expired_keys.fetch_add(1, Ordering::Relaxed);
+        }
         
-        Ok(())
+        // Store the value and move the key to the back of the FIFO, replacing
+        // any position it held before
+        let fifo_seq = self.next_fifo_seq.fetch_add(1, Ordering::Relaxed);
+        let stored = entry.insert(ValueEntry { value: Value::String(value), expiry, fifo_seq: Some(fifo_seq) });
+        self.reindex_expiry(&key, old_expiry, expiry);
+        for (field, expiry) in old_fields {
+            self.reindex(|| ExpiryTarget::HashField(key.clone(), field.clone()), Some(expiry), None);
+        }
+        
+        let mut fifo_keys = self.fifo_keys.lock().unwrap();
+        if let Some((_, Some(old_seq))) = old {
+            fifo_keys.remove(&old_seq);
+        }
+        fifo_keys.insert(fifo_seq, key);
+        drop(fifo_keys);
+        drop(stored);
+        self.fifo_waiters.wake_one();
+        
+        Ok(SetOutcome { written: true, previous })
     }
 
     pub fn get(&self, key: &str) -> Result<Bytes> {
-        let entry = self.map.get(key).ok_or(StorageError::KeyNotFound)?;
+        let entry = self.live_entry(key, Instant::now())?;
+        match &entry.value {
+            Value::String(data) => Ok(data.clone()),
+            _ => Err(StorageError::WrongType),
+        }
+    }
+
+    /// Adds `increment` to the integer stored at `key`, which counts as 0 if
+    /// there is no such key, and returns the result. The key keeps its TTL.
+    pub fn incr_by(&self, key: &str, increment: i64) -> Result<i64> {
+        self.modify_string(key, |data| {
+            let current = match data {
+                Some(data) => parse_integer(data).ok_or(StorageError::NotInteger)?,
+                None => 0,
+            };
+            let value = current.checked_add(increment).ok_or(StorageError::Overflow)?;
+            Ok((Bytes::from(value.to_string()), value))
+        })
+    }
+
+    /// Adds `increment` to the number stored at `key`, which counts as 0 if
+    /// there is no such key, and returns the result as stored. The key keeps
+    /// its TTL.
+    pub fn incr_by_float(&self, key: &str, increment: f64) -> Result<Bytes> {
+        self.modify_string(key, |data| {
+            let current = match data {
+                Some(data) => std::str::from_utf8(data).ok()
+                    .and_then(|data| data.parse::<f64>().ok())
+                    .filter(|value| !value.is_nan())
+                    .ok_or(StorageError::NotFloat)?,
+                None => 0.0,
+            };
+            let sum = add_floats(current, increment)?;
+            Ok((sum.clone(), sum))
+        })
+    }
+
+    /// Appends `suffix` to the string at `key`, creating it if there is no
+    /// such key, and returns the new length. The key keeps its TTL.
+    pub fn append(&self, key: &str, suffix: &[u8]) -> Result<usize> {
+        self.modify_string(key, |data| {
+            let current = data.map_or(&[][..], |data| data.as_ref());
+            let len = current.len().saturating_add(suffix.len());
+            if len > MAX_STRING_LEN {
+                return Err(StorageError::StringTooLong);
+            }
+            
+            let mut value = BytesMut::with_capacity(len);
+            value.extend_from_slice(current);
+            value.extend_from_slice(suffix);
+            Ok((value.freeze(), len))
+        })
+    }
+
+    /// Returns the length of the string at `key`, 0 if there is no such key.
+    pub fn string_len(&self, key: &str) -> Result<usize> {
+        Ok(self.read_string(key, |data| data.len())?.unwrap_or(0))
+    }
+
+    /// Returns the bytes of the string at `key` from `start` to `end`
+    /// inclusive. Negative offsets count from the end, and the range is
+    /// clamped to the string rather than wrapping.
+    pub fn get_range(&self, key: &str, start: i64, end: i64) -> Result<Bytes> {
+        let range = self.read_string(key, |data| {
+            let len = data.len() as i64;
+            if start < 0 && end < 0 && start > end {
+                return Bytes::new();
+            }
+            
+            let start = if start < 0 { start + len } else { start }.max(0);
+            let end = if end < 0 { end + len } else { end }.max(0).min(len - 1);
+            if start > end {
+                return Bytes::new();
+            }
+            data.slice(start as usize..=end as usize)
+        })?;
+        Ok(range.unwrap_or_default())
+    }
+
+    /// Overwrites the string at `key` with `data` from `offset` on, padding
+    /// with zero bytes if the string is shorter than `offset`, and returns the
+    /// new length. A missing key counts as an empty string, but is not
+    /// created if `data` is empty. The key keeps its TTL.
+    pub fn set_range(&self, key: &str, offset: usize, data: &[u8]) -> Result<usize> {
+        if data.is_empty() {
+            return self.string_len(key);
+        }
+        
+        self.modify_string(key, |current| {
+            let end = offset.checked_add(data.len())
+                .filter(|&end| end <= MAX_STRING_LEN)
+                .ok_or(StorageError::StringTooLong)?;
+            
+            let mut value = BytesMut::from(current.map_or(&[][..], |current| current.as_ref()));
+            if value.len() < end {
+                value.resize(end, 0);
+            }
+            value[offset..end].copy_from_slice(data);
+            let len = value.len();
+            Ok((value.freeze(), len))
+        })
+    }
+
+    /// Removes the string at `key` and returns it.
+    pub fn get_delete(&self, key: &str) -> Result<Bytes> {
+        let now = Instant::now();
+        let mut wrong_type = false;
+        
+        let removed = self.map.remove_if(key, |_, entry| {
+            if entry.is_expired(now) {
+                return true;
+            }
+            wrong_type = !matches!(entry.value, Value::String(_));
+            !wrong_type
+        });
         
-        // Check if key has expired
-        if let Some(expiry) = entry.expiry {
-            if Instant::now() > expiry {
-                // Remove expired key
-                self.map.remove(key);
+        let Some((_, entry)) = removed else {
+            return Err(if wrong_type { StorageError::WrongType } else { StorageError::KeyNotFound });
+        };
+        self.unlink(key, &entry);
+        
+        let expired = entry.is_expired(now);
+        match entry.value {
+            Value::String(data) if !expired => Ok(data),
+            _ => {
+                self.expired_keys.fetch_add(1, Ordering::Relaxed);
+                Err(StorageError::KeyExpired)
+            },
+        }
+    }
+
+    /// Returns the string at `key`, changing its TTL in the same step when
+    /// `expiry` is given: `Some(None)` removes the TTL, and a deadline that
+    /// has already passed deletes the key after reading it.
+    pub fn get_expire(&self, key: &str, expiry: Option<Option<Expiry>>) -> Result<Bytes> {
+        let now = Instant::now();
+        let mut value = None;
+        let mut wrong_type = false;
+        
+        // Reindex under the shard lock, so a concurrent TTL change cannot
+        // slip in between and leave the index out of step with the entry
+        let removed = self.map.remove_if_mut(key, |_, entry| {
+            if entry.is_expired(now) {
+                return true;
+            }
+            let Value::String(data) = &entry.value else {
+                wrong_type = true;
+                return false;
+            };
+            value = Some(data.clone());
+            
+            match expiry {
+                Some(Some(expiry)) if expiry.at <= now => return true,
+                Some(expiry) => self.reindex_expiry(key, std::mem::replace(&mut entry.expiry, expiry), expiry),
+                None => {},
+            }
+            false
+        });
+        
+        if let Some((_, entry)) = removed {
+            self.unlink(key, &entry);
+            if value.is_none() {
+                self.expired_keys.fetch_add(1, Ordering::Relaxed);
                 return Err(StorageError::KeyExpired);
             }
         }
+        if wrong_type {
+            return Err(StorageError::WrongType);
+        }
+        value.ok_or(StorageError::KeyNotFound)
+    }
+
+    /// Sets the TTL of an existing key if `condition` holds, returning whether
+    /// it was applied. A deadline that has already passed deletes the key.
+    pub fn expire(&self, key: &str, expiry: Expiry, condition: ExpireCondition) -> Result<bool> {
+        let now = Instant::now();
+        let mut applied = None;
+        
+        // Decide, apply and reindex under the shard lock so a deadline in the
+        // past deletes exactly the value it was checked against, and a racing
+        // TTL change cannot leave the index out of step with the entry
+        let removed = self.map.remove_if_mut(key, |_, entry| {
+            if entry.is_expired(now) {
+                return true;
+            }
+            
+            let allowed = condition.allows(entry.expiry, expiry);
+            applied = Some(allowed);
+            
+            if !allowed {
+                return false;
+            }
+            if expiry.at <= now {
+                return true;
+            }
+            self.reindex_expiry(key, entry.expiry.replace(expiry), Some(expiry));
+            false
+        });
+        
+        match (removed, applied) {
+            (Some((_, entry)), None) => {
+                self.expired_keys.fetch_add(1, Ordering::Relaxed);
+                self.unlink(key, &entry);
+            },
+            (Some((_, entry)), Some(_)) => self.unlink(key, &entry),
+            (None, _) => {},
+        }
+        
+        applied.ok_or(StorageError::KeyNotFound)
+    }
+
+    /// Returns the key's expiry, or `None` if it has no TTL.
+    pub fn expiry(&self, key: &str) -> Result<Option<Expiry>> {
+        let now = Instant::now();
+        Ok(self.live_entry_mut(key, now)?.expiry)
+    }
+
+    /// Removes the key's TTL, returning whether it had one.
+    pub fn persist(&self, key: &str) -> Result<bool> {
+        let now = Instant::now();
+        let mut entry = self.live_entry_mut(key, now)?;
+        
+        let old_expiry = entry.expiry.take();
+        self.reindex_expiry(key, old_expiry, None);
+        Ok(old_expiry.is_some())
+    }
+
+    /// Returns a read guard for `key`, lazily deleting it if it has expired.
+    fn live_entry(&self, key: &str, now: Instant) -> Result<Ref<'_, String, ValueEntry>> {
+        let entry = self.map.get(key).ok_or(StorageError::KeyNotFound)?;
+        if entry.is_expired(now) {
+            // Release the shard lock before removing the expired key
+            drop(entry);
+            self.remove_expired(key);
+            return Err(StorageError::KeyExpired);
+        }
+        Ok(entry)
+    }
+
+    /// Returns a write guard for `key`, lazily deleting it if it has expired.
+    fn live_entry_mut(&self, key: &str, now: Instant) -> Result<RefMut<'_, String, ValueEntry>> {
+        let entry = self.map.get_mut(key).ok_or(StorageError::KeyNotFound)?;
+        if entry.is_expired(now) {
+            drop(entry);
+            self.remove_expired(key);
+            return Err(StorageError::KeyExpired);
+        }
+        Ok(entry)
+    }
+
+    /// Removes `key` if it is (still) expired. Re-checking under the shard lock
+    /// keeps a concurrent SET of a fresh value from being deleted.
+    fn remove_expired(&self, key: &str) {
+        let now = Instant::now();
+        if let Some((_, entry)) = self.map.remove_if(key, |_, entry| entry.is_expired(now)) {
+            self.expired_keys.fetch_add(1, Ordering::Relaxed);
+            self.unlink(key, &entry);
+        }
+    }
+
+    /// Drops the side structures that refer to an entry removed from the map.
+    fn unlink(&self, key: &str, entry: &ValueEntry) {
+        self.reindex_expiry(key, entry.expiry, None);
+        for (field, expiry) in field_expiries(&entry.value) {
+            self.reindex(|| ExpiryTarget::HashField(key.to_string(), field.clone()), Some(expiry), None);
+        }
+        if let Some(seq) = entry.fifo_seq {
+            self.fifo_keys.lock().unwrap().remove(&seq);
+        }
+    }
+
+    /// Moves `key` in the expiry index from its `old` deadline to its `new` one.
+    fn reindex_expiry(&self, key: &str, old: Option<Expiry>, new: Option<Expiry>) {
+        self.reindex(|| ExpiryTarget::Key(key.to_string()), old, new);
+    }
+
+    fn reindex(&self, target: impl Fn() -> ExpiryTarget, old: Option<Expiry>, new: Option<Expiry>) {
+        let old = old.map(|expiry| expiry.at);
+        let new = new.map(|expiry| expiry.at);
+        if old == new {
+            return;
+        }
         
-        Ok(entry.data.clone())
+        let mut expires = self.expires.lock().unwrap();
+        if let Some(at) = old {
+            let target = target();
+            let is_key = target.is_key();
+            if expires.remove(&(at, target)) && is_key {
+                self.expiring_keys.fetch_sub(1, Ordering::Relaxed);
+            }
+        }
+        if let Some(at) = new {
+            let target = target();
+            let is_key = target.is_key();
+            if expires.insert((at, target)) && is_key {
+                self.expiring_keys.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Registers a client blocked on the FIFO; it is woken by a later SET.
+    pub fn wait_for_fifo(&self) -> WaitGuard<'_> {
+        self.fifo_waiters.register()
     }
 
+    /// Removes and returns the key that was SET the longest ago, skipping
+    /// (and collecting) any that have expired in the meantime.
     pub fn pop_fifo(&self) -> Result<(String, Bytes)> {
-        // Find the oldest key
-        let oldest = self.fifo_keys.iter()
-            .min_by_key(|entry| *entry.key())
-            .ok_or(StorageError::KeyNotFound)?;
-        
-        let time = *oldest.key();
-        let key = oldest.value().clone();
-        
-        // Remove from FIFO list
-        self.fifo_keys.remove(&time);
-        
-        // Get and remove the value
-        match self.map.remove(&key) {
-            Some((k, v)) => {
-                // Check if key has expired
-                if let Some(expiry) = v.expiry {
-                    if Instant::now() > expiry {
-                        return Err(StorageError::KeyExpired);
-                    }
-                }
-                Ok((k, v.data))
-            },
-            None => Err(StorageError::KeyNotFound),
+        loop {
+            let (seq, key) = self.fifo_keys.lock().unwrap()
+                .pop_first()
+                .ok_or(StorageError::KeyNotFound)?;
+            
+            // A concurrent SET may have re-queued the key under a newer sequence
+            // number, or a write may have replaced the string with another
+            // type, in which case this position is stale and the key stays
+            let Some((key, entry)) = self.map.remove_if(&key, |_, entry| {
+                entry.fifo_seq == Some(seq) && matches!(entry.value, Value::String(_))
+            }) else {
+                continue;
+            };
+            self.reindex_expiry(&key, entry.expiry, None);
+            
+            if entry.is_expired(Instant::now()) {
+                self.expired_keys.fetch_add(1, Ordering::Relaxed);
+                continue;
+            }
+            let Value::String(data) = entry.value else {
+                continue;
+            };
+            return Ok((key, data));
         }
     }
 
     pub fn delete(&self, key: &str) -> Result<()> {
-        self.map.remove(key).ok_or(StorageError::KeyNotFound)?;
-        // TODO: Remove from fifo_keys
+        let (_, entry) = self.map.remove(key).ok_or(StorageError::KeyNotFound)?;
+        self.unlink(key, &entry);
         Ok(())
     }
     
-    pub fn cleanup_expired(&self) -> usize {
-        let now = Instant::now();
+    /// Active expiry: removes keys and queue items whose deadline has passed,
+    /// earliest first, for at most `budget`. The work done is proportional to
+    /// what is due rather than to the size of the keyspace. Returns the number
+    /// of keys removed.
+    pub fn cleanup_expired(&self, budget: Duration) -> usize {
+        let started = Instant::now();
         let mut removed = 0;
         
-        // Find expired keys
-        let expired_keys: Vec<String> = self.map.iter()
-            .filter_map(|entry| {
-                if let Some(expiry) = entry.expiry {
-                    if now > expiry {
-                        Some(entry.key().clone())
-                    } else {
-                        None
+        loop {
+            let now = Instant::now();
+            
+            // Take a batch of due entries off the index, releasing its lock
+            // before touching the maps to respect the shard-then-index order
+            let due: Vec<(Instant, ExpiryTarget)> = {
+                let mut expires = self.expires.lock().unwrap();
+                let mut due = Vec::new();
+                while due.len() < EXPIRE_BATCH {
+                    match expires.first() {
+                        Some((at, _)) if *at <= now => due.extend(expires.pop_first()),
+                        _ => break,
                     }
-                } else {
-                    None
                 }
-            })
-            .collect();
-        
-        // Remove expired keys
-        for key in expired_keys {
-            self.map.remove(&key);
-            removed += 1;
+                let keys = due.iter().filter(|(_, target)| target.is_key()).count();
+                self.expiring_keys.fetch_sub(keys, Ordering::Relaxed);
+                due
+            };
+            
+            let batch_full = due.len() == EXPIRE_BATCH;
+            for (at, target) in due {
+                // Skip entries that were given a new deadline since being indexed
+                let still_due = |expiry: Option<Expiry>| expiry.is_some_and(|expiry| expiry.at == at);
+                
+                match target {
+                    ExpiryTarget::Key(key) => {
+                        let due_entry = |_: &String, entry: &ValueEntry| still_due(entry.expiry);
+                        if let Some((_, entry)) = self.map.remove_if(&key, due_entry) {
+                            self.unlink(&key, &entry);
+                            removed += 1;
+                        }
+                    },
+                    ExpiryTarget::HashField(key, field) => {
+                        let mut expired = false;
+                        let emptied = self.map.remove_if_mut(&key, |_, entry| {
+                            let Value::Hash(hash) = &mut entry.value else {
+                                return false;
+                            };
+                            expired = still_due(hash.expiry(&field)) && hash.remove(&field).is_some();
+                            expired && hash.is_empty()
+                        });
+                        if let Some((_, entry)) = emptied {
+                            self.unlink(&key, &entry);
+                        }
+                        if expired {
+                            self.expired_fields.fetch_add(1, Ordering::Relaxed);
+                        }
+                    },
+                    ExpiryTarget::QueueItem(name, seq) => {
+                        if let Some(mut queue) = self.queues.get_mut(&name)
+                            && queue.get(seq).is_some_and(|item| still_due(item.expiry)) {
+                            queue.remove(seq);
+                            drop(queue);
+                            self.remove_queue_if_empty(&name);
+                        }
+                    },
+                }
+            }
+            
+            if !batch_full {
+                break;
+            }
+            if started.elapsed() >= budget {
+                self.expire_cycle_cap_reached.fetch_add(1, Ordering::Relaxed);
+                break;
+            }
         }
         
+        self.expired_keys.fetch_add(removed as u64, Ordering::Relaxed);
         removed
     }
 
-    pub fn keys(&self, pattern: &str) -> Vec<String> {
+    /// Whether any key is past its deadline but not yet removed.
+    pub fn has_due_expirations(&self) -> bool {
         let now = Instant::now();
-        let mut keys = Vec::new();
+        self.expires.lock().unwrap().first().is_some_and(|(at, _)| *at <= now)
+    }
+
+    pub fn stats(&self) -> StorageStats {
+        StorageStats {
+            keys: self.map.len(),
+            expires: self.expiring_keys.load(Ordering::Relaxed),
+            queues: self.queues.len(),
+            expired_keys: self.expired_keys.load(Ordering::Relaxed),
+            expired_fields: self.expired_fields.load(Ordering::Relaxed),
+            expire_cycle_cap_reached: self.expire_cycle_cap_reached.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Appends `value` to the named queue, behind the items of the same or a
+    /// higher priority, creating the queue if needed. Returns the number of
+    /// visible items.
+    pub fn queue_push(&self, name: &str, value: Bytes, options: PushOptions) -> usize {
+        let now = Instant::now();
+        let mut queue = self.queues.entry(name.to_string()).or_default();
+        self.remove_expired_items(name, &mut queue, now);
+        queue.promote_due(now);
         
-        // Simple pattern matching (only supports * wildcard at the end)
-        let is_wildcard = pattern.ends_with('*');
-        let prefix = if is_wildcard {
-            pattern[..pattern.len() - 1].to_string()
-        } else {
-            pattern.to_string()
+        let expiry = options.expiry;
+        let item = QueueItem::new(value, expiry).with_priority(options.priority);
+        let seq = match options.visible_at {
+            Some(at) if at.at > now => queue.schedule(item, at),
+            _ => queue.push(item),
+        };
+        self.reindex(|| ExpiryTarget::QueueItem(name.to_string(), seq), None, expiry);
+        queue.len()
+    }
+
+    /// Removes up to `count` live items from the front of the named queue.
+    pub fn queue_pop(&self, name: &str, count: usize) -> Vec<Bytes> {
+        let now = Instant::now();
+        let Some(mut queue) = self.live_queue(name, now) else {
+            return Vec::new();
         };
         
-        for entry in self.map.iter() {
-            let key = entry.key();
+        let mut popped = Vec::new();
+        while popped.len() < count {
+            let Some((seq, item)) = queue.pop_front() else {
+                break;
+            };
+            self.reindex(|| ExpiryTarget::QueueItem(name.to_string(), seq), item.expiry, None);
             
-            // Skip expired keys
-            if let Some(expiry) = entry.value().expiry {
-                if now > expiry {
-                    continue;
-                }
+            if !item.is_expired(now) {
+                popped.push(item.data);
+            }
+        }
+        
+        drop(queue);
+        self.remove_queue_if_empty(name);
+        popped
+    }
+
+    /// Returns up to `count` live items from the front of the named queue
+    /// without removing them.
+    pub fn queue_peek(&self, name: &str, count: usize) -> Vec<Bytes> {
+        let now = Instant::now();
+        self.live_queue(name, now)
+            .map(|queue| queue.iter_live(now).take(count).map(|item| item.data.clone()).collect())
+            .unwrap_or_default()
+    }
+
+    pub fn queue_len(&self, name: &str) -> usize {
+        let now = Instant::now();
+        self.live_queue(name, now).map_or(0, |queue| queue.len())
+    }
+
+    /// Number of visible items of the named queue at each priority, highest
+    /// first.
+    pub fn queue_depths(&self, name: &str) -> Vec<(i64, usize)> {
+        let now = Instant::now();
+        self.live_queue(name, now).map(|queue| queue.depths()).unwrap_or_default()
+    }
+
+    /// Hides the first live item of the named queue for `visibility` and
+    /// hands it out. Unless acknowledged in time, it is delivered again.
+    pub fn queue_reserve(&self, name: &str, visibility: Duration) -> Option<Delivery> {
+        let now = Instant::now();
+        let mut queue = self.live_queue(name, now)?;
+        
+        loop {
+            let seq = queue.front()?;
+            if queue.get(seq).is_some_and(|item| item.is_expired(now)) {
+                let item = queue.remove(seq).unwrap();
+                self.reindex(|| ExpiryTarget::QueueItem(name.to_string(), seq), item.expiry, None);
+                continue;
+            }
+            
+            let (id, item) = queue.reserve(seq, now + visibility)?;
+            return Some(Delivery { id, data: item.data.clone(), deliveries: item.deliveries });
+        }
+    }
+
+    /// Deletes the items held by the given deliveries, returning how many were
+    /// still held. Deliveries that timed out no longer hold their item.
+    pub fn queue_ack(&self, name: &str, deliveries: &[u64]) -> usize {
+        let Some(mut queue) = self.live_queue(name, Instant::now()) else {
+            return 0;
+        };
+        
+        let mut acked = 0;
+        for &delivery in deliveries {
+            if let Some(seq) = queue.reserved_seq(delivery)
+                && let Some(item) = queue.remove(seq) {
+                self.reindex(|| ExpiryTarget::QueueItem(name.to_string(), seq), item.expiry, None);
+                acked += 1;
+            }
+        }
+        
+        drop(queue);
+        self.remove_queue_if_empty(name);
+        acked
+    }
+
+    /// Makes the items held by the given deliveries ready again, returning how
+    /// many were still held.
+    pub fn queue_nack(&self, name: &str, deliveries: &[u64]) -> usize {
+        let Some(mut queue) = self.live_queue(name, Instant::now()) else {
+            return 0;
+        };
+        deliveries.iter().filter(|&&delivery| queue.release(delivery)).count()
+    }
+
+    /// Sets how many times a queue item may be rejected before it is moved to
+    /// the dead-letter queue.
+    pub fn set_max_failures(&self, max_failures: u64) {
+        self.max_failures.store(max_failures.max(1), Ordering::Relaxed);
+    }
+
+    /// Records a failure for the items held by the given deliveries, requeueing
+    /// them or dead-lettering those that have failed too often. Returns how
+    /// many deliveries still held their item.
+    pub fn queue_reject(&self, name: &str, deliveries: &[u64]) -> usize {
+        let max_failures = self.max_failures.load(Ordering::Relaxed);
+        let Some(mut queue) = self.live_queue(name, Instant::now()) else {
+            return 0;
+        };
+        deliveries.iter().filter(|&&delivery| queue.reject(delivery, max_failures).is_some()).count()
+    }
+
+    /// Returns up to `count` dead-lettered items of the named queue, oldest
+    /// first, with their failure counts.
+    pub fn queue_dead(&self, name: &str, count: usize) -> Vec<(Bytes, u64)> {
+        let now = Instant::now();
+        self.queues.get(name)
+            .map(|queue| queue.iter_dead(now).take(count).map(|item| (item.data.clone(), item.failures)).collect())
+            .unwrap_or_default()
+    }
+
+    pub fn queue_dead_len(&self, name: &str) -> usize {
+        let now = Instant::now();
+        self.queues.get(name).map_or(0, |queue| queue.iter_dead(now).count())
+    }
+
+    /// Moves up to `count` dead-lettered items back into the named queue,
+    /// returning how many were moved.
+    pub fn queue_replay(&self, name: &str, count: usize) -> usize {
+        let now = Instant::now();
+        let Some(mut queue) = self.queues.get_mut(name) else {
+            return 0;
+        };
+        
+        let mut replayed = 0;
+        while replayed < count {
+            let Some(seq) = queue.first_dead() else {
+                break;
+            };
+            if queue.get(seq).is_some_and(|item| item.is_expired(now)) {
+                let item = queue.remove(seq).unwrap();
+                self.reindex(|| ExpiryTarget::QueueItem(name.to_string(), seq), item.expiry, None);
+            } else {
+                queue.revive(seq);
+                replayed += 1;
+            }
+        }
+        
+        drop(queue);
+        self.remove_queue_if_empty(name);
+        replayed
+    }
+
+    /// Deletes every dead-lettered item of the named queue, returning how many
+    /// live ones there were.
+    pub fn queue_purge_dead(&self, name: &str) -> usize {
+        let now = Instant::now();
+        let Some(mut queue) = self.queues.get_mut(name) else {
+            return 0;
+        };
+        
+        let mut purged = 0;
+        while let Some(seq) = queue.first_dead() {
+            let item = queue.remove(seq).unwrap();
+            self.reindex(|| ExpiryTarget::QueueItem(name.to_string(), seq), item.expiry, None);
+            if !item.is_expired(now) {
+                purged += 1;
+            }
+        }
+        
+        drop(queue);
+        self.remove_queue_if_empty(name);
+        purged
+    }
+
+    /// Returns up to `count` items of the named queue that are scheduled to
+    /// become visible, soonest first, with their ids and due times.
+    pub fn queue_scheduled(&self, name: &str, count: usize) -> Vec<(u64, Bytes, Expiry)> {
+        let now = Instant::now();
+        self.live_queue(name, now)
+            .map(|queue| {
+                queue.iter_scheduled(now)
+                    .take(count)
+                    .filter_map(|(seq, item)| Some((seq, item.data.clone(), item.visible_at?)))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Deletes the given scheduled items before they become visible, returning
+    /// how many were still scheduled.
+    pub fn queue_cancel(&self, name: &str, ids: &[u64]) -> usize {
+        let Some(mut queue) = self.live_queue(name, Instant::now()) else {
+            return 0;
+        };
+        
+        let mut cancelled = 0;
+        for &seq in ids {
+            if let Some(item) = queue.cancel(seq) {
+                self.reindex(|| ExpiryTarget::QueueItem(name.to_string(), seq), item.expiry, None);
+                cancelled += 1;
+            }
+        }
+        
+        drop(queue);
+        self.remove_queue_if_empty(name);
+        cancelled
+    }
+
+    /// The named queue, with expired items dropped, any scheduled item that
+    /// is due made visible and any reservation past its visibility deadline
+    /// released first. `None` if that leaves the queue empty.
+    fn live_queue(&self, name: &str, now: Instant) -> Option<RefMut<'_, String, Queue>> {
+        let mut queue = self.queues.get_mut(name)?;
+        self.remove_expired_items(name, &mut queue, now);
+        if queue.is_empty() {
+            drop(queue);
+            self.remove_queue_if_empty(name);
+            return None;
+        }
+        
+        queue.promote_due(now);
+        queue.release_overdue(now);
+        Some(queue)
+    }
+
+    /// Drops the items of the named queue whose TTL has passed, ahead of the
+    /// active expiry cycle.
+    fn remove_expired_items(&self, name: &str, queue: &mut Queue, now: Instant) {
+        for (seq, item) in queue.remove_expired(now) {
+            self.reindex(|| ExpiryTarget::QueueItem(name.to_string(), seq), item.expiry, None);
+        }
+    }
+
+    /// Drops a queue once its last item is gone, like Redis does for lists.
+    fn remove_queue_if_empty(&self, name: &str) {
+        self.queues.remove_if(name, |_, queue| queue.is_empty());
+    }
+
+    /// Pushes `values` one by one onto `end` of the list at `key`, creating
+    /// it if needed, and returns the new length.
+    pub fn list_push(&self, key: &str, end: ListEnd, values: Vec<Bytes>) -> Result<usize> {
+        let mut entry = self.entry_or_insert(key, || Value::List(VecDeque::new()))?;
+        let Value::List(list) = &mut entry.value else {
+            return Err(StorageError::WrongType);
+        };
+        
+        let pushed = values.len();
+        for value in values {
+            list::push(list, end, value);
+        }
+        let len = list.len();
+        drop(entry);
+        
+        // Each new element can serve one blocked client
+        for _ in 0..pushed {
+            self.list_waiters.wake_one(key);
+        }
+        Ok(len)
+    }
+
+    /// Registers a client blocked on the lists at `keys`; it is woken by a
+    /// later push onto any of them.
+    pub fn wait_for_lists(&self, keys: &[String]) -> WaitGuard<'_> {
+        self.list_waiters.register(keys)
+    }
+
+    /// Pops up to `count` elements from `end` of the list at `key`, or returns
+    /// `None` if there is no such key.
+    pub fn list_pop(&self, key: &str, end: ListEnd, count: usize) -> Result<Option<Vec<Bytes>>> {
+        self.update_list(key, |list| (0..count).map_while(|_| list::pop(list, end)).collect())
+    }
+
+    /// Returns the elements between `start` and `stop`, inclusive.
+    pub fn list_range(&self, key: &str, start: i64, stop: i64) -> Result<Vec<Bytes>> {
+        let range = self.read_list(key, |list| match list::range(list.len(), start, stop) {
+            Some((start, stop)) => list.range(start..=stop).cloned().collect(),
+            None => Vec::new(),
+        })?;
+        Ok(range.unwrap_or_default())
+    }
+
+    pub fn list_len(&self, key: &str) -> Result<usize> {
+        Ok(self.read_list(key, |list| list.len())?.unwrap_or(0))
+    }
+
+    pub fn list_index(&self, key: &str, index: i64) -> Result<Option<Bytes>> {
+        let element = self.read_list(key, |list| list::index(list.len(), index).map(|index| list[index].clone()))?;
+        Ok(element.flatten())
+    }
+
+    pub fn list_set(&self, key: &str, index: i64, value: Bytes) -> Result<()> {
+        let outcome = self.update_list(key, |list| {
+            let index = list::index(list.len(), index).ok_or(StorageError::IndexOutOfRange)?;
+            list[index] = value;
+            Ok(())
+        })?;
+        outcome.ok_or(StorageError::KeyNotFound)?
+    }
+
+    /// Removes elements equal to `value` as LREM does, returning how many.
+    pub fn list_remove(&self, key: &str, count: i64, value: &[u8]) -> Result<usize> {
+        Ok(self.update_list(key, |list| list::remove(list, count, value))?.unwrap_or(0))
+    }
+
+    /// Keeps only the elements between `start` and `stop`, inclusive.
+    pub fn list_trim(&self, key: &str, start: i64, stop: i64) -> Result<()> {
+        self.update_list(key, |list| match list::range(list.len(), start, stop) {
+            Some((start, stop)) => {
+                list.truncate(stop + 1);
+                list.drain(..start);
+            },
+            None => list.clear(),
+        })?;
+        Ok(())
+    }
+
+    /// Inserts `value` next to the first element equal to `pivot`. Returns the
+    /// new length, -1 if there is no such element, or 0 if there is no list.
+    pub fn list_insert(&self, key: &str, before: bool, pivot: &[u8], value: Bytes) -> Result<i64> {
+        let inserted = self.update_list(key, |list| {
+            let Some(position) = list.iter().position(|element| element == pivot) else {
+                return -1;
+            };
+            list.insert(if before { position } else { position + 1 }, value);
+            list.len() as i64
+        })?;
+        Ok(inserted.unwrap_or(0))
+    }
+
+    /// Pops an element from `from` of `source` and pushes it onto `to` of
+    /// `destination`, returning it, or `None` if `source` does not exist.
+    /// Both keys change in one atomic step, and nothing changes if either
+    /// holds another type.
+    pub fn list_move(&self, source: &str, destination: &str, from: ListEnd, to: ListEnd) -> Result<Option<Bytes>> {
+        if source == destination {
+            let moved = self.update_list(source, |list| {
+                let value = list::pop(list, from)?;
+                list::push(list, to, value.clone());
+                Some(value)
+            })?;
+            return Ok(moved.flatten());
+        }
+        
+        // Hold both keys' shards for the whole move so no client sees the
+        // element in neither list. Shards are locked lowest first, so moves in
+        // opposite directions cannot deadlock; both keys may share a shard.
+        let now = Instant::now();
+        let source_shard = self.map.determine_map(source);
+        let destination_shard = self.map.determine_map(destination);
+        let low = source_shard.min(destination_shard);
+        let high = source_shard.max(destination_shard);
+        
+        let mut shards = vec![self.map.shards()[low].write()];
+        if high != low {
+            shards.push(self.map.shards()[high].write());
+        }
+        let slot = |shard: usize| usize::from(shard != low);
+        let (source_slot, destination_slot) = (slot(source_shard), slot(destination_shard));
+        
+        // Expired keys count as missing
+        for (key, slot) in [(source, source_slot), (destination, destination_slot)] {
+            if shards[slot].get(key).is_some_and(|entry| entry.get().is_expired(now)) {
+                let expired = shards[slot].remove(key).expect("entry exists").into_inner();
+                self.expired_keys.fetch_add(1, Ordering::Relaxed);
+                self.unlink(key, &expired);
+            }
+        }
+        
+        let destination_is_list = shards[destination_slot].get(destination)
+            .is_none_or(|entry| matches!(entry.get().value, Value::List(_)));
+        let value = match shards[source_slot].get_mut(source).map(|entry| &mut entry.get_mut().value) {
+            None => return Ok(None),
+            Some(Value::List(list)) if destination_is_list => list::pop(list, from),
+            Some(_) => return Err(StorageError::WrongType),
+        };
+        let Some(value) = value else {
+            return Ok(None);
+        };
+        
+        if shards[source_slot].get(source).is_some_and(|entry| entry.get().value.is_empty()) {
+            let emptied = shards[source_slot].remove(source).expect("entry exists").into_inner();
+            self.unlink(source, &emptied);
+        }
+        match shards[destination_slot].get_mut(destination).map(|entry| &mut entry.get_mut().value) {
+            Some(Value::List(list)) => list::push(list, to, value.clone()),
+            _ => {
+                let mut list = VecDeque::new();
+                list::push(&mut list, to, value.clone());
+                let entry = SharedValue::new(ValueEntry::new(Value::List(list)));
+                shards[destination_slot].insert(destination.to_string(), entry);
+            },
+        }
+        drop(shards);
+        
+        self.list_waiters.wake_one(destination);
+        Ok(Some(value))
+    }
+
+    /// Sets the given fields of the hash at `key`, creating it if needed, and
+    /// returns how many of them are new. Fields that had a TTL lose it.
+    pub fn hash_set(&self, key: &str, pairs: Vec<(Bytes, Bytes)>) -> Result<usize> {
+        self.write_hash(key, |hash| {
+            let mut added = 0;
+            for (field, value) in pairs {
+                self.set_field_expiry(key, hash, &field, None);
+                if hash.insert(field, value) {
+                    added += 1;
+                }
+            }
+            Ok(added)
+        })
+    }
+
+    pub fn hash_get(&self, key: &str, field: &[u8]) -> Result<Option<Bytes>> {
+        Ok(self.read_hash(key, |hash| hash.get(field).cloned())?.flatten())
+    }
+
+    /// Returns the value of each of `fields`, `None` for those not set.
+    pub fn hash_get_many(&self, key: &str, fields: &[Bytes]) -> Result<Vec<Option<Bytes>>> {
+        let values = self.read_hash(key, |hash| fields.iter().map(|field| hash.get(field).cloned()).collect())?;
+        Ok(values.unwrap_or_else(|| vec![None; fields.len()]))
+    }
+
+    /// Removes the given fields, returning how many were set.
+    pub fn hash_delete(&self, key: &str, fields: &[Bytes]) -> Result<usize> {
+        let removed = self.update_hash(key, |hash| {
+            fields.iter()
+                .filter(|field| {
+                    self.set_field_expiry(key, hash, field, None);
+                    hash.remove(field).is_some()
+                })
+                .count()
+        })?;
+        Ok(removed.unwrap_or(0))
+    }
+
+    pub fn hash_get_all(&self, key: &str) -> Result<Vec<(Bytes, Bytes)>> {
+        let pairs = self.read_hash(key, |hash| hash.iter().map(|(field, value)| (field.clone(), value.clone())).collect())?;
+        Ok(pairs.unwrap_or_default())
+    }
+
+    /// Adds `increment` to the integer stored in `field`, which counts as 0 if
+    /// not set, and returns the result.
+    pub fn hash_incr_by(&self, key: &str, field: Bytes, increment: i64) -> Result<i64> {
+        self.write_hash(key, |hash| {
+            let current = match hash.get(&field) {
+                Some(value) => parse_integer(value).ok_or(StorageError::HashValueNotInteger)?,
+                None => 0,
+            };
+            let value = current.checked_add(increment).ok_or(StorageError::Overflow)?;
+            hash.insert(field, Bytes::from(value.to_string()));
+            Ok(value)
+        })
+    }
+
+    /// Adds `increment` to the number stored in `field`, which counts as 0 if
+    /// not set, and returns the result as stored.
+    pub fn hash_incr_by_float(&self, key: &str, field: Bytes, increment: f64) -> Result<Bytes> {
+        self.write_hash(key, |hash| {
+            let current = match hash.get(&field) {
+                Some(value) => std::str::from_utf8(value).ok()
+                    .and_then(|value| value.parse::<f64>().ok())
+                    .filter(|value| !value.is_nan())
+                    .ok_or(StorageError::HashValueNotFloat)?,
+                None => 0.0,
+            };
+            let sum = add_floats(current, increment)?;
+            hash.insert(field, sum.clone());
+            Ok(sum)
+        })
+    }
+
+    pub fn hash_exists(&self, key: &str, field: &[u8]) -> Result<bool> {
+        Ok(self.read_hash(key, |hash| hash.get(field).is_some())?.unwrap_or(false))
+    }
+
+    pub fn hash_len(&self, key: &str) -> Result<usize> {
+        Ok(self.read_hash(key, |hash| hash.len())?.unwrap_or(0))
+    }
+
+    pub fn hash_keys(&self, key: &str) -> Result<Vec<Bytes>> {
+        Ok(self.read_hash(key, |hash| hash.iter().map(|(field, _)| field.clone()).collect())?.unwrap_or_default())
+    }
+
+    pub fn hash_values(&self, key: &str) -> Result<Vec<Bytes>> {
+        Ok(self.read_hash(key, |hash| hash.iter().map(|(_, value)| value.clone()).collect())?.unwrap_or_default())
+    }
+
+    /// One step of an HSCAN: visits about `count` fields from `cursor` and
+    /// returns those matching `pattern`, with the cursor to continue from.
+    pub fn hash_scan(&self, key: &str, cursor: u64, pattern: Option<&[u8]>, count: usize) -> Result<(u64, Vec<(Bytes, Bytes)>)> {
+        let step = self.read_hash(key, |hash| {
+            let (next, pairs) = hash.scan(cursor, count);
+            let pairs = pairs.into_iter()
+                .filter(|(field, _)| pattern.is_none_or(|pattern| glob::matches(pattern, field)))
+                .map(|(field, value)| (field.clone(), value.clone()))
+                .collect();
+            (next, pairs)
+        })?;
+        Ok(step.unwrap_or_default())
+    }
+
+    /// Sets the TTL of each of `fields` if `condition` holds for it. A
+    /// deadline that has already passed deletes the field.
+    pub fn hash_expire(
+        &self,
+        key: &str,
+        fields: &[Bytes],
+        expiry: Expiry,
+        condition: ExpireCondition,
+    ) -> Result<Vec<FieldExpire>> {
+        let now = Instant::now();
+        let outcomes = self.update_hash(key, |hash| {
+            fields.iter()
+                .map(|field| {
+                    if hash.get(field).is_none() {
+                        return FieldExpire::NoSuchField;
+                    }
+                    if !condition.allows(hash.expiry(field), expiry) {
+                        return FieldExpire::NotApplied;
+                    }
+                    if expiry.at <= now {
+                        self.set_field_expiry(key, hash, field, None);
+                        hash.remove(field);
+                        return FieldExpire::Deleted;
+                    }
+                    self.set_field_expiry(key, hash, field, Some(expiry));
+                    FieldExpire::Applied
+                })
+                .collect()
+        })?;
+        Ok(outcomes.unwrap_or_else(|| vec![FieldExpire::NoSuchField; fields.len()]))
+    }
+
+    /// Returns the TTL of each of `fields`: `None` for fields not set, and
+    /// `Some(None)` for fields without a TTL.
+    pub fn hash_field_expiry(&self, key: &str, fields: &[Bytes]) -> Result<Vec<Option<Option<Expiry>>>> {
+        let expiries = self.read_hash(key, |hash| {
+            fields.iter().map(|field| hash.get(field).map(|_| hash.expiry(field))).collect()
+        })?;
+        Ok(expiries.unwrap_or_else(|| vec![None; fields.len()]))
+    }
+
+    /// Removes the TTL of each of `fields`: `None` for fields not set, and
+    /// otherwise whether the field had a TTL.
+    pub fn hash_persist(&self, key: &str, fields: &[Bytes]) -> Result<Vec<Option<bool>>> {
+        let persisted = self.update_hash(key, |hash| {
+            fields.iter()
+                .map(|field| {
+                    hash.get(field)?;
+                    Some(self.set_field_expiry(key, hash, field, None).is_some())
+                })
+                .collect()
+        })?;
+        Ok(persisted.unwrap_or_else(|| vec![None; fields.len()]))
+    }
+
+    /// Adds `members` to the set at `key`, creating it if needed, and returns
+    /// how many of them are new.
+    pub fn set_add(&self, key: &str, members: Vec<Bytes>) -> Result<usize> {
+        let mut entry = self.entry_or_insert(key, || Value::Set(Set::default()))?;
+        let Value::Set(set) = &mut entry.value else {
+            return Err(StorageError::WrongType);
+        };
+        Ok(members.into_iter().filter(|member| set.insert(member.clone())).count())
+    }
+
+    /// Removes `members` from the set at `key`, returning how many were there.
+    pub fn set_remove(&self, key: &str, members: &[Bytes]) -> Result<usize> {
+        let removed = self.update_set(key, |set| members.iter().filter(|member| set.remove(member)).count())?;
+        Ok(removed.unwrap_or(0))
+    }
+
+    pub fn set_members(&self, key: &str) -> Result<Vec<Bytes>> {
+        Ok(self.read_set(key, |set| set.iter().collect())?.unwrap_or_default())
+    }
+
+    pub fn set_contains(&self, key: &str, member: &[u8]) -> Result<bool> {
+        Ok(self.read_set(key, |set| set.contains(member))?.unwrap_or(false))
+    }
+
+    /// Whether each of `members` is in the set at `key`.
+    pub fn set_contains_many(&self, key: &str, members: &[Bytes]) -> Result<Vec<bool>> {
+        let found = self.read_set(key, |set| members.iter().map(|member| set.contains(member)).collect())?;
+        Ok(found.unwrap_or_else(|| vec![false; members.len()]))
+    }
+
+    pub fn set_len(&self, key: &str) -> Result<usize> {
+        Ok(self.read_set(key, |set| set.len())?.unwrap_or(0))
+    }
+
+    /// Removes up to `count` random members from the set at `key` and
+    /// returns them.
+    pub fn set_pop(&self, key: &str, count: usize) -> Result<Vec<Bytes>> {
+        Ok(self.update_set(key, |set| set.pop(count))?.unwrap_or_default())
+    }
+
+    /// Returns random members of the set at `key` as SRANDMEMBER does: up to
+    /// `count` distinct ones if it is positive, or exactly `-count` possibly
+    /// repeated ones if it is negative.
+    pub fn set_random(&self, key: &str, count: i64) -> Result<Vec<Bytes>> {
+        let members = self.read_set(key, |set| {
+            if count >= 0 {
+                set.random_distinct(count as usize)
+            } else {
+                set.random_repeated(count.unsigned_abs() as usize)
+            }
+        })?;
+        Ok(members.unwrap_or_default())
+    }
+
+    /// Combines the sets at `keys` as `op` says, missing keys counting as
+    /// empty sets.
+    pub fn set_combine(&self, keys: &[String], op: SetOp) -> Result<Vec<Bytes>> {
+        Ok(Set::combine(&self.snapshot_sets(keys)?, op).iter().collect())
+    }
+
+    /// Like `set_combine`, but stores the result at `destination`, replacing
+    /// whatever it held, and returns its size.
+    pub fn set_combine_store(&self, destination: &str, keys: &[String], op: SetOp) -> Result<usize> {
+        let result = Set::combine(&self.snapshot_sets(keys)?, op);
+        let len = result.len();
+        self.store(destination, Value::Set(result));
+        Ok(len)
+    }
+
+    /// Size of the intersection of the sets at `keys`, counting no further
+    /// than `limit`.
+    pub fn set_intersection_len(&self, keys: &[String], limit: usize) -> Result<usize> {
+        Ok(Set::intersection_len(&self.snapshot_sets(keys)?, limit))
+    }
+
+    /// Copies of the sets at `keys`, `None` for missing keys. Each key is read
+    /// atomically, but not all of them at once.
+    fn snapshot_sets(&self, keys: &[String]) -> Result<Vec<Option<Set>>> {
+        keys.iter().map(|key| self.read_set(key, |set| set.clone())).collect()
+    }
+
+    /// Adds `members` to the sorted set at `key` or updates their scores as
+    /// `options` allow, creating the set if needed. Returns how many members
+    /// were added, or added or updated with `options.changed`.
+    pub fn zset_add(&self, key: &str, members: Vec<(f64, Bytes)>, options: ZAddOptions) -> Result<usize> {
+        let add = |zset: &mut SortedSet| {
+            let mut count = 0;
+            for (score, member) in members {
+                let old = zset.score(&member);
+                let new = zadd_member(zset, member, score, false, &options)?;
+                if new.is_some() && (old.is_none() || (options.changed && old != new)) {
+                    count += 1;
+                }
+            }
+            Ok(count)
+        };
+
+        // Only XX can leave a new set empty, so it never creates one
+        if options.condition == Some(SetCondition::Exists) {
+            Ok(self.update_zset(key, add)?.transpose()?.unwrap_or(0))
+        } else {
+            self.write_zset(key, add)
+        }
+    }
+
+    /// Adds `increment` to the score of `member` in the sorted set at `key`,
+    /// as ZINCRBY and ZADD INCR do. Returns the new score, or `None` if
+    /// `options` ruled the change out.
+    pub fn zset_incr(&self, key: &str, member: Bytes, increment: f64, options: ZAddOptions) -> Result<Option<f64>> {
+        let incr = |zset: &mut SortedSet| zadd_member(zset, member, increment, true, &options);
+        if options.condition == Some(SetCondition::Exists) {
+            Ok(self.update_zset(key, incr)?.transpose()?.flatten())
+        } else {
+            self.write_zset(key, incr)
+        }
+    }
+
+    pub fn zset_len(&self, key: &str) -> Result<usize> {
+        Ok(self.read_zset(key, |zset| zset.len())?.unwrap_or(0))
+    }
+
+    pub fn zset_score(&self, key: &str, member: &[u8]) -> Result<Option<f64>> {
+        Ok(self.read_zset(key, |zset| zset.score(member))?.flatten())
+    }
+
+    /// The score of each of `members` in the sorted set at `key`.
+    pub fn zset_scores(&self, key: &str, members: &[Bytes]) -> Result<Vec<Option<f64>>> {
+        let scores = self.read_zset(key, |zset| members.iter().map(|member| zset.score(member)).collect())?;
+        Ok(scores.unwrap_or_else(|| vec![None; members.len()]))
+    }
+
+    /// Position of `member` in the sorted set at `key`, counting from the
+    /// lowest score or, with `rev`, from the highest.
+    pub fn zset_rank(&self, key: &str, member: &[u8], rev: bool) -> Result<Option<usize>> {
+        let rank = self.read_zset(key, |zset| {
+            zset.rank(member).map(|rank| if rev { zset.len() - 1 - rank } else { rank })
+        })?;
+        Ok(rank.flatten())
+    }
+
+    /// The members of the sorted set at `key` that `by` covers, with their
+    /// scores. See `SortedSet::range`.
+    pub fn zset_range(&self, key: &str, by: &RangeBy, rev: bool, limit: Limit) -> Result<Vec<(Bytes, f64)>> {
+        Ok(self.read_zset(key, |zset| zset.range(by, rev, limit))?.unwrap_or_default())
+    }
+
+    pub fn zset_count(&self, key: &str, by: &RangeBy) -> Result<usize> {
+        Ok(self.read_zset(key, |zset| zset.count(by))?.unwrap_or(0))
+    }
+
+    /// Removes `members` from the sorted set at `key`, returning how many
+    /// were there.
+    pub fn zset_remove(&self, key: &str, members: &[Bytes]) -> Result<usize> {
+        let removed = self.update_zset(key, |zset| {
+            members.iter().filter(|member| zset.remove(member).is_some()).count()
+        })?;
+        Ok(removed.unwrap_or(0))
+    }
+
+    /// Removes the members `by` covers from the sorted set at `key`, returning
+    /// how many there were.
+    pub fn zset_remove_range(&self, key: &str, by: &RangeBy) -> Result<usize> {
+        Ok(self.update_zset(key, |zset| zset.remove_range(by))?.unwrap_or(0))
+    }
+
+    /// Removes up to `count` members with the lowest scores from the sorted
+    /// set at `key`, or the highest with `max`, and returns them.
+    pub fn zset_pop(&self, key: &str, count: usize, max: bool) -> Result<Vec<(Bytes, f64)>> {
+        Ok(self.update_zset(key, |zset| zset.pop(count, max))?.unwrap_or_default())
+    }
+
+    /// Combines the sorted sets at `keys` into `destination` as ZUNIONSTORE
+    /// and ZINTERSTORE do, replacing whatever it held, and returns its size.
+    /// Plain sets are accepted as sources, their members scoring 1.
+    pub fn zset_combine_store(
+        &self,
+        destination: &str,
+        keys: &[String],
+        weights: &[f64],
+        aggregate: Aggregate,
+        op: SetOp,
+    ) -> Result<usize> {
+        let sets = keys.iter()
+            .map(|key| self.read_value(key, |value| match value {
+                Value::SortedSet(zset) => Some(zset.clone()),
+                Value::Set(set) => Some(set.iter().map(|member| (member, 1.0)).collect()),
+                _ => None,
+            }))
+            .collect::<Result<Vec<_>>>()?;
+
+        let result = SortedSet::combine(&sets, weights, aggregate, op);
+        let len = result.len();
+        self.store(destination, Value::SortedSet(result));
+        Ok(len)
+    }
+
+    /// Adds an entry to the stream at `key`, creating the stream unless
+    /// `options.no_create`, then trims it as `options` say. Returns the ID of
+    /// the entry, or `None` if the stream was missing and not created.
+    pub fn stream_add(&self, key: &str, id: NewId, fields: Fields, options: StreamAddOptions) -> Result<Option<StreamId>> {
+        let now = unix_millis_now();
+        let add = |stream: &mut Stream| {
+            let id = stream.add(id, fields, now)?;
+            if let Some(trim) = options.trim {
+                stream.trim(trim, options.trim_limit);
+            }
+            Ok(id)
+        };
+        let id = if options.no_create {
+            self.update_stream(key, add)?.transpose()?
+        } else {
+            Some(self.write_stream(key, add)?)
+        };
+
+        // Unlike a list element, an entry can be read by every blocked client
+        if id.is_some() {
+            self.stream_waiters.wake_all(key);
+        }
+        Ok(id)
+    }
+
+    pub fn stream_len(&self, key: &str) -> Result<usize> {
+        Ok(self.read_stream(key, Stream::len)?.unwrap_or(0))
+    }
+
+    /// Up to `count` entries of the stream at `key` with IDs between `start`
+    /// and `end`, highest first with `rev`.
+    pub fn stream_range(
+        &self,
+        key: &str,
+        start: Bound<StreamId>,
+        end: Bound<StreamId>,
+        count: usize,
+        rev: bool,
+    ) -> Result<Vec<StreamEntry>> {
+        Ok(self.read_stream(key, |stream| stream.range(start, end, count, rev))?.unwrap_or_default())
+    }
+
+    /// Deletes entries from the stream at `key`, returning how many there were.
+    pub fn stream_delete(&self, key: &str, ids: &[StreamId]) -> Result<usize> {
+        Ok(self.update_stream(key, |stream| stream.delete(ids))?.unwrap_or(0))
+    }
+
+    /// The last ID added to the stream at `key`, which XREAD's `$` stands
+    /// for, or 0-0 if there is no such key.
+    pub fn stream_last_id(&self, key: &str) -> Result<StreamId> {
+        Ok(self.read_stream(key, Stream::last_id)?.unwrap_or(StreamId::MIN))
+    }
+
+    /// Up to `count` entries from each of the streams at the given keys, from
+    /// after the ID given with each. Streams with nothing newer are left out.
+    pub fn stream_read(&self, streams: &[(String, StreamId)], count: usize) -> Result<Vec<(String, Vec<StreamEntry>)>> {
+        let mut read = Vec::new();
+        for (key, after) in streams {
+            let entries = self.stream_range(key, Bound::Excluded(*after), Bound::Unbounded, count, false)?;
+            if !entries.is_empty() {
+                read.push((key.clone(), entries));
+            }
+        }
+        Ok(read)
+    }
+
+    /// Registers a client blocked on the streams at `keys`; it is woken by a
+    /// later XADD to any of them.
+    pub fn wait_for_streams(&self, keys: &[String]) -> WaitGuard<'_> {
+        self.stream_waiters.register(keys)
+    }
+
+    /// Creates a consumer group on the stream at `key` that has had every
+    /// entry up to `last_delivered`, or up to the last one if that is `None`.
+    /// With `create_stream`, a missing stream is created empty first.
+    pub fn stream_group_create(
+        &self,
+        key: &str,
+        group: Bytes,
+        last_delivered: Option<StreamId>,
+        create_stream: bool,
+    ) -> Result<()> {
+        let create = |stream: &mut Stream| stream.create_group(group, last_delivered.unwrap_or(stream.last_id()));
+        let created = if create_stream {
+            self.write_stream(key, |stream| Ok(create(stream)))?
+        } else {
+            self.update_stream(key, create)?.ok_or(StorageError::NoStream)?
+        };
+        if created { Ok(()) } else { Err(StorageError::BusyGroup) }
+    }
+
+    /// Removes a consumer group, returning whether there was one.
+    pub fn stream_group_destroy(&self, key: &str, group: &[u8]) -> Result<bool> {
+        let destroyed = self.update_stream(key, |stream| stream.destroy_group(group))?.ok_or(StorageError::NoStream)?;
+
+        // Clients blocked reading for the group find out it is gone
+        if destroyed {
+            self.stream_waiters.wake_all(key);
+        }
+        Ok(destroyed)
+    }
+
+    /// Sets the last delivered ID of a consumer group, to the stream's last ID
+    /// if `last_delivered` is `None`.
+    pub fn stream_group_set_id(&self, key: &str, group: &[u8], last_delivered: Option<StreamId>) -> Result<()> {
+        let set = |stream: &mut Stream| stream.set_group_id(group, last_delivered.unwrap_or(stream.last_id()));
+        match self.update_stream(key, set)? {
+            Some(true) => Ok(()),
+            Some(false) => Err(no_group(key, group)),
+            None => Err(StorageError::NoStream),
+        }
+    }
+
+    /// Adds a consumer to a group, returning whether it is new.
+    pub fn stream_create_consumer(&self, key: &str, group: &[u8], consumer: &Bytes) -> Result<bool> {
+        let now = unix_millis_now();
+        self.update_stream(key, |stream| stream.create_consumer(group, consumer, now))?
+            .ok_or(StorageError::NoStream)?
+            .ok_or_else(|| no_group(key, group))
+    }
+
+    /// Removes a consumer from a group, returning how many entries were
+    /// pending for it.
+    pub fn stream_delete_consumer(&self, key: &str, group: &[u8], consumer: &[u8]) -> Result<usize> {
+        self.update_stream(key, |stream| stream.delete_consumer(group, consumer))?
+            .ok_or(StorageError::NoStream)?
+            .ok_or_else(|| no_group(key, group))
+    }
+
+    /// Reads for `consumer` of `group` from each of the streams at the given
+    /// keys, up to `count` entries each, as XREADGROUP does. Streams with no
+    /// new entries are left out; reads of pending entries are always answered.
+    pub fn stream_read_group(
+        &self,
+        group: &[u8],
+        consumer: &Bytes,
+        streams: &[(String, GroupRead)],
+        count: usize,
+        noack: bool,
+    ) -> Result<Vec<(String, Vec<GroupEntry>)>> {
+        // Like Redis, fail before delivering anything if a group is missing
+        for (key, _) in streams {
+            if self.read_stream(key, |stream| stream.has_group(group))? != Some(true) {
+                return Err(no_group(key, group));
+            }
+        }
+
+        let now = unix_millis_now();
+        let mut read = Vec::new();
+        for (key, from) in streams {
+            let entries = self.update_group(key, group, |stream| stream.read_group(group, consumer, *from, count, noack, now))?;
+            if !entries.is_empty() || *from != GroupRead::New {
+                read.push((key.clone(), entries));
+            }
+        }
+        Ok(read)
+    }
+
+    /// Acknowledges entries delivered to a consumer group, returning how many
+    /// were pending. A missing stream or group has none pending.
+    pub fn stream_ack(&self, key: &str, group: &[u8], ids: &[StreamId]) -> Result<usize> {
+        Ok(self.update_stream(key, |stream| stream.ack(group, ids))?.flatten().unwrap_or(0))
+    }
+
+    pub fn stream_pending_summary(&self, key: &str, group: &[u8]) -> Result<PendingSummary> {
+        self.read_stream(key, |stream| stream.pending_summary(group))?
+            .flatten()
+            .ok_or_else(|| no_group(key, group))
+    }
+
+    /// The pending entries of a consumer group that `filter` selects.
+    pub fn stream_pending(&self, key: &str, group: &[u8], filter: &PendingFilter) -> Result<Vec<PendingEntry>> {
+        let now = unix_millis_now();
+        self.read_stream(key, |stream| stream.pending(group, filter, now))?
+            .flatten()
+            .ok_or_else(|| no_group(key, group))
+    }
+
+    /// Hands pending entries of a consumer group over to `consumer` as XCLAIM
+    /// does, returning those claimed.
+    pub fn stream_claim(
+        &self,
+        key: &str,
+        group: &[u8],
+        consumer: &Bytes,
+        ids: &[StreamId],
+        options: &ClaimOptions,
+    ) -> Result<Vec<StreamEntry>> {
+        let now = unix_millis_now();
+        self.update_group(key, group, |stream| stream.claim(group, consumer, ids, options, now))
+    }
+
+    /// Hands up to `count` idle pending entries of a consumer group over to
+    /// `consumer`, scanning from `start`, as XAUTOCLAIM does.
+    pub fn stream_auto_claim(
+        &self,
+        key: &str,
+        group: &[u8],
+        consumer: &Bytes,
+        start: StreamId,
+        count: usize,
+        options: &ClaimOptions,
+    ) -> Result<AutoClaim> {
+        let now = unix_millis_now();
+        self.update_group(key, group, |stream| stream.auto_claim(group, consumer, start, count, options, now))
+    }
+
+    pub fn stream_info(&self, key: &str) -> Result<StreamInfo> {
+        self.read_stream(key, Stream::info)?.ok_or(StorageError::KeyNotFound)
+    }
+
+    pub fn stream_groups(&self, key: &str) -> Result<Vec<GroupInfo>> {
+        self.read_stream(key, Stream::groups)?.ok_or(StorageError::KeyNotFound)
+    }
+
+    pub fn stream_consumers(&self, key: &str, group: &[u8]) -> Result<Vec<ConsumerInfo>> {
+        let now = unix_millis_now();
+        self.read_stream(key, |stream| stream.consumers(group, now))?
+            .ok_or(StorageError::KeyNotFound)?
+            .ok_or_else(|| no_group(key, group))
+    }
+
+    /// Runs `f` on the list at `key`, or returns `None` if there is no such key.
+    fn read_list<T>(&self, key: &str, f: impl FnOnce(&VecDeque<Bytes>) -> T) -> Result<Option<T>> {
+        self.read_value(key, |value| match value {
+            Value::List(list) => Some(f(list)),
+            _ => None,
+        })
+    }
+
+    /// Runs `f` on the list at `key` under its shard lock, deleting the key if
+    /// that leaves the list empty. Returns `None` if there is no such key.
+    fn update_list<T>(&self, key: &str, f: impl FnOnce(&mut VecDeque<Bytes>) -> T) -> Result<Option<T>> {
+        self.update_value(key, |value| match value {
+            Value::List(list) => Some(f(list)),
+            _ => None,
+        })
+    }
+
+    /// Runs `f` on the hash at `key`, or returns `None` if there is no such key.
+    fn read_hash<T>(&self, key: &str, f: impl FnOnce(&Hash) -> T) -> Result<Option<T>> {
+        // Fields past their TTL must be deleted first, which needs the write
+        // lock; most hashes have none, so only take it when some are due
+        let now = Instant::now();
+        let mut f = Some(f);
+        let read = self.read_value(key, |value| match value {
+            Value::Hash(hash) if hash.has_expired_fields(now) => Some(None),
+            Value::Hash(hash) => Some(f.take().map(|f| f(hash))),
+            _ => None,
+        })?;
+
+        match (read, f) {
+            (Some(None), Some(f)) => self.update_hash(key, |hash| f(hash)),
+            (read, _) => Ok(read.flatten()),
+        }
+    }
+
+    /// Runs `f` on the hash at `key` under its shard lock, deleting the key if
+    /// that leaves the hash empty. Returns `None` if there is no such key.
+    fn update_hash<T>(&self, key: &str, f: impl FnOnce(&mut Hash) -> T) -> Result<Option<T>> {
+        self.update_value(key, |value| match value {
+            Value::Hash(hash) => {
+                self.expire_fields(key, hash, Instant::now());
+                Some(f(hash))
+            },
+            _ => None,
+        })
+    }
+
+    /// Runs `f` on the hash at `key` under its shard lock, creating the hash
+    /// first if there is no such key.
+    fn write_hash<T>(&self, key: &str, f: impl FnOnce(&mut Hash) -> Result<T>) -> Result<T> {
+        let mut entry = self.entry_or_insert(key, || Value::Hash(Hash::default()))?;
+        let Value::Hash(hash) = &mut entry.value else {
+            return Err(StorageError::WrongType);
+        };
+        self.expire_fields(key, hash, Instant::now());
+        f(hash)
+    }
+
+    /// Deletes the fields of `hash` whose TTL has passed. The caller deletes
+    /// the key should that leave the hash empty.
+    fn expire_fields(&self, key: &str, hash: &mut Hash, now: Instant) {
+        for (field, expiry) in hash.remove_expired(now) {
+            self.reindex(|| ExpiryTarget::HashField(key.to_string(), field.clone()), Some(expiry), None);
+            self.expired_fields.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Sets or clears the TTL of a field of the hash at `key`, keeping the
+    /// expiry index in step. Returns the TTL the field had.
+    fn set_field_expiry(&self, key: &str, hash: &mut Hash, field: &[u8], expiry: Option<Expiry>) -> Option<Expiry> {
+        let old = hash.set_expiry(field, expiry);
+        let target = || ExpiryTarget::HashField(key.to_string(), Bytes::copy_from_slice(field));
+        self.reindex(target, old, expiry.filter(|_| hash.get(field).is_some()));
+        old
+    }
+
+    /// Runs `f` on the set at `key`, or returns `None` if there is no such key.
+    fn read_set<T>(&self, key: &str, f: impl FnOnce(&Set) -> T) -> Result<Option<T>> {
+        self.read_value(key, |value| match value {
+            Value::Set(set) => Some(f(set)),
+            _ => None,
+        })
+    }
+
+    /// Runs `f` on the set at `key` under its shard lock, deleting the key if
+    /// that leaves the set empty. Returns `None` if there is no such key.
+    fn update_set<T>(&self, key: &str, f: impl FnOnce(&mut Set) -> T) -> Result<Option<T>> {
+        self.update_value(key, |value| match value {
+            Value::Set(set) => Some(f(set)),
+            _ => None,
+        })
+    }
+
+    /// Runs `f` on the sorted set at `key`, or returns `None` if there is no
+    /// such key.
+    fn read_zset<T>(&self, key: &str, f: impl FnOnce(&SortedSet) -> T) -> Result<Option<T>> {
+        self.read_value(key, |value| match value {
+            Value::SortedSet(zset) => Some(f(zset)),
+            _ => None,
+        })
+    }
+
+    /// Runs `f` on the sorted set at `key` under its shard lock, deleting the
+    /// key if that leaves the set empty. Returns `None` if there is no such key.
+    fn update_zset<T>(&self, key: &str, f: impl FnOnce(&mut SortedSet) -> T) -> Result<Option<T>> {
+        self.update_value(key, |value| match value {
+            Value::SortedSet(zset) => Some(f(zset)),
+            _ => None,
+        })
+    }
+
+    /// Runs `f` on the sorted set at `key` under its shard lock, creating the
+    /// set first if there is no such key.
+    fn write_zset<T>(&self, key: &str, f: impl FnOnce(&mut SortedSet) -> Result<T>) -> Result<T> {
+        let mut entry = self.entry_or_insert(key, || Value::SortedSet(SortedSet::default()))?;
+        let Value::SortedSet(zset) = &mut entry.value else {
+            return Err(StorageError::WrongType);
+        };
+        f(zset)
+    }
+
+    /// Runs `f` on the stream at `key`, or returns `None` if there is no such
+    /// key.
+    fn read_stream<T>(&self, key: &str, f: impl FnOnce(&Stream) -> T) -> Result<Option<T>> {
+        self.read_value(key, |value| match value {
+            Value::Stream(stream) => Some(f(stream)),
+            _ => None,
+        })
+    }
+
+    /// Runs `f` on the stream at `key` under its shard lock, or returns `None`
+    /// if there is no such key. Streams are kept even when emptied.
+    fn update_stream<T>(&self, key: &str, f: impl FnOnce(&mut Stream) -> T) -> Result<Option<T>> {
+        self.update_value(key, |value| match value {
+            Value::Stream(stream) => Some(f(stream)),
+            _ => None,
+        })
+    }
+
+    /// Runs `f` on the stream at `key` under its shard lock, creating the
+    /// stream first if there is no such key.
+    fn write_stream<T>(&self, key: &str, f: impl FnOnce(&mut Stream) -> Result<T>) -> Result<T> {
+        let mut entry = self.entry_or_insert(key, || Value::Stream(Stream::default()))?;
+        let Value::Stream(stream) = &mut entry.value else {
+            return Err(StorageError::WrongType);
+        };
+        f(stream)
+    }
+
+    /// Runs `f`, which returns `None` if the consumer group is missing, on the
+    /// stream at `key` under its shard lock. A missing stream or group is an
+    /// error.
+    fn update_group<T>(&self, key: &str, group: &[u8], f: impl FnOnce(&mut Stream) -> Option<T>) -> Result<T> {
+        self.update_stream(key, f)?.flatten().ok_or_else(|| no_group(key, group))
+    }
+
+    /// Runs `f` on the value at `key`, or returns `None` if there is no such
+    /// key. `f` returns `None` if the value is not of the type it expects.
+    fn read_value<T>(&self, key: &str, f: impl FnOnce(&Value) -> Option<T>) -> Result<Option<T>> {
+        let entry = match self.live_entry(key, Instant::now()) {
+            Ok(entry) => entry,
+            Err(StorageError::KeyNotFound | StorageError::KeyExpired) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        f(&entry.value).map(Some).ok_or(StorageError::WrongType)
+    }
+
+    /// Runs `f` on the value at `key` under its shard lock, deleting the key
+    /// if that leaves an empty collection. Returns `None` if there is no such
+    /// key; `f` returns `None` if the value is not of the type it expects.
+    fn update_value<T>(&self, key: &str, f: impl FnOnce(&mut Value) -> Option<T>) -> Result<Option<T>> {
+        let now = Instant::now();
+        let mut outcome = None;
+        let mut wrong_type = false;
+        
+        let removed = self.map.remove_if_mut(key, |_, entry| {
+            if entry.is_expired(now) {
+                return true;
+            }
+            outcome = f(&mut entry.value);
+            wrong_type = outcome.is_none();
+            !wrong_type && entry.value.is_empty()
+        });
+        
+        if let Some((_, entry)) = removed {
+            if outcome.is_none() {
+                self.expired_keys.fetch_add(1, Ordering::Relaxed);
+            }
+            self.unlink(key, &entry);
+        }
+        if wrong_type {
+            return Err(StorageError::WrongType);
+        }
+        Ok(outcome)
+    }
+
+    /// Replaces whatever `key` holds with `value`, without a TTL, or deletes
+    /// the key if `value` is an empty collection.
+    fn store(&self, key: &str, value: Value) {
+        let now = Instant::now();
+        // Unlink the old entry under the shard lock, so a concurrent write to
+        // the key cannot reindex it in between
+        let unlink = |old: &ValueEntry| {
+            if old.is_expired(now) {
+                self.expired_keys.fetch_add(1, Ordering::Relaxed);
+            }
+            self.unlink(key, old);
+        };
+        if value.is_empty() {
+            self.map.remove_if(key, |_, old| {
+                unlink(old);
+                true
+            });
+            return;
+        }
+        match self.map.entry(key.to_string()) {
+            Entry::Occupied(mut occupied) => unlink(&occupied.insert(ValueEntry::new(value))),
+            Entry::Vacant(vacant) => {
+                vacant.insert(ValueEntry::new(value));
+            },
+        }
+    }
+
+    /// Runs `f` on the string at `key`. Returns `None` if there is no such key.
+    fn read_string<T>(&self, key: &str, f: impl FnOnce(&Bytes) -> T) -> Result<Option<T>> {
+        self.read_value(key, |value| match value {
+            Value::String(data) => Some(f(data)),
+            _ => None,
+        })
+    }
+
+    /// Replaces the string at `key` with what `f` makes of it, `f` getting
+    /// `None` if there is no such key. Nothing is written if `f` fails. The key
+    /// keeps its TTL and FIFO position; a new key has neither.
+    fn modify_string<T>(&self, key: &str, f: impl FnOnce(Option<&Bytes>) -> Result<(Bytes, T)>) -> Result<T> {
+        match self.map.entry(key.to_string()) {
+            Entry::Occupied(mut occupied) if !occupied.get().is_expired(Instant::now()) => {
+                let Value::String(data) = &mut occupied.get_mut().value else {
+                    return Err(StorageError::WrongType);
+                };
+                let (value, outcome) = f(Some(data))?;
+                *data = value;
+                Ok(outcome)
+            },
+            Entry::Occupied(mut occupied) => {
+                let (value, outcome) = f(None)?;
+                let expired = occupied.insert(ValueEntry::new(Value::String(value)));
+                self.expired_keys.fetch_add(1, Ordering::Relaxed);
+                self.unlink(key, &expired);
+                Ok(outcome)
+            },
+            Entry::Vacant(vacant) => {
+                let (value, outcome) = f(None)?;
+                vacant.insert(ValueEntry::new(Value::String(value)));
+                Ok(outcome)
+            },
+        }
+    }
+
+    /// Returns a write guard for the live value at `key`, storing `create()`
+    /// there first if the key does not exist or has expired.
+    fn entry_or_insert(&self, key: &str, create: impl FnOnce() -> Value) -> Result<RefMut<'_, String, ValueEntry>> {
+        match self.map.entry(key.to_string()) {
+            Entry::Occupied(mut occupied) => {
+                if occupied.get().is_expired(Instant::now()) {
+                    let expired = occupied.insert(ValueEntry::new(create()));
+                    self.expired_keys.fetch_add(1, Ordering::Relaxed);
+                    self.unlink(key, &expired);
+                }
+                Ok(occupied.into_ref())
+            },
+            Entry::Vacant(vacant) => Ok(vacant.insert(ValueEntry::new(create()))),
+        }
+    }
+
+    pub fn keys(&self, pattern: &str) -> Vec<String> {
+        let now = Instant::now();
+        let mut keys = Vec::new();
+        
+        // Simple pattern matching (only supports * wildcard at the end)
+        let is_wildcard = pattern.ends_with('*');
+        let prefix = if is_wildcard {
+            pattern[..pattern.len() - 1].to_string()
+        } else {
+            pattern.to_string()
+        };
+        
+        for entry in self.map.iter() {
+            let key = entry.key();
+            
+            // Skip expired keys
+            if entry.is_expired(now) {
+                continue;
             }
             
             // Match the pattern
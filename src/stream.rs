@@ -0,0 +1,614 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::collections::btree_map::Entry;
+use std::fmt;
+use std::ops::Bound;
+use bytes::Bytes;
+
+use crate::storage::StorageError;
+
+/// The fields of a stream entry and their values, in the order XADD gave them.
+pub type Fields = Vec<(Bytes, Bytes)>;
+
+/// A stream entry as XRANGE and XREAD return it.
+pub type StreamEntry = (StreamId, Fields);
+
+/// An entry as XREADGROUP returns it, without fields if it was deleted after
+/// being delivered.
+pub type GroupEntry = (StreamId, Option<Fields>);
+
+/// A stream entry ID: a Unix time in milliseconds and a sequence number
+/// telling apart entries added within the same millisecond.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl StreamId {
+    pub const MIN: StreamId = StreamId { ms: 0, seq: 0 };
+    pub const MAX: StreamId = StreamId { ms: u64::MAX, seq: u64::MAX };
+
+    /// The smallest ID above this one, if there is any.
+    pub fn next(self) -> Option<StreamId> {
+        match self.seq.checked_add(1) {
+            Some(seq) => Some(StreamId { ms: self.ms, seq }),
+            None => Some(StreamId { ms: self.ms.checked_add(1)?, seq: 0 }),
+        }
+    }
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+/// The ID XADD is asked to give a new entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NewId {
+    /// `*`: the current time, or the last ID's time if the clock went back.
+    Auto,
+    /// `<ms>-*`: the given time with the next free sequence number.
+    AutoSeq(u64),
+    Explicit(StreamId),
+}
+
+/// How XADD trims a stream after adding to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trim {
+    /// Keep only the newest this many entries (`MAXLEN`).
+    MaxLen(usize),
+    /// Drop the entries with lower IDs than this (`MINID`).
+    MinId(StreamId),
+}
+
+/// Where XREADGROUP reads a stream from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupRead {
+    /// `>`: entries never delivered to anyone in the group.
+    New,
+    /// The reading consumer's own pending entries, from after this ID.
+    Pending(StreamId),
+}
+
+/// Which pending entries the extended form of XPENDING lists.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingFilter {
+    pub start: Bound<StreamId>,
+    pub end: Bound<StreamId>,
+    pub count: usize,
+    /// Only entries delivered at least this many milliseconds ago (`IDLE`).
+    pub min_idle: u64,
+    /// Only entries pending for this consumer.
+    pub consumer: Option<Bytes>,
+}
+
+/// How XCLAIM and XAUTOCLAIM take over pending entries.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ClaimOptions {
+    /// Only entries delivered at least this many milliseconds ago.
+    pub min_idle: u64,
+    /// Record the entries as delivered this many milliseconds ago (`IDLE`)
+    /// rather than now.
+    pub idle: Option<u64>,
+    /// Record the entries as delivered at this Unix time in milliseconds
+    /// (`TIME`) rather than now.
+    pub time: Option<u64>,
+    /// Set the delivery counts to this (`RETRYCOUNT`) rather than adding one.
+    pub retry_count: Option<u64>,
+    /// Also claim entries that are in the stream but not pending (`FORCE`).
+    pub force: bool,
+    /// Leave delivery counts alone; the caller only wants the IDs (`JUSTID`).
+    pub just_id: bool,
+    /// Move the group's last delivered ID up to this if it is behind (`LASTID`).
+    pub last_id: Option<StreamId>,
+}
+
+/// XPENDING's summary of a group's pending entries.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingSummary {
+    pub count: usize,
+    pub first: Option<StreamId>,
+    pub last: Option<StreamId>,
+    /// Consumers with pending entries and how many each has.
+    pub consumers: Vec<(Bytes, usize)>,
+}
+
+/// A pending entry as the extended form of XPENDING lists it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingEntry {
+    pub id: StreamId,
+    pub consumer: Bytes,
+    /// Milliseconds since the entry was last delivered.
+    pub idle: u64,
+    pub deliveries: u64,
+}
+
+/// What one XAUTOCLAIM call did.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AutoClaim {
+    /// Where the next call should start, 0-0 once the whole list was scanned.
+    pub next: StreamId,
+    pub claimed: Vec<StreamEntry>,
+    /// Pending entries found deleted from the stream, which were dropped.
+    pub deleted: Vec<StreamId>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamInfo {
+    pub length: usize,
+    pub last_id: StreamId,
+    pub max_deleted_id: StreamId,
+    pub entries_added: u64,
+    pub groups: usize,
+    pub first: Option<StreamEntry>,
+    pub last: Option<StreamEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupInfo {
+    pub name: Bytes,
+    pub consumers: usize,
+    pub pending: usize,
+    pub last_delivered: StreamId,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConsumerInfo {
+    pub name: Bytes,
+    pub pending: usize,
+    /// Milliseconds since the consumer last did anything.
+    pub idle: u64,
+    /// Milliseconds since it last read or claimed an entry, if it ever has.
+    pub inactive: Option<u64>,
+}
+
+/// A stream value: an append-only log of entries ordered by ID, and the
+/// consumer groups reading it.
+///
+/// Unlike other collections, a stream is kept when its last entry is deleted,
+/// so that its groups and last ID survive.
+#[derive(Debug, Default)]
+pub struct Stream {
+    entries: BTreeMap<StreamId, Fields>,
+    /// The highest ID ever added, which new IDs must exceed.
+    last_id: StreamId,
+    /// The highest ID XDEL has removed.
+    max_deleted_id: StreamId,
+    /// Entries ever added, including those since deleted or trimmed.
+    entries_added: u64,
+    groups: BTreeMap<Bytes, Group>,
+}
+
+#[derive(Debug)]
+struct Group {
+    last_delivered: StreamId,
+    /// Entries delivered to a consumer and not acknowledged yet.
+    pending: BTreeMap<StreamId, Pending>,
+    consumers: BTreeMap<Bytes, Consumer>,
+}
+
+#[derive(Debug)]
+struct Pending {
+    consumer: Bytes,
+    /// Unix time in milliseconds.
+    delivered_at: u64,
+    deliveries: u64,
+}
+
+#[derive(Debug)]
+struct Consumer {
+    /// When the consumer last did anything, in Unix milliseconds.
+    seen_at: u64,
+    /// When it last read or claimed an entry.
+    active_at: Option<u64>,
+    /// Its share of the group's pending entries.
+    pending: BTreeSet<StreamId>,
+}
+
+impl Stream {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn last_id(&self) -> StreamId {
+        self.last_id
+    }
+
+    /// Adds an entry and returns its ID, which must be above every ID the
+    /// stream has had. `now` is the Unix time in milliseconds.
+    pub fn add(&mut self, id: NewId, fields: Fields, now: u64) -> Result<StreamId, StorageError> {
+        let last = self.last_id;
+        let id = match id {
+            NewId::Auto if now > last.ms => StreamId { ms: now, seq: 0 },
+            NewId::Auto => last.next().ok_or(StorageError::StreamExhausted)?,
+            NewId::AutoSeq(ms) if ms > last.ms => StreamId { ms, seq: 0 },
+            NewId::AutoSeq(ms) if ms == last.ms => StreamId {
+                ms,
+                seq: last.seq.checked_add(1).ok_or(StorageError::StreamIdTooSmall)?,
+            },
+            NewId::AutoSeq(_) => return Err(StorageError::StreamIdTooSmall),
+            NewId::Explicit(id) if id > last => id,
+            NewId::Explicit(_) => return Err(StorageError::StreamIdTooSmall),
+        };
+
+        self.entries.insert(id, fields);
+        self.last_id = id;
+        self.entries_added += 1;
+        Ok(id)
+    }
+
+    /// Drops the oldest entries as `trim` says, no more than `limit` of them.
+    /// Returns how many were dropped.
+    pub fn trim(&mut self, trim: Trim, limit: Option<usize>) -> usize {
+        let mut trimmed = 0;
+        while limit.is_none_or(|limit| trimmed < limit)
+            && let Some((&first, _)) = self.entries.first_key_value() {
+            let excess = match trim {
+                Trim::MaxLen(max) => self.entries.len() > max,
+                Trim::MinId(min) => first < min,
+            };
+            if !excess {
+                break;
+            }
+            self.entries.remove(&first);
+            trimmed += 1;
+        }
+        trimmed
+    }
+
+    /// Up to `count` entries with IDs between `start` and `end`, lowest
+    /// first, or highest first with `rev`.
+    pub fn range(&self, start: Bound<StreamId>, end: Bound<StreamId>, count: usize, rev: bool) -> Vec<StreamEntry> {
+        if !is_ordered(start, end) {
+            return Vec::new();
+        }
+        let range = self.entries.range((start, end)).map(|(&id, fields)| (id, fields.clone()));
+        if rev {
+            range.rev().take(count).collect()
+        } else {
+            range.take(count).collect()
+        }
+    }
+
+    /// Deletes the entries with the given IDs, returning how many there were.
+    pub fn delete(&mut self, ids: &[StreamId]) -> usize {
+        let mut deleted = 0;
+        for &id in ids {
+            if self.entries.remove(&id).is_some() {
+                self.max_deleted_id = self.max_deleted_id.max(id);
+                deleted += 1;
+            }
+        }
+        deleted
+    }
+
+    /// Adds a consumer group that has had every entry up to `last_delivered`,
+    /// returning false if the name is taken.
+    pub fn create_group(&mut self, name: Bytes, last_delivered: StreamId) -> bool {
+        match self.groups.entry(name) {
+            Entry::Occupied(_) => false,
+            Entry::Vacant(vacant) => {
+                vacant.insert(Group { last_delivered, pending: BTreeMap::new(), consumers: BTreeMap::new() });
+                true
+            },
+        }
+    }
+
+    pub fn has_group(&self, name: &[u8]) -> bool {
+        self.groups.contains_key(name)
+    }
+
+    pub fn destroy_group(&mut self, name: &[u8]) -> bool {
+        self.groups.remove(name).is_some()
+    }
+
+    /// Sets the last delivered ID of a group, returning false if there is no
+    /// such group.
+    pub fn set_group_id(&mut self, name: &[u8], last_delivered: StreamId) -> bool {
+        match self.groups.get_mut(name) {
+            Some(group) => {
+                group.last_delivered = last_delivered;
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Adds a consumer to a group, returning whether it is new, or `None` if
+    /// there is no such group.
+    pub fn create_consumer(&mut self, group: &[u8], consumer: &Bytes, now: u64) -> Option<bool> {
+        let group = self.groups.get_mut(group)?;
+        let new = !group.consumers.contains_key(consumer);
+        group.consumer(consumer, now);
+        Some(new)
+    }
+
+    /// Removes a consumer from a group along with its pending entries,
+    /// returning how many it had, or `None` if there is no such group.
+    pub fn delete_consumer(&mut self, group: &[u8], consumer: &[u8]) -> Option<usize> {
+        let group = self.groups.get_mut(group)?;
+        let Some(removed) = group.consumers.remove(consumer) else {
+            return Some(0);
+        };
+        for id in &removed.pending {
+            group.pending.remove(id);
+        }
+        Some(removed.pending.len())
+    }
+
+    /// Reads up to `count` entries for `consumer` of `group`, as XREADGROUP
+    /// does. New entries join the consumer's pending entries unless `noack`;
+    /// pending entries deleted from the stream since come back without their
+    /// fields. Returns `None` if there is no such group.
+    pub fn read_group(
+        &mut self,
+        group: &[u8],
+        consumer: &Bytes,
+        from: GroupRead,
+        count: usize,
+        noack: bool,
+        now: u64,
+    ) -> Option<Vec<GroupEntry>> {
+        let group = self.groups.get_mut(group)?;
+        group.consumer(consumer, now);
+
+        match from {
+            GroupRead::New => {
+                let read: Vec<StreamEntry> = self.entries
+                    .range((Bound::Excluded(group.last_delivered), Bound::Unbounded))
+                    .take(count)
+                    .map(|(&id, fields)| (id, fields.clone()))
+                    .collect();
+                if let Some(&(last, _)) = read.last() {
+                    group.last_delivered = last;
+                    group.consumer(consumer, now).active_at = Some(now);
+                }
+                if !noack {
+                    for &(id, _) in &read {
+                        group.deliver(id, consumer, now, 1);
+                    }
+                }
+                Some(read.into_iter().map(|(id, fields)| (id, Some(fields))).collect())
+            },
+            GroupRead::Pending(after) => {
+                let ids: Vec<StreamId> = group.consumer(consumer, now).pending
+                    .range((Bound::Excluded(after), Bound::Unbounded))
+                    .take(count)
+                    .copied()
+                    .collect();
+                for id in &ids {
+                    let pending = group.pending.get_mut(id).expect("consumer entries are pending");
+                    pending.delivered_at = now;
+                    pending.deliveries += 1;
+                }
+                Some(ids.into_iter().map(|id| (id, self.entries.get(&id).cloned())).collect())
+            },
+        }
+    }
+
+    /// Acknowledges entries delivered to `group`, returning how many were
+    /// pending, or `None` if there is no such group.
+    pub fn ack(&mut self, group: &[u8], ids: &[StreamId]) -> Option<usize> {
+        let group = self.groups.get_mut(group)?;
+        Some(ids.iter().filter(|&&id| group.remove_pending(id)).count())
+    }
+
+    pub fn pending_summary(&self, group: &[u8]) -> Option<PendingSummary> {
+        let group = self.groups.get(group)?;
+        Some(PendingSummary {
+            count: group.pending.len(),
+            first: group.pending.keys().next().copied(),
+            last: group.pending.keys().next_back().copied(),
+            consumers: group.consumers.iter()
+                .filter(|(_, consumer)| !consumer.pending.is_empty())
+                .map(|(name, consumer)| (name.clone(), consumer.pending.len()))
+                .collect(),
+        })
+    }
+
+    /// The pending entries of `group` that `filter` selects, or `None` if
+    /// there is no such group.
+    pub fn pending(&self, group: &[u8], filter: &PendingFilter, now: u64) -> Option<Vec<PendingEntry>> {
+        let group = self.groups.get(group)?;
+        if !is_ordered(filter.start, filter.end) {
+            return Some(Vec::new());
+        }
+        let entries = group.pending.range((filter.start, filter.end))
+            .map(|(&id, pending)| PendingEntry {
+                id,
+                consumer: pending.consumer.clone(),
+                idle: now.saturating_sub(pending.delivered_at),
+                deliveries: pending.deliveries,
+            })
+            .filter(|entry| entry.idle >= filter.min_idle)
+            .filter(|entry| filter.consumer.as_ref().is_none_or(|consumer| entry.consumer == consumer))
+            .take(filter.count)
+            .collect();
+        Some(entries)
+    }
+
+    /// Hands the pending entries `ids` over to `consumer` as XCLAIM does.
+    /// Entries deleted from the stream are dropped from the pending entries
+    /// instead. Returns the claimed entries, or `None` if there is no such
+    /// group.
+    pub fn claim(
+        &mut self,
+        group: &[u8],
+        consumer: &Bytes,
+        ids: &[StreamId],
+        options: &ClaimOptions,
+        now: u64,
+    ) -> Option<Vec<StreamEntry>> {
+        let group = self.groups.get_mut(group)?;
+        if let Some(last_id) = options.last_id {
+            group.last_delivered = group.last_delivered.max(last_id);
+        }
+        group.consumer(consumer, now);
+
+        let mut claimed = Vec::new();
+        for &id in ids {
+            let Some(fields) = self.entries.get(&id) else {
+                group.remove_pending(id);
+                continue;
+            };
+            let claimable = group.pending.contains_key(&id) || options.force;
+            if claimable && group.claim(id, consumer, options, now) {
+                claimed.push((id, fields.clone()));
+            }
+        }
+        if !claimed.is_empty() {
+            group.consumer(consumer, now).active_at = Some(now);
+        }
+        Some(claimed)
+    }
+
+    /// Hands over up to `count` pending entries idle long enough, scanning
+    /// from `start`, as XAUTOCLAIM does. Returns `None` if there is no such
+    /// group.
+    pub fn auto_claim(
+        &mut self,
+        group: &[u8],
+        consumer: &Bytes,
+        start: StreamId,
+        count: usize,
+        options: &ClaimOptions,
+        now: u64,
+    ) -> Option<AutoClaim> {
+        let group = self.groups.get_mut(group)?;
+        group.consumer(consumer, now);
+
+        // Like Redis, look at no more than ten entries per one asked for, so a
+        // long list of busy entries cannot make a single call slow
+        let attempts = count.saturating_mul(10);
+        let scan: Vec<StreamId> = group.pending.range(start..).map(|(&id, _)| id).take(attempts.saturating_add(1)).collect();
+        let mut result = AutoClaim { next: StreamId::MIN, claimed: Vec::new(), deleted: Vec::new() };
+        for (i, &id) in scan.iter().enumerate() {
+            if i == attempts || result.claimed.len() == count {
+                result.next = id;
+                break;
+            }
+            match self.entries.get(&id) {
+                Some(fields) => {
+                    if group.claim(id, consumer, options, now) {
+                        result.claimed.push((id, fields.clone()));
+                    }
+                },
+                None => {
+                    group.remove_pending(id);
+                    result.deleted.push(id);
+                },
+            }
+        }
+        if !result.claimed.is_empty() {
+            group.consumer(consumer, now).active_at = Some(now);
+        }
+        Some(result)
+    }
+
+    pub fn info(&self) -> StreamInfo {
+        let entry = |(&id, fields): (&StreamId, &Fields)| (id, fields.clone());
+        StreamInfo {
+            length: self.entries.len(),
+            last_id: self.last_id,
+            max_deleted_id: self.max_deleted_id,
+            entries_added: self.entries_added,
+            groups: self.groups.len(),
+            first: self.entries.first_key_value().map(entry),
+            last: self.entries.last_key_value().map(entry),
+        }
+    }
+
+    pub fn groups(&self) -> Vec<GroupInfo> {
+        self.groups.iter()
+            .map(|(name, group)| GroupInfo {
+                name: name.clone(),
+                consumers: group.consumers.len(),
+                pending: group.pending.len(),
+                last_delivered: group.last_delivered,
+            })
+            .collect()
+    }
+
+    /// The consumers of `group`, or `None` if there is no such group.
+    pub fn consumers(&self, group: &[u8], now: u64) -> Option<Vec<ConsumerInfo>> {
+        let group = self.groups.get(group)?;
+        let consumers = group.consumers.iter()
+            .map(|(name, consumer)| ConsumerInfo {
+                name: name.clone(),
+                pending: consumer.pending.len(),
+                idle: now.saturating_sub(consumer.seen_at),
+                inactive: consumer.active_at.map(|active_at| now.saturating_sub(active_at)),
+            })
+            .collect();
+        Some(consumers)
+    }
+}
+
+impl Group {
+    /// The consumer called `name`, created if needed, marked as seen now.
+    fn consumer(&mut self, name: &Bytes, now: u64) -> &mut Consumer {
+        let consumer = self.consumers.entry(name.clone())
+            .or_insert_with(|| Consumer { seen_at: now, active_at: None, pending: BTreeSet::new() });
+        consumer.seen_at = now;
+        consumer
+    }
+
+    /// Records `id` as delivered to `consumer` at `at`, taking it from any
+    /// consumer it was pending for.
+    fn deliver(&mut self, id: StreamId, consumer: &Bytes, at: u64, deliveries: u64) {
+        let pending = Pending { consumer: consumer.clone(), delivered_at: at, deliveries };
+        if let Some(old) = self.pending.insert(id, pending)
+            && let Some(owner) = self.consumers.get_mut(&old.consumer) {
+            owner.pending.remove(&id);
+        }
+        self.consumer(consumer, at).pending.insert(id);
+    }
+
+    /// Hands `id` over to `consumer` if it has been idle long enough, as
+    /// XCLAIM does. An entry not pending at all is always taken, which only
+    /// happens with `FORCE`. Returns whether the entry was claimed.
+    fn claim(&mut self, id: StreamId, consumer: &Bytes, options: &ClaimOptions, now: u64) -> bool {
+        let (idle, deliveries) = match self.pending.get(&id) {
+            Some(pending) => (now.saturating_sub(pending.delivered_at), pending.deliveries),
+            None => (u64::MAX, 0),
+        };
+        if idle < options.min_idle {
+            return false;
+        }
+
+        let delivered_at = match (options.time, options.idle) {
+            (Some(time), _) => time,
+            (None, Some(idle)) => now.saturating_sub(idle),
+            (None, None) => now,
+        };
+        let deliveries = match options.retry_count {
+            Some(count) => count,
+            None if options.just_id => deliveries,
+            None => deliveries + 1,
+        };
+        self.deliver(id, consumer, delivered_at, deliveries);
+        true
+    }
+
+    /// Drops `id` from the pending entries, returning whether it was there.
+    fn remove_pending(&mut self, id: StreamId) -> bool {
+        let Some(pending) = self.pending.remove(&id) else {
+            return false;
+        };
+        if let Some(consumer) = self.consumers.get_mut(&pending.consumer) {
+            consumer.pending.remove(&id);
+        }
+        true
+    }
+}
+
+/// Whether `start` comes no later than `end`. `BTreeMap::range` panics on
+/// bounds the other way round, which here just mean an empty range.
+fn is_ordered(start: Bound<StreamId>, end: Bound<StreamId>) -> bool {
+    match (start, end) {
+        (Bound::Excluded(start), Bound::Excluded(end)) => start < end,
+        (Bound::Included(start) | Bound::Excluded(start), Bound::Included(end) | Bound::Excluded(end)) => start <= end,
+        _ => true,
+    }
+}
@@ -1,16 +1,35 @@
+mod protocol;
+mod server;
+
 #[cfg(test)]
-mod tests {
-    use super::*;
+mod storage {
+    use crate::list::ListEnd;
+    use crate::set::SetOp;
+    use crate::sorted_set::{Aggregate, LexBound, Limit, RangeBy, ScoreBound};
+    use crate::storage::{
+        ExpireCondition, Expiry, FieldExpire, PushOptions, SetCondition, SetOptions, Storage, StorageError,
+        StreamAddOptions, ZAddOptions,
+    };
+    use crate::stream::{ClaimOptions, Fields, GroupRead, NewId, PendingFilter, StreamId, Trim};
     use bytes::Bytes;
+    use std::ops::Bound;
     use std::time::Duration;
     
+    fn expires_in(ttl: Duration) -> SetOptions {
+        SetOptions { expiry: Some(Expiry::after(ttl)), ..SetOptions::default() }
+    }
+
+    fn expiring(ttl: Duration) -> PushOptions {
+        PushOptions { expiry: Some(Expiry::after(ttl)), ..PushOptions::default() }
+    }
+
     #[test]
     fn test_storage_set_get() {
         let storage = Storage::new();
         let key = "test_key".to_string();
         let value = Bytes::from("test_value".as_bytes().to_vec());
         
-        assert!(storage.set(key.clone(), value.clone(), None).is_ok());
+        assert!(storage.set(key.clone(), value.clone(), SetOptions::default()).is_ok());
         
         let result = storage.get(&key);
         assert!(result.is_ok());
@@ -24,7 +43,7 @@ mod tests {
         let value = Bytes::from("test_value".as_bytes().to_vec());
         
         // Set with very short TTL
-        assert!(storage.set(key.clone(), value, Some(Duration::from_millis(10))).is_ok());
+        assert!(storage.set(key.clone(), value, expires_in(Duration::from_millis(10))).is_ok());
         
         // Should be available immediately
         assert!(storage.get(&key).is_ok());
@@ -44,7 +63,7 @@ mod tests {
         for i in 0..5 {
             let key = format!("key_{}", i);
             let value = Bytes::from(format!("value_{}", i).as_bytes().to_vec());
-            assert!(storage.set(key, value, None).is_ok());
+            assert!(storage.set(key, value, SetOptions::default()).is_ok());
         }
         
         // Pop them in FIFO order
@@ -59,4 +78,890 @@ mod tests {
         // Queue should be empty now
         assert!(matches!(storage.pop_fifo(), Err(StorageError::KeyNotFound)));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_storage_set_conditions() {
+        let storage = Storage::new();
+        let key = "lock".to_string();
+        let nx = || SetOptions { condition: Some(SetCondition::NotExists), ..SetOptions::default() };
+        let xx = || SetOptions { condition: Some(SetCondition::Exists), ..SetOptions::default() };
+
+        let outcome = storage.set(key.clone(), Bytes::from("a"), xx()).unwrap();
+        assert!(!outcome.written);
+
+        let outcome = storage.set(key.clone(), Bytes::from("a"), nx()).unwrap();
+        assert!(outcome.written);
+        assert_eq!(outcome.previous, None);
+
+        let outcome = storage.set(key.clone(), Bytes::from("b"), nx()).unwrap();
+        assert!(!outcome.written);
+        assert_eq!(outcome.previous, Some(Bytes::from("a")));
+
+        let outcome = storage.set(key.clone(), Bytes::from("c"), xx()).unwrap();
+        assert!(outcome.written);
+        assert_eq!(storage.get(&key).unwrap(), Bytes::from("c"));
+    }
+
+    #[test]
+    fn test_storage_keep_ttl() {
+        let storage = Storage::new();
+        let key = "session".to_string();
+
+        let ttl = expires_in(Duration::from_millis(10));
+        storage.set(key.clone(), Bytes::from("a"), ttl).unwrap();
+
+        let keep = SetOptions { keep_ttl: true, ..SetOptions::default() };
+        storage.set(key.clone(), Bytes::from("b"), keep).unwrap();
+
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(matches!(storage.get(&key), Err(StorageError::KeyExpired)));
+    }
+
+    #[test]
+    fn test_storage_expire_conditions() {
+        let storage = Storage::new();
+        let key = "flag".to_string();
+        storage.set(key.clone(), Bytes::from("on"), SetOptions::default()).unwrap();
+
+        let xx = ExpireCondition { xx: true, ..ExpireCondition::default() };
+        let gt = ExpireCondition { gt: true, ..ExpireCondition::default() };
+        let lt = ExpireCondition { lt: true, ..ExpireCondition::default() };
+        let hour = Expiry::after(Duration::from_secs(3600));
+        let minute = Expiry::after(Duration::from_secs(60));
+
+        // Without a TTL the key behaves as if it never expires
+        assert!(!storage.expire(&key, hour, xx).unwrap());
+        assert!(!storage.expire(&key, hour, gt).unwrap());
+        assert!(storage.expire(&key, hour, lt).unwrap());
+
+        assert!(!storage.expire(&key, minute, gt).unwrap());
+        assert!(storage.expire(&key, minute, lt).unwrap());
+        assert_eq!(storage.expiry(&key).unwrap(), Some(minute));
+
+        assert!(storage.persist(&key).unwrap());
+        assert_eq!(storage.expiry(&key).unwrap(), None);
+
+        // Racing deadlines leave a single one indexed
+        std::thread::scope(|scope| {
+            for i in 0..4u64 {
+                let (storage, key) = (&storage, &key);
+                scope.spawn(move || {
+                    for j in 0..5000 {
+                        let expiry = Expiry::after(Duration::from_secs(60 + i * 5000 + j));
+                        storage.expire(key, expiry, ExpireCondition::default()).unwrap();
+                    }
+                });
+            }
+        });
+        assert_eq!(storage.stats().expires, 1);
+        assert!(storage.persist(&key).unwrap());
+        assert_eq!(storage.stats().expires, 0);
+
+        // A deadline in the past deletes the key
+        assert!(storage.expire(&key, Expiry::at_unix_millis(1000), ExpireCondition::default()).unwrap());
+        assert!(matches!(storage.get(&key), Err(StorageError::KeyNotFound)));
+        assert!(matches!(storage.expire(&key, hour, ExpireCondition::default()), Err(StorageError::KeyNotFound)));
+    }
+
+    #[test]
+    fn test_storage_active_expiry() {
+        let storage = Storage::new();
+
+        for i in 0..200 {
+            let options = expires_in(Duration::from_millis(50));
+            storage.set(format!("short:{}", i), Bytes::from("v"), options).unwrap();
+        }
+        for i in 0..10 {
+            storage.set(format!("plain:{}", i), Bytes::from("v"), SetOptions::default()).unwrap();
+        }
+
+        // Extending a deadline must keep the key alive past its old one
+        let extended = Expiry::after(Duration::from_secs(60));
+        assert!(storage.expire("short:0", extended, ExpireCondition::default()).unwrap());
+
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(storage.has_due_expirations());
+        assert_eq!(storage.cleanup_expired(Duration::from_secs(1)), 199);
+        assert!(!storage.has_due_expirations());
+
+        let stats = storage.stats();
+        assert_eq!(stats.keys, 11);
+        assert_eq!(stats.expires, 1);
+        assert_eq!(stats.expired_keys, 199);
+        assert!(storage.get("short:0").is_ok());
+
+        assert!(storage.persist("short:0").unwrap());
+        assert_eq!(storage.stats().expires, 0);
+    }
+
+    #[test]
+    fn test_storage_fifo_bookkeeping() {
+        let storage = Storage::new();
+        let set = |key: &str, value: &str| {
+            storage.set(key.to_string(), Bytes::from(value.to_string()), SetOptions::default()).unwrap();
+        };
+
+        set("a", "1");
+        set("b", "1");
+        set("c", "1");
+        set("d", "1");
+        storage.set("e".to_string(), Bytes::from("1"), expires_in(Duration::from_millis(5))).unwrap();
+        set("f", "1");
+
+        // Overwriting moves a key to the back without leaving a duplicate,
+        // deleting drops it, and expired keys are skipped
+        set("a", "2");
+        storage.delete("b").unwrap();
+        std::thread::sleep(Duration::from_millis(10));
+
+        let mut popped = Vec::new();
+        while let Ok((key, value)) = storage.pop_fifo() {
+            popped.push(format!("{}={}", key, String::from_utf8_lossy(&value)));
+        }
+        assert_eq!(popped, ["c=1", "d=1", "f=1", "a=2"]);
+        assert_eq!(storage.stats().keys, 0);
+    }
+
+    #[test]
+    fn test_storage_named_queues() {
+        let storage = Storage::new();
+        let push = |queue: &str, value: &str| storage.queue_push(queue, Bytes::from(value.to_string()), PushOptions::default());
+
+        assert_eq!(push("jobs", "a"), 1);
+        assert_eq!(push("jobs", "b"), 2);
+        assert_eq!(push("mail", "x"), 1);
+        storage.queue_push("jobs", Bytes::from("short"), expiring(Duration::from_millis(5)));
+        push("jobs", "c");
+
+        // Queues do not share ordering with each other or with the keyspace
+        storage.set("jobs".to_string(), Bytes::from("v"), SetOptions::default()).unwrap();
+        assert_eq!(storage.queue_peek("jobs", 10).len(), 4);
+        std::thread::sleep(Duration::from_millis(10));
+        assert_eq!(storage.queue_len("jobs"), 3);
+
+        assert_eq!(storage.queue_pop("jobs", 1), [Bytes::from("a")]);
+        assert_eq!(storage.queue_pop("jobs", 10), [Bytes::from("b"), Bytes::from("c")]);
+        assert!(storage.queue_pop("jobs", 1).is_empty());
+        assert_eq!(storage.queue_peek("mail", 1), [Bytes::from("x")]);
+        assert_eq!(storage.get("jobs").unwrap(), Bytes::from("v"));
+
+        // Expired items are dropped by the active cycle too, and empty queues
+        // disappear
+        storage.queue_push("mail", Bytes::from("y"), expiring(Duration::from_millis(5)));
+        storage.queue_pop("mail", 1);
+        std::thread::sleep(Duration::from_millis(10));
+        storage.cleanup_expired(Duration::from_secs(1));
+        let stats = storage.stats();
+        assert_eq!((stats.queues, stats.expires), (0, 0));
+
+        // An expired item no longer counts towards the length QPUSH reports
+        storage.queue_push("late", Bytes::from("old"), expiring(Duration::from_millis(5)));
+        std::thread::sleep(Duration::from_millis(10));
+        assert_eq!(push("late", "new"), 1);
+        assert_eq!(storage.queue_peek("late", 10), [Bytes::from("new")]);
+    }
+
+    #[test]
+    fn test_storage_queue_reservations() {
+        let storage = Storage::new();
+        let visibility = Duration::from_secs(60);
+        for value in ["a", "b", "c"] {
+            storage.queue_push("jobs", Bytes::from(value), PushOptions::default());
+        }
+
+        let a = storage.queue_reserve("jobs", visibility).unwrap();
+        let b = storage.queue_reserve("jobs", Duration::from_millis(5)).unwrap();
+        assert_eq!((a.data.as_ref(), a.deliveries), (&b"a"[..], 1));
+        assert_eq!(storage.queue_peek("jobs", 10), [Bytes::from("c")]);
+
+        // A NACKed item goes back to its place; an unacknowledged one comes
+        // back once its visibility timeout passes, under a new delivery id
+        assert_eq!(storage.queue_nack("jobs", &[a.id, a.id]), 1);
+        std::thread::sleep(Duration::from_millis(10));
+        assert_eq!(storage.queue_len("jobs"), 3);
+        assert_eq!(storage.queue_ack("jobs", &[b.id]), 0);
+
+        let a = storage.queue_reserve("jobs", visibility).unwrap();
+        let b = storage.queue_reserve("jobs", visibility).unwrap();
+        assert_eq!((a.data.as_ref(), a.deliveries), (&b"a"[..], 2));
+        assert_eq!((b.data.as_ref(), b.deliveries), (&b"b"[..], 2));
+
+        assert_eq!(storage.queue_ack("jobs", &[a.id, b.id, 99]), 2);
+        assert_eq!(storage.queue_pop("jobs", 10), [Bytes::from("c")]);
+        assert_eq!(storage.stats().queues, 0);
+    }
+
+    #[test]
+    fn test_storage_dead_letters() {
+        let storage = Storage::new();
+        storage.set_max_failures(2);
+        let visibility = Duration::from_secs(60);
+        storage.queue_push("jobs", Bytes::from("poison"), PushOptions::default());
+        storage.queue_push("jobs", Bytes::from("ok"), PushOptions::default());
+
+        // The first rejection requeues the item, the second dead-letters it
+        let first = storage.queue_reserve("jobs", visibility).unwrap();
+        assert_eq!(storage.queue_reject("jobs", &[first.id]), 1);
+        let second = storage.queue_reserve("jobs", visibility).unwrap();
+        assert_eq!((second.data.as_ref(), second.deliveries), (&b"poison"[..], 2));
+        assert_eq!(storage.queue_reject("jobs", &[second.id, first.id]), 1);
+
+        assert_eq!(storage.queue_peek("jobs", 10), [Bytes::from("ok")]);
+        assert_eq!(storage.queue_dead("jobs", 10), [(Bytes::from("poison"), 2)]);
+        assert_eq!(storage.queue_dead_len("jobs"), 1);
+
+        // Replayed items get a clean slate; purging the rest leaves no trace
+        assert_eq!(storage.queue_replay("jobs", 10), 1);
+        assert_eq!(storage.queue_dead_len("jobs"), 0);
+        let again = storage.queue_reserve("jobs", visibility).unwrap();
+        assert_eq!(again.data, Bytes::from("poison"));
+        storage.queue_reject("jobs", &[again.id]);
+        assert_eq!(storage.queue_dead_len("jobs"), 0);
+
+        assert_eq!(storage.queue_pop("jobs", 10).len(), 2);
+        storage.queue_push("jobs", Bytes::from("late"), PushOptions::default());
+        for _ in 0..2 {
+            let late = storage.queue_reserve("jobs", visibility).unwrap();
+            storage.queue_reject("jobs", &[late.id]);
+        }
+        assert_eq!(storage.queue_purge_dead("jobs"), 1);
+        assert_eq!(storage.stats().queues, 0);
+    }
+
+    #[test]
+    fn test_storage_scheduled_items() {
+        let storage = Storage::new();
+        let soon = Some(Expiry::after(Duration::from_millis(5)));
+        let later = Some(Expiry::after(Duration::from_secs(60)));
+
+        storage.queue_push("jobs", Bytes::from("retry"), PushOptions { visible_at: soon, ..PushOptions::default() });
+        storage.queue_push("jobs", Bytes::from("tomorrow"), PushOptions { visible_at: later, ..PushOptions::default() });
+        assert_eq!(storage.queue_push("jobs", Bytes::from("now"), PushOptions::default()), 1);
+
+        let scheduled = storage.queue_scheduled("jobs", 10);
+        let ids: Vec<_> = scheduled.iter().map(|(id, data, _)| (*id, data.clone())).collect();
+        assert_eq!(ids, [(0, Bytes::from("retry")), (1, Bytes::from("tomorrow"))]);
+
+        // A due item joins the back of the queue rather than its push position
+        std::thread::sleep(Duration::from_millis(10));
+        assert_eq!(storage.queue_len("jobs"), 2);
+        assert_eq!(storage.queue_cancel("jobs", &[0, 1, 2]), 1);
+        assert_eq!(storage.queue_pop("jobs", 10), [Bytes::from("now"), Bytes::from("retry")]);
+        assert_eq!(storage.stats().queues, 0);
+    }
+
+    #[test]
+    fn test_storage_priority_queue() {
+        let storage = Storage::new();
+        let push = |value: &str, priority: i64| {
+            let options = PushOptions { priority, ..PushOptions::default() };
+            storage.queue_push("jobs", Bytes::from(value.to_string()), options);
+        };
+
+        push("low", -1);
+        push("a", 0);
+        push("urgent", 10);
+        push("b", 0);
+        push("urgent-2", 10);
+        assert_eq!(storage.queue_depths("jobs"), [(10, 2), (0, 2), (-1, 1)]);
+
+        // A requeued item keeps its place within its priority
+        let urgent = storage.queue_reserve("jobs", Duration::from_secs(60)).unwrap();
+        assert_eq!(storage.queue_depths("jobs"), [(10, 1), (0, 2), (-1, 1)]);
+        storage.queue_nack("jobs", &[urgent.id]);
+
+        let popped = storage.queue_pop("jobs", 10);
+        assert_eq!(popped, ["urgent", "urgent-2", "a", "b", "low"].map(Bytes::from));
+        assert!(storage.queue_depths("jobs").is_empty());
+    }
+
+    #[test]
+    fn test_storage_lists() {
+        let storage = Storage::new();
+        let values = |values: &[&str]| values.iter().map(|value| Bytes::from(value.to_string())).collect::<Vec<_>>();
+
+        assert_eq!(storage.list_push("l", ListEnd::Right, values(&["b", "c", "b"])).unwrap(), 3);
+        assert_eq!(storage.list_push("l", ListEnd::Left, values(&["a", "z"])).unwrap(), 5);
+        assert_eq!(storage.list_range("l", 0, -1).unwrap(), values(&["z", "a", "b", "c", "b"]));
+        assert_eq!(storage.list_range("l", -100, 1).unwrap(), values(&["z", "a"]));
+        assert!(storage.list_range("l", 3, 1).unwrap().is_empty());
+        assert_eq!(storage.list_index("l", -1).unwrap(), Some(Bytes::from("b")));
+        assert_eq!(storage.list_index("l", 5).unwrap(), None);
+
+        assert_eq!(storage.list_remove("l", -1, b"b").unwrap(), 1);
+        assert_eq!(storage.list_insert("l", false, b"a", Bytes::from("y")).unwrap(), 5);
+        assert_eq!(storage.list_insert("l", true, b"nope", Bytes::from("y")).unwrap(), -1);
+        storage.list_set("l", 0, Bytes::from("x")).unwrap();
+        assert!(matches!(storage.list_set("l", 9, Bytes::new()), Err(StorageError::IndexOutOfRange)));
+        storage.list_trim("l", 1, -2).unwrap();
+        assert_eq!(storage.list_range("l", 0, -1).unwrap(), values(&["a", "y", "b"]));
+
+        assert_eq!(storage.list_move("l", "m", ListEnd::Left, ListEnd::Right).unwrap(), Some(Bytes::from("a")));
+        assert_eq!(storage.list_move("l", "l", ListEnd::Right, ListEnd::Left).unwrap(), Some(Bytes::from("b")));
+        assert_eq!(storage.list_pop("l", ListEnd::Left, 10).unwrap(), Some(values(&["b", "y"])));
+
+        // Emptied lists disappear, and types are enforced both ways
+        assert_eq!(storage.list_len("l").unwrap(), 0);
+        assert!(storage.list_pop("l", ListEnd::Left, 1).unwrap().is_none());
+        storage.set("s".to_string(), Bytes::from("v"), SetOptions::default()).unwrap();
+        assert!(matches!(storage.get("m"), Err(StorageError::WrongType)));
+        assert!(matches!(storage.list_len("s"), Err(StorageError::WrongType)));
+        assert!(matches!(storage.list_move("m", "s", ListEnd::Left, ListEnd::Left), Err(StorageError::WrongType)));
+        assert_eq!(storage.list_len("m").unwrap(), 1);
+
+        // SET replaces a list, unless it was asked for the old string value
+        let get = SetOptions { get: true, ..SetOptions::default() };
+        assert!(matches!(storage.set("m".to_string(), Bytes::from("v"), get), Err(StorageError::WrongType)));
+        storage.set("m".to_string(), Bytes::from("v"), SetOptions::default()).unwrap();
+        assert_eq!(storage.pop_fifo().unwrap().0, "s");
+        assert_eq!(storage.pop_fifo().unwrap().0, "m");
+    }
+
+    #[test]
+    fn test_storage_hashes() {
+        let storage = Storage::new();
+        let pair = |field: &str, value: &str| (Bytes::from(field.to_string()), Bytes::from(value.to_string()));
+
+        assert_eq!(storage.hash_set("h", vec![pair("a", "1"), pair("b", "2")]).unwrap(), 2);
+        assert_eq!(storage.hash_set("h", vec![pair("a", "3"), pair("c", "x")]).unwrap(), 1);
+        assert_eq!(storage.hash_get("h", b"a").unwrap(), Some(Bytes::from("3")));
+        assert_eq!(
+            storage.hash_get_many("h", &[Bytes::from("b"), Bytes::from("nope")]).unwrap(),
+            vec![Some(Bytes::from("2")), None],
+        );
+        assert_eq!(storage.hash_get_many("missing", &[Bytes::from("a")]).unwrap(), vec![None]);
+
+        assert_eq!(storage.hash_incr_by("h", Bytes::from("a"), -5).unwrap(), -2);
+        assert_eq!(storage.hash_incr_by("h", Bytes::from("n"), 7).unwrap(), 7);
+        assert!(matches!(storage.hash_incr_by("h", Bytes::from("c"), 1), Err(StorageError::HashValueNotInteger)));
+        assert!(matches!(storage.hash_incr_by("h", Bytes::from("n"), i64::MAX), Err(StorageError::Overflow)));
+        assert_eq!(storage.hash_incr_by_float("h", Bytes::from("b"), 0.5).unwrap(), Bytes::from("2.5"));
+        assert!(matches!(storage.hash_incr_by_float("h", Bytes::from("c"), 1.0), Err(StorageError::HashValueNotFloat)));
+        assert_eq!(storage.hash_get("h", b"b").unwrap(), Some(Bytes::from("2.5")));
+
+        assert_eq!(storage.hash_delete("h", &[Bytes::from("a"), Bytes::from("a"), Bytes::from("z")]).unwrap(), 1);
+        assert!(!storage.hash_exists("h", b"a").unwrap());
+        assert_eq!(storage.hash_len("h").unwrap(), 3);
+        let mut fields = storage.hash_keys("h").unwrap();
+        fields.sort();
+        assert_eq!(fields, vec![Bytes::from("b"), Bytes::from("c"), Bytes::from("n")]);
+
+        // Emptied hashes disappear, and types are enforced
+        let fields: Vec<Bytes> = storage.hash_keys("h").unwrap();
+        assert_eq!(storage.hash_delete("h", &fields).unwrap(), 3);
+        assert!(storage.keys("h").is_empty());
+        storage.set("s".to_string(), Bytes::from("v"), SetOptions::default()).unwrap();
+        assert!(matches!(storage.hash_set("s", vec![pair("a", "1")]), Err(StorageError::WrongType)));
+        assert!(matches!(storage.hash_len("s"), Err(StorageError::WrongType)));
+        assert!(matches!(storage.list_len("s"), Err(StorageError::WrongType)));
+    }
+
+    #[test]
+    fn test_storage_hash_scan() {
+        let storage = Storage::new();
+        let pairs = |range: std::ops::Range<usize>| {
+            range.map(|i| (Bytes::from(format!("f{}", i)), Bytes::from(i.to_string()))).collect::<Vec<_>>()
+        };
+
+        // A compact hash comes back whole, whatever the count
+        storage.hash_set("small", pairs(0..20)).unwrap();
+        let (cursor, found) = storage.hash_scan("small", 0, None, 1).unwrap();
+        assert_eq!((cursor, found.len()), (0, 20));
+        let (_, found) = storage.hash_scan("small", 0, Some(b"f1?"), 1).unwrap();
+        assert_eq!(found.len(), 10);
+
+        // Overwriting a field with a long value outgrows the compact form too
+        storage.hash_set("small", vec![(Bytes::from("f0"), Bytes::from("v".repeat(65)))]).unwrap();
+        let (cursor, found) = storage.hash_scan("small", 0, None, 1).unwrap();
+        assert!(cursor != 0 && found.len() < 20);
+
+        // A table is walked in steps, each field present throughout being
+        // returned exactly once even as others come and go
+        storage.hash_set("big", pairs(0..300)).unwrap();
+        let mut seen = std::collections::HashSet::new();
+        let mut cursor = 0;
+        let mut steps = 0;
+        loop {
+            let (next, found) = storage.hash_scan("big", cursor, None, 10).unwrap();
+            assert!(next == 0 || found.len() >= 10);
+            for (field, _) in found {
+                assert!(seen.insert(field));
+            }
+            storage.hash_set("big", pairs(300 + steps..301 + steps)).unwrap();
+            storage.hash_delete("big", &[Bytes::from(format!("f{}", 300 + steps / 2))]).unwrap();
+            steps += 1;
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+        assert!(steps > 1);
+        assert!(pairs(0..300).iter().all(|(field, _)| seen.contains(field)));
+    }
+
+    #[test]
+    fn test_storage_hash_field_ttls() {
+        let storage = Storage::new();
+        let fields = |fields: &[&str]| fields.iter().map(|field| Bytes::from(field.to_string())).collect::<Vec<_>>();
+        let pairs = |fields: &[&str]| fields.iter().map(|field| (Bytes::from(field.to_string()), Bytes::from("v"))).collect();
+        let expire = |key: &str, names: &[&str], expiry: Expiry| {
+            storage.hash_expire(key, &fields(names), expiry, ExpireCondition::default()).unwrap()
+        };
+        let soon = Expiry::after(Duration::from_millis(50));
+        let hour = Expiry::after(Duration::from_secs(3600));
+        let gt = ExpireCondition { gt: true, ..ExpireCondition::default() };
+
+        storage.hash_set("h", pairs(&["a", "b", "c", "d"])).unwrap();
+        assert_eq!(
+            storage.hash_expire("h", &fields(&["a", "b", "x"]), hour, ExpireCondition::default()).unwrap(),
+            vec![FieldExpire::Applied, FieldExpire::Applied, FieldExpire::NoSuchField],
+        );
+        assert_eq!(storage.hash_expire("h", &fields(&["a", "c"]), soon, gt).unwrap(), vec![FieldExpire::NotApplied; 2]);
+        assert_eq!(expire("h", &["c"], soon), vec![FieldExpire::Applied]);
+        assert_eq!(expire("h", &["d"], Expiry::at_unix_millis(0)), vec![FieldExpire::Deleted]);
+        assert_eq!(storage.hash_field_expiry("h", &fields(&["a", "d"])).unwrap(), vec![Some(Some(hour)), None]);
+
+        // HSET drops a field's TTL, HINCRBY keeps it
+        storage.hash_set("h", pairs(&["a"])).unwrap();
+        storage.hash_incr_by("h", Bytes::from("n"), 1).unwrap();
+        expire("h", &["n"], hour);
+        storage.hash_incr_by("h", Bytes::from("n"), 1).unwrap();
+        assert_eq!(
+            storage.hash_persist("h", &fields(&["a", "b", "n", "x"])).unwrap(),
+            vec![Some(false), Some(true), Some(true), None],
+        );
+
+        // Due fields disappear lazily on access, or through the active cycle
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(storage.has_due_expirations());
+        assert_eq!(storage.hash_get("h", b"c").unwrap(), None);
+        assert_eq!(storage.hash_len("h").unwrap(), 3);
+        assert!(!storage.has_due_expirations());
+
+        storage.hash_set("other", pairs(&["a", "b"])).unwrap();
+        expire("other", &["a"], Expiry::after(Duration::from_millis(50)));
+        expire("other", &["b"], Expiry::after(Duration::from_millis(50)));
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(storage.cleanup_expired(Duration::from_secs(1)), 0);
+        assert!(storage.keys("other").is_empty());
+        assert_eq!(storage.stats().expired_fields, 3);
+
+        // Replacing or deleting a hash forgets its field deadlines
+        expire("h", &["a"], Expiry::after(Duration::from_millis(50)));
+        storage.set("h".to_string(), Bytes::from("v"), SetOptions::default()).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(!storage.has_due_expirations());
+    }
+
+    #[test]
+    fn test_storage_sets() {
+        let storage = Storage::new();
+        let members = |members: &[&str]| members.iter().map(|member| Bytes::from(member.to_string())).collect::<Vec<_>>();
+        let keys = |keys: &[&str]| keys.iter().map(|key| key.to_string()).collect::<Vec<_>>();
+        let sorted = |mut members: Vec<Bytes>| {
+            members.sort();
+            members
+        };
+
+        // Integer members are kept in order until a non-integer arrives
+        assert_eq!(storage.set_add("s", members(&["3", "1", "2", "1"])).unwrap(), 3);
+        assert_eq!(storage.set_members("s").unwrap(), members(&["1", "2", "3"]));
+        assert!(!storage.set_contains("s", b"01").unwrap());
+        assert_eq!(storage.set_add("s", members(&["x", "3"])).unwrap(), 1);
+        assert_eq!(sorted(storage.set_members("s").unwrap()), members(&["1", "2", "3", "x"]));
+        assert_eq!(storage.set_contains_many("s", &members(&["2", "y"])).unwrap(), vec![true, false]);
+        assert_eq!(storage.set_contains_many("missing", &members(&["2"])).unwrap(), vec![false]);
+        assert_eq!(storage.set_remove("s", &members(&["1", "y"])).unwrap(), 1);
+        assert_eq!(storage.set_len("s").unwrap(), 3);
+
+        storage.set_add("t", members(&["2", "x", "z"])).unwrap();
+        let combine = |keys: &[String], op| sorted(storage.set_combine(keys, op).unwrap());
+        assert_eq!(combine(&keys(&["s", "t"]), SetOp::Inter), members(&["2", "x"]));
+        assert_eq!(combine(&keys(&["s", "t"]), SetOp::Union), members(&["2", "3", "x", "z"]));
+        assert_eq!(combine(&keys(&["s", "t"]), SetOp::Diff), members(&["3"]));
+        assert!(combine(&keys(&["s", "missing"]), SetOp::Inter).is_empty());
+        assert_eq!(storage.set_intersection_len(&keys(&["s", "t"]), 1).unwrap(), 1);
+
+        // Storing replaces the destination, TTL included, and an empty result
+        // deletes it
+        storage.set("d".to_string(), Bytes::from("v"), expires_in(Duration::from_secs(60))).unwrap();
+        assert_eq!(storage.set_combine_store("d", &keys(&["s", "t"]), SetOp::Union).unwrap(), 4);
+        assert!(storage.expiry("d").unwrap().is_none());
+        assert_eq!(storage.set_combine_store("d", &keys(&["s", "missing"]), SetOp::Inter).unwrap(), 0);
+        assert!(storage.keys("d").is_empty());
+
+        // An expired destination counts as expired when it is replaced
+        storage.set("e".to_string(), Bytes::from("v"), expires_in(Duration::from_millis(10))).unwrap();
+        storage.set("f".to_string(), Bytes::from("v"), expires_in(Duration::from_millis(10))).unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(storage.set_combine_store("e", &keys(&["s"]), SetOp::Union).unwrap(), 3);
+        assert_eq!(storage.set_combine_store("f", &keys(&["missing"]), SetOp::Union).unwrap(), 0);
+        let stats = storage.stats();
+        assert_eq!((stats.expired_keys, stats.expires), (2, 0));
+
+        // Random picks are distinct up to the size, or repeat when negative
+        assert_eq!(sorted(storage.set_random("t", 10).unwrap()), members(&["2", "x", "z"]));
+        assert_eq!(storage.set_random("t", -5).unwrap().len(), 5);
+        assert!(storage.set_random("missing", -5).unwrap().is_empty());
+        assert_eq!(storage.set_pop("t", 2).unwrap().len(), 2);
+        assert_eq!(storage.set_len("t").unwrap(), 1);
+        storage.set_pop("t", 5).unwrap();
+        assert!(storage.keys("t").is_empty());
+
+        // The same holds for a table, whichever way the picks are drawn
+        let big: Vec<Bytes> = (0..100).map(|i| Bytes::from(format!("m{}", i))).collect();
+        storage.set_add("big", big.clone()).unwrap();
+        for count in [1, 10, 50, 99] {
+            let picked = sorted(storage.set_random("big", count).unwrap());
+            assert_eq!(picked.len(), count as usize);
+            assert!(picked.windows(2).all(|pair| pair[0] != pair[1]));
+            assert!(picked.iter().all(|member| big.contains(member)));
+        }
+        let mut popped = Vec::new();
+        while storage.set_len("big").unwrap() > 0 {
+            popped.extend(storage.set_pop("big", 7).unwrap());
+        }
+        assert_eq!(sorted(popped), sorted(big));
+
+        // Types are enforced both ways
+        storage.set("str".to_string(), Bytes::from("v"), SetOptions::default()).unwrap();
+        assert!(matches!(storage.set_add("str", members(&["a"])), Err(StorageError::WrongType)));
+        assert!(matches!(storage.set_combine(&keys(&["s", "str"]), SetOp::Union), Err(StorageError::WrongType)));
+        assert!(matches!(storage.get("s"), Err(StorageError::WrongType)));
+    }
+
+    #[test]
+    fn test_storage_sorted_sets() {
+        let storage = Storage::new();
+        let scored = |pairs: &[(f64, &str)]| {
+            pairs.iter().map(|&(score, member)| (score, Bytes::from(member.to_string()))).collect::<Vec<_>>()
+        };
+        let members = |pairs: Vec<(Bytes, f64)>| pairs.into_iter().map(|(member, _)| member).collect::<Vec<_>>();
+        let names = |names: &[&str]| names.iter().map(|name| Bytes::from(name.to_string())).collect::<Vec<_>>();
+        let add = |pairs: &[(f64, &str)], options| storage.zset_add("z", scored(pairs), options).unwrap();
+
+        assert_eq!(add(&[(1.0, "a"), (2.0, "b"), (2.0, "c"), (3.0, "d")], ZAddOptions::default()), 4);
+        let nx = ZAddOptions { condition: Some(SetCondition::NotExists), ..ZAddOptions::default() };
+        assert_eq!(add(&[(9.0, "a"), (4.0, "e")], nx), 1);
+        let gt_ch = ZAddOptions { only_if: Some(std::cmp::Ordering::Greater), changed: true, ..ZAddOptions::default() };
+        assert_eq!(add(&[(0.0, "a"), (5.0, "b"), (6.0, "f")], gt_ch), 2);
+        let xx = ZAddOptions { condition: Some(SetCondition::Exists), ..ZAddOptions::default() };
+        assert_eq!(storage.zset_add("missing", scored(&[(1.0, "a")]), xx).unwrap(), 0);
+        assert!(storage.keys("missing").is_empty());
+        assert_eq!(storage.zset_incr("z", Bytes::from("a"), 0.5, ZAddOptions::default()).unwrap(), Some(1.5));
+        assert_eq!(storage.zset_incr("z", Bytes::from("x"), 1.0, xx).unwrap(), None);
+
+        // a=1.5 c=2 d=3 e=4 b=5 f=6
+        let range = |key, by: &RangeBy, rev, limit| members(storage.zset_range(key, by, rev, limit).unwrap());
+        let all = RangeBy::Rank(0, -1);
+        assert_eq!(range("z", &all, false, Limit::default()), names(&["a", "c", "d", "e", "b", "f"]));
+        assert_eq!(range("z", &RangeBy::Rank(0, 1), true, Limit::default()), names(&["f", "b"]));
+        let by_score = RangeBy::Score(ScoreBound::Exclusive(2.0), ScoreBound::Inclusive(5.0));
+        assert_eq!(range("z", &by_score, false, Limit::default()), names(&["d", "e", "b"]));
+        assert_eq!(range("z", &by_score, true, Limit { offset: 1, count: Some(1) }), names(&["e"]));
+        assert_eq!(storage.zset_count("z", &by_score).unwrap(), 3);
+        assert_eq!(storage.zset_rank("z", b"d", false).unwrap(), Some(2));
+        assert_eq!(storage.zset_rank("z", b"d", true).unwrap(), Some(3));
+        assert_eq!(storage.zset_scores("z", &names(&["b", "nope"])).unwrap(), vec![Some(5.0), None]);
+
+        assert_eq!(storage.zset_pop("z", 2, true).unwrap(), vec![(Bytes::from("f"), 6.0), (Bytes::from("b"), 5.0)]);
+        assert_eq!(storage.zset_remove_range("z", &RangeBy::Rank(-1, -1)).unwrap(), 1);
+        assert_eq!(storage.zset_remove("z", &names(&["a", "nope"])).unwrap(), 1);
+        assert_eq!(storage.zset_len("z").unwrap(), 2);
+
+        // Lexicographic ranges over members with equal scores
+        let pairs = scored(&[(0.0, "a"), (0.0, "b"), (0.0, "c"), (0.0, "d")]);
+        storage.zset_add("lex", pairs, ZAddOptions::default()).unwrap();
+        let by_lex = RangeBy::Lex(LexBound::Exclusive(Bytes::from("a")), LexBound::Inclusive(Bytes::from("c")));
+        assert_eq!(range("lex", &by_lex, false, Limit::default()), names(&["b", "c"]));
+        assert_eq!(storage.zset_remove_range("lex", &RangeBy::Lex(LexBound::Min, LexBound::Max)).unwrap(), 4);
+        assert!(storage.keys("lex").is_empty());
+
+        // Ranks stay right through many inserts, updates and removals
+        let expected: Vec<usize> = (0..500).filter(|i| i % 3 != 0).collect();
+        for i in 0..500 {
+            storage.zset_add("big", vec![(-(i as f64), Bytes::from(i.to_string()))], ZAddOptions::default()).unwrap();
+        }
+        for i in 0..500 {
+            storage.zset_add("big", vec![(i as f64, Bytes::from(i.to_string()))], ZAddOptions::default()).unwrap();
+        }
+        let removed: Vec<Bytes> = (0..500).step_by(3).map(|i| Bytes::from(i.to_string())).collect();
+        storage.zset_remove("big", &removed).unwrap();
+        for (rank, i) in expected.iter().enumerate() {
+            assert_eq!(storage.zset_rank("big", i.to_string().as_bytes(), false).unwrap(), Some(rank));
+            let at = storage.zset_range("big", &RangeBy::Rank(rank as i64, rank as i64), false, Limit::default());
+            assert_eq!(at.unwrap(), vec![(Bytes::from(i.to_string()), *i as f64)]);
+        }
+
+        // Union and intersection with weights, sets counting as score 1
+        storage.zset_add("z1", scored(&[(1.0, "a"), (2.0, "b")]), ZAddOptions::default()).unwrap();
+        storage.zset_add("z2", scored(&[(10.0, "b"), (20.0, "c")]), ZAddOptions::default()).unwrap();
+        storage.set_add("s", names(&["b", "c"])).unwrap();
+        let keys = |keys: &[&str]| keys.iter().map(|key| key.to_string()).collect::<Vec<_>>();
+        let stored = storage.zset_combine_store("out", &keys(&["z1", "z2"]), &[2.0, 1.0], Aggregate::Sum, SetOp::Union);
+        assert_eq!(stored.unwrap(), 3);
+        assert_eq!(
+            storage.zset_range("out", &all, false, Limit::default()).unwrap(),
+            vec![(Bytes::from("a"), 2.0), (Bytes::from("b"), 14.0), (Bytes::from("c"), 20.0)],
+        );
+        let stored = storage.zset_combine_store("out", &keys(&["z2", "s"]), &[], Aggregate::Min, SetOp::Inter);
+        assert_eq!(stored.unwrap(), 2);
+        assert_eq!(storage.zset_scores("out", &names(&["b", "c"])).unwrap(), vec![Some(1.0), Some(1.0)]);
+
+        // NaN scores are refused, and types are enforced
+        storage.zset_add("inf", scored(&[(f64::INFINITY, "a")]), ZAddOptions::default()).unwrap();
+        let nan = storage.zset_incr("inf", Bytes::from("a"), f64::NEG_INFINITY, ZAddOptions::default());
+        assert!(matches!(nan, Err(StorageError::ScoreNaN)));
+        assert!(matches!(storage.zset_len("s"), Err(StorageError::WrongType)));
+        assert!(matches!(storage.get("z"), Err(StorageError::WrongType)));
+    }
+
+    #[test]
+    fn test_storage_streams() {
+        let storage = Storage::new();
+        let id = |ms, seq| StreamId { ms, seq };
+        let fields = |value: &str| vec![(Bytes::from("f"), Bytes::from(value.to_string()))];
+        let ids = |entries: Vec<(StreamId, Fields)>| entries.into_iter().map(|(id, _)| id).collect::<Vec<_>>();
+        let add = |key, new_id, value| storage.stream_add(key, new_id, fields(value), StreamAddOptions::default());
+
+        assert_eq!(add("s", NewId::Explicit(id(1, 1)), "a").unwrap(), Some(id(1, 1)));
+        assert_eq!(add("s", NewId::AutoSeq(1), "b").unwrap(), Some(id(1, 2)));
+        assert_eq!(add("s", NewId::AutoSeq(5), "c").unwrap(), Some(id(5, 0)));
+        assert!(matches!(add("s", NewId::Explicit(id(5, 0)), "x"), Err(StorageError::StreamIdTooSmall)));
+        assert!(matches!(add("s", NewId::AutoSeq(4), "x"), Err(StorageError::StreamIdTooSmall)));
+        let auto = add("s", NewId::Auto, "d").unwrap().unwrap();
+        assert!(auto > id(5, 0));
+        let no_create = StreamAddOptions { no_create: true, ..StreamAddOptions::default() };
+        assert_eq!(storage.stream_add("missing", NewId::Auto, fields("x"), no_create).unwrap(), None);
+        assert!(storage.keys("missing").is_empty());
+
+        let range = |start, end, count, rev| ids(storage.stream_range("s", start, end, count, rev).unwrap());
+        assert_eq!(range(Bound::Unbounded, Bound::Unbounded, usize::MAX, false), vec![id(1, 1), id(1, 2), id(5, 0), auto]);
+        assert_eq!(range(Bound::Excluded(id(1, 1)), Bound::Included(id(5, 0)), 1, true), vec![id(5, 0)]);
+        assert!(range(Bound::Included(id(5, 0)), Bound::Excluded(id(1, 1)), 10, false).is_empty());
+        assert!(range(Bound::Excluded(id(5, 0)), Bound::Excluded(id(5, 0)), 10, false).is_empty());
+
+        // Trimming by length and by ID; a stream outlives its last entry
+        assert_eq!(storage.stream_delete("s", &[id(1, 2), id(9, 9)]).unwrap(), 1);
+        let trim = |trim| StreamAddOptions { trim: Some(trim), ..StreamAddOptions::default() };
+        storage.stream_add("s", NewId::Auto, fields("e"), trim(Trim::MaxLen(3))).unwrap();
+        assert_eq!(range(Bound::Unbounded, Bound::Included(auto), 10, false), vec![id(5, 0), auto]);
+        storage.stream_add("s", NewId::Auto, fields("f"), trim(Trim::MinId(auto))).unwrap();
+        let left = range(Bound::Unbounded, Bound::Unbounded, usize::MAX, false);
+        assert_eq!((left.len(), left[0]), (3, auto));
+        assert_eq!(storage.stream_delete("s", &left).unwrap(), 3);
+        assert_eq!(storage.stream_len("s").unwrap(), 0);
+        assert_eq!(storage.keys("s"), vec!["s".to_string()]);
+        assert_eq!(storage.stream_last_id("s").unwrap(), left[2]);
+
+        add("r", NewId::Explicit(id(1, 0)), "a").unwrap();
+        add("r", NewId::Explicit(id(2, 0)), "b").unwrap();
+        let streams = [("r".to_string(), id(1, 0)), ("empty".to_string(), StreamId::MIN)];
+        let read = storage.stream_read(&streams, 10).unwrap();
+        assert_eq!(read.len(), 1);
+        assert_eq!(ids(read[0].1.clone()), vec![id(2, 0)]);
+
+        // Consumer groups
+        let g = || Bytes::from("g");
+        storage.stream_group_create("r", g(), Some(StreamId::MIN), false).unwrap();
+        assert!(matches!(storage.stream_group_create("r", g(), None, false), Err(StorageError::BusyGroup)));
+        assert!(matches!(storage.stream_group_create("nope", g(), None, false), Err(StorageError::NoStream)));
+        storage.stream_group_create("made", g(), None, true).unwrap();
+        assert_eq!(storage.stream_len("made").unwrap(), 0);
+
+        let (alice, bob) = (Bytes::from("alice"), Bytes::from("bob"));
+        let read_group = |consumer: &Bytes, from, count| {
+            let read = storage.stream_read_group(b"g", consumer, &[("r".to_string(), from)], count, false).unwrap();
+            read.into_iter()
+                .flat_map(|(_, entries)| entries)
+                .map(|(id, fields)| (id, fields.is_some()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(read_group(&alice, GroupRead::New, 1), vec![(id(1, 0), true)]);
+        assert_eq!(read_group(&bob, GroupRead::New, 10), vec![(id(2, 0), true)]);
+        assert!(read_group(&alice, GroupRead::New, 10).is_empty());
+        storage.stream_delete("r", &[id(1, 0)]).unwrap();
+        assert_eq!(read_group(&alice, GroupRead::Pending(StreamId::MIN), 10), vec![(id(1, 0), false)]);
+
+        let summary = storage.stream_pending_summary("r", b"g").unwrap();
+        assert_eq!((summary.count, summary.first, summary.last), (2, Some(id(1, 0)), Some(id(2, 0))));
+        assert_eq!(summary.consumers, vec![(alice.clone(), 1), (bob.clone(), 1)]);
+        let filter = PendingFilter { start: Bound::Unbounded, end: Bound::Unbounded, count: 10, min_idle: 0, consumer: None };
+        let pending = |filter: &PendingFilter| {
+            let entries = storage.stream_pending("r", b"g", filter).unwrap();
+            entries.into_iter().map(|entry| (entry.id, entry.consumer, entry.deliveries)).collect::<Vec<_>>()
+        };
+        assert_eq!(pending(&filter), vec![(id(1, 0), alice.clone(), 2), (id(2, 0), bob.clone(), 1)]);
+        assert!(pending(&PendingFilter { min_idle: 60_000, ..filter.clone() }).is_empty());
+
+        // Claiming drops pending entries that were deleted from the stream
+        let claimed = storage.stream_claim("r", b"g", &alice, &[id(1, 0), id(2, 0)], &ClaimOptions::default()).unwrap();
+        assert_eq!(ids(claimed), vec![id(2, 0)]);
+        assert_eq!(pending(&filter), vec![(id(2, 0), alice.clone(), 2)]);
+        let busy = ClaimOptions { min_idle: 60_000, ..ClaimOptions::default() };
+        assert!(storage.stream_claim("r", b"g", &bob, &[id(2, 0)], &busy).unwrap().is_empty());
+
+        add("r", NewId::Explicit(id(3, 0)), "c").unwrap();
+        assert_eq!(read_group(&bob, GroupRead::New, 10), vec![(id(3, 0), true)]);
+        storage.stream_delete("r", &[id(3, 0)]).unwrap();
+        let claim = storage.stream_auto_claim("r", b"g", &bob, StreamId::MIN, 10, &ClaimOptions::default()).unwrap();
+        assert_eq!((claim.next, ids(claim.claimed), claim.deleted), (StreamId::MIN, vec![id(2, 0)], vec![id(3, 0)]));
+
+        assert_eq!(storage.stream_ack("r", b"g", &[id(2, 0), id(2, 0)]).unwrap(), 1);
+        assert_eq!(storage.stream_ack("r", b"nope", &[id(2, 0)]).unwrap(), 0);
+        let groups = storage.stream_groups("r").unwrap();
+        assert_eq!((groups[0].consumers, groups[0].pending, groups[0].last_delivered), (2, 0, id(3, 0)));
+        assert!(storage.stream_create_consumer("r", b"g", &Bytes::from("carol")).unwrap());
+        assert_eq!(storage.stream_delete_consumer("r", b"g", &alice).unwrap(), 0);
+        let consumers = storage.stream_consumers("r", b"g").unwrap();
+        assert_eq!(consumers.into_iter().map(|consumer| consumer.name).collect::<Vec<_>>(), vec![bob, Bytes::from("carol")]);
+
+        let missing = storage.stream_read_group(b"nope", &alice, &[("r".to_string(), GroupRead::New)], 1, false);
+        assert!(matches!(missing, Err(StorageError::NoGroup { .. })));
+        assert!(storage.stream_group_destroy("r", b"g").unwrap());
+        assert!(!storage.stream_group_destroy("r", b"g").unwrap());
+        storage.set("str".to_string(), Bytes::from("v"), SetOptions::default()).unwrap();
+        assert!(matches!(storage.stream_len("str"), Err(StorageError::WrongType)));
+    }
+
+    #[test]
+    fn test_storage_counters() {
+        let storage = Storage::new();
+        let set = |key: &str, value: &str, options| storage.set(key.to_string(), Bytes::from(value.to_string()), options);
+
+        assert_eq!(storage.incr_by("n", 5).unwrap(), 5);
+        assert_eq!(storage.incr_by("n", -7).unwrap(), -2);
+        assert_eq!(storage.get("n").unwrap(), Bytes::from("-2"));
+
+        set("ttl", "10", expires_in(Duration::from_secs(60))).unwrap();
+        assert_eq!(storage.incr_by("ttl", 1).unwrap(), 11);
+        assert!(storage.expiry("ttl").unwrap().is_some());
+
+        // Failed increments leave the value alone
+        set("max", &i64::MAX.to_string(), SetOptions::default()).unwrap();
+        assert!(matches!(storage.incr_by("max", 1), Err(StorageError::Overflow)));
+        assert_eq!(storage.get("max").unwrap(), Bytes::from(i64::MAX.to_string()));
+        for value in ["+1", "01", "-0", " 1", "1.5", ""] {
+            set("bad", value, SetOptions::default()).unwrap();
+            assert!(matches!(storage.incr_by("bad", 1), Err(StorageError::NotInteger)), "{value:?}");
+        }
+
+        assert_eq!(storage.incr_by_float("f", 10.5).unwrap(), Bytes::from("10.5"));
+        assert_eq!(storage.incr_by_float("f", 0.1).unwrap(), Bytes::from("10.6"));
+        assert_eq!(storage.get("f").unwrap(), Bytes::from("10.6"));
+        assert!(matches!(storage.incr_by("f", 1), Err(StorageError::NotInteger)));
+        assert!(matches!(storage.incr_by_float("bad", 1.0), Err(StorageError::NotFloat)));
+        assert!(matches!(storage.incr_by_float("missing", f64::INFINITY), Err(StorageError::NaNOrInfinity)));
+        assert!(storage.keys("missing").is_empty());
+
+        storage.list_push("l", ListEnd::Left, vec![Bytes::from("a")]).unwrap();
+        assert!(matches!(storage.incr_by("l", 1), Err(StorageError::WrongType)));
+    }
+
+    #[test]
+    fn test_storage_string_ranges() {
+        let storage = Storage::new();
+
+        assert_eq!(storage.append("s", b"foo").unwrap(), 3);
+        assert_eq!(storage.append("s", b"bar").unwrap(), 6);
+        assert_eq!(storage.string_len("s").unwrap(), 6);
+        assert_eq!(storage.string_len("missing").unwrap(), 0);
+
+        let range = |start, end| storage.get_range("s", start, end).unwrap();
+        assert_eq!(range(0, 2), Bytes::from("foo"));
+        assert_eq!(range(-3, -1), Bytes::from("bar"));
+        assert_eq!(range(4, 100), Bytes::from("ar"));
+        assert_eq!(range(0, -100), Bytes::from("f"));
+        assert_eq!(range(-1, -2), Bytes::new());
+        assert_eq!(range(10, 20), Bytes::new());
+        assert_eq!(storage.get_range("missing", 0, -1).unwrap(), Bytes::new());
+
+        // SETRANGE pads with zero bytes and keeps the TTL
+        storage.set("t".to_string(), Bytes::from("abc"), expires_in(Duration::from_secs(60))).unwrap();
+        assert_eq!(storage.set_range("t", 1, b"XY").unwrap(), 3);
+        assert_eq!(storage.set_range("t", 5, b"Z").unwrap(), 6);
+        assert_eq!(storage.get("t").unwrap(), Bytes::from_static(b"aXY\0\0Z"));
+        assert!(storage.expiry("t").unwrap().is_some());
+        assert_eq!(storage.set_range("missing", 5, b"").unwrap(), 0);
+        assert!(storage.keys("missing").is_empty());
+        assert!(matches!(storage.set_range("t", 512 * 1024 * 1024, b"x"), Err(StorageError::StringTooLong)));
+
+        storage.list_push("l", ListEnd::Left, vec![Bytes::from("a")]).unwrap();
+        assert!(matches!(storage.append("l", b"x"), Err(StorageError::WrongType)));
+        assert!(matches!(storage.set_range("l", 0, b"x"), Err(StorageError::WrongType)));
+        assert!(matches!(storage.get_range("l", 0, -1), Err(StorageError::WrongType)));
+    }
+
+    #[test]
+    fn test_storage_get_delete_and_expire() {
+        let storage = Storage::new();
+        storage.set("k".to_string(), Bytes::from("v"), SetOptions::default()).unwrap();
+
+        assert_eq!(storage.get_expire("k", None).unwrap(), Bytes::from("v"));
+        assert!(storage.expiry("k").unwrap().is_none());
+        let expiry = Expiry::after(Duration::from_secs(60));
+        assert_eq!(storage.get_expire("k", Some(Some(expiry))).unwrap(), Bytes::from("v"));
+        assert_eq!(storage.expiry("k").unwrap(), Some(expiry));
+        assert_eq!(storage.get_expire("k", Some(None)).unwrap(), Bytes::from("v"));
+        assert!(storage.expiry("k").unwrap().is_none());
+
+        // A deadline in the past still returns the value, then deletes it
+        assert_eq!(storage.get_expire("k", Some(Some(Expiry::at_unix_millis(1)))).unwrap(), Bytes::from("v"));
+        assert!(matches!(storage.get("k"), Err(StorageError::KeyNotFound)));
+        assert!(matches!(storage.get_expire("k", None), Err(StorageError::KeyNotFound)));
+
+        // Racing TTL changes leave the expiry index in step with the key
+        storage.set("r".to_string(), Bytes::from("v"), SetOptions::default()).unwrap();
+        std::thread::scope(|scope| {
+            for i in 0..4u64 {
+                let storage = &storage;
+                scope.spawn(move || {
+                    for j in 0..5000 {
+                        let expiry = (j % 2 == 0).then(|| Expiry::after(Duration::from_secs(60 + i)));
+                        storage.get_expire("r", Some(expiry)).unwrap();
+                    }
+                });
+            }
+        });
+        let expected = usize::from(storage.expiry("r").unwrap().is_some());
+        assert_eq!(storage.stats().expires, expected);
+        storage.get_expire("r", Some(None)).unwrap();
+        assert_eq!(storage.stats().expires, 0);
+
+        storage.set("d".to_string(), Bytes::from("v"), SetOptions::default()).unwrap();
+        assert_eq!(storage.get_delete("d").unwrap(), Bytes::from("v"));
+        assert!(matches!(storage.get_delete("d"), Err(StorageError::KeyNotFound)));
+
+        storage.list_push("l", ListEnd::Left, vec![Bytes::from("a")]).unwrap();
+        assert!(matches!(storage.get_delete("l"), Err(StorageError::WrongType)));
+        assert!(matches!(storage.get_expire("l", Some(None)), Err(StorageError::WrongType)));
+        assert_eq!(storage.list_len("l").unwrap(), 1);
+    }
+
+    #[test]
+    fn test_storage_list_move_is_atomic() {
+        let storage = Storage::new();
+        let elements = (0..100).map(|i| Bytes::from(i.to_string())).collect();
+        storage.list_push("a", ListEnd::Right, elements).unwrap();
+
+        // Moves in opposite directions neither deadlock nor lose elements
+        std::thread::scope(|scope| {
+            for (source, destination) in [("a", "b"), ("b", "a"), ("a", "b"), ("b", "a")] {
+                let storage = &storage;
+                scope.spawn(move || {
+                    for _ in 0..2000 {
+                        storage.list_move(source, destination, ListEnd::Left, ListEnd::Right).unwrap();
+                    }
+                });
+            }
+        });
+        assert_eq!(storage.list_len("a").unwrap() + storage.list_len("b").unwrap(), 100);
+
+        // A destination of the wrong type leaves the source untouched
+        storage.set("s".to_string(), Bytes::from("v"), SetOptions::default()).unwrap();
+        let source = if storage.list_len("a").unwrap() > 0 { "a" } else { "b" };
+        let before = storage.list_range(source, 0, -1).unwrap();
+        assert!(matches!(storage.list_move(source, "s", ListEnd::Left, ListEnd::Left), Err(StorageError::WrongType)));
+        assert_eq!(storage.list_range(source, 0, -1).unwrap(), before);
+    }
+}
@@ -0,0 +1,169 @@
+use bytes::{Bytes, BytesMut};
+
+use crate::protocol::{
+    parse_command, parse_value, serialize_response, ProtocolError, ProtocolVersion, RedisCommand, RedisValue,
+};
+
+#[test]
+fn test_parse_binary_bulk_value() {
+    let mut value = b"line one\r\nline two\r\n".to_vec();
+    value.extend_from_slice(&[0x00, 0xff, 0xfe]);
+
+    let mut buffer = BytesMut::new();
+    buffer.extend_from_slice(b"*3\r\n$3\r\nSET\r\n$3\r\nkey\r\n$");
+    buffer.extend_from_slice(value.len().to_string().as_bytes());
+    buffer.extend_from_slice(b"\r\n");
+    buffer.extend_from_slice(&value);
+    buffer.extend_from_slice(b"\r\n");
+
+    match parse_command(&mut buffer) {
+        Ok(Some(RedisCommand::Set { key, value: parsed, expiration, .. })) => {
+            assert_eq!(key, "key");
+            assert_eq!(parsed, Bytes::from(value));
+            assert_eq!(expiration, None);
+        },
+        other => panic!("unexpected parse result: {:?}", other),
+    }
+    assert!(buffer.is_empty());
+}
+
+#[test]
+fn test_parse_partial_frame() {
+    let frame = b"*2\r\n$3\r\nGET\r\n$5\r\nhello\r\n";
+
+    for split in 1..frame.len() {
+        let mut buffer = BytesMut::from(&frame[..split]);
+        assert!(matches!(parse_command(&mut buffer), Ok(None)));
+        assert_eq!(buffer.len(), split);
+
+        buffer.extend_from_slice(&frame[split..]);
+        assert!(matches!(parse_command(&mut buffer), Ok(Some(RedisCommand::Get { .. }))));
+        assert!(buffer.is_empty());
+    }
+}
+
+#[test]
+fn test_parse_consumes_single_frame() {
+    let mut buffer = BytesMut::from(&b"*1\r\n$4\r\nPING\r\nGET foo\r\n*1\r\n$3\r\nPOP"[..]);
+
+    assert!(matches!(parse_command(&mut buffer), Ok(Some(RedisCommand::Ping { message: None }))));
+    assert!(matches!(parse_command(&mut buffer), Ok(Some(RedisCommand::Get { key })) if key == "foo"));
+    assert!(matches!(parse_command(&mut buffer), Ok(None)));
+    assert_eq!(&buffer[..], b"*1\r\n$3\r\nPOP");
+}
+
+#[test]
+fn test_parse_malformed_frame() {
+    let mut buffer = BytesMut::from(&b"*1\r\n+PING\r\n"[..]);
+    assert!(matches!(parse_command(&mut buffer), Err(ProtocolError::Protocol(_))));
+
+    let mut buffer = BytesMut::from(&b"*1\r\n$4\r\nPINGXX"[..]);
+    assert!(matches!(parse_command(&mut buffer), Err(ProtocolError::Protocol(_))));
+}
+
+#[test]
+fn test_parse_reply_values() {
+    let mut buffer = BytesMut::from(&b"*3\r\n+OK\r\n$-1\r\n:42\r\n$2\r\nhi"[..]);
+
+    assert_eq!(
+        parse_value(&mut buffer).unwrap(),
+        Some(RedisValue::Array(vec![
+            RedisValue::String("OK".to_string()),
+            RedisValue::Nil,
+            RedisValue::Integer(42),
+        ]))
+    );
+    assert_eq!(parse_value(&mut buffer).unwrap(), None);
+    assert_eq!(&buffer[..], b"$2\r\nhi");
+}
+
+#[test]
+fn test_resp3_encoding_and_fallback() {
+    let value = RedisValue::Map(vec![
+        (RedisValue::Bytes("a".into()), RedisValue::Double(1.5)),
+        (RedisValue::Bytes("b".into()), RedisValue::Boolean(true)),
+    ]);
+
+    assert_eq!(
+        &serialize_response(value.clone(), ProtocolVersion::Resp3)[..],
+        b"%2\r\n$1\r\na\r\n,1.5\r\n$1\r\nb\r\n#t\r\n"
+    );
+    assert_eq!(
+        &serialize_response(value, ProtocolVersion::Resp2)[..],
+        b"*4\r\n$1\r\na\r\n$3\r\n1.5\r\n$1\r\nb\r\n:1\r\n"
+    );
+
+    assert_eq!(&serialize_response(RedisValue::Nil, ProtocolVersion::Resp3)[..], b"_\r\n");
+    assert_eq!(&serialize_response(RedisValue::Null, ProtocolVersion::Resp2)[..], b"*-1\r\n");
+
+    // Doubles are sent in full, so reading one back gives the same value
+    assert_eq!(&serialize_response(RedisValue::Double(0.1 + 0.2), ProtocolVersion::Resp3)[..], b",0.30000000000000004\r\n");
+    assert_eq!(&serialize_response(RedisValue::Double(-f64::INFINITY), ProtocolVersion::Resp3)[..], b",-inf\r\n");
+}
+
+#[test]
+fn test_resp3_round_trip() {
+    let value = RedisValue::Array(vec![
+        RedisValue::Set(vec![RedisValue::Integer(1)]),
+        RedisValue::Verbatim { format: "txt".to_string(), text: "a:b".to_string() },
+        RedisValue::BigNumber("12345678901234567890".to_string()),
+        RedisValue::Null,
+        RedisValue::Push(vec![RedisValue::Boolean(false), RedisValue::Double(f64::INFINITY)]),
+    ]);
+
+    let mut buffer = BytesMut::from(&serialize_response(value.clone(), ProtocolVersion::Resp3)[..]);
+    assert_eq!(parse_value(&mut buffer).unwrap(), Some(value));
+    assert!(buffer.is_empty());
+}
+
+#[test]
+fn test_inline_quoting() {
+    let mut buffer = BytesMut::from(&b"SET greeting \"hello world\"\r\n"[..]);
+    match parse_command(&mut buffer) {
+        Ok(Some(RedisCommand::Set { key, value, .. })) => {
+            assert_eq!(key, "greeting");
+            assert_eq!(value, Bytes::from("hello world"));
+        },
+        other => panic!("unexpected parse result: {:?}", other),
+    }
+
+    let mut buffer = BytesMut::from(&b"SET k \"a\\x00\\xff\\n\\\"b\"\r\nSET k 'it\\'s \"raw\"'\n"[..]);
+    assert!(matches!(parse_command(&mut buffer), Ok(Some(RedisCommand::Set { value, .. }))
+        if value[..] == b"a\x00\xff\n\"b"[..]));
+    assert!(matches!(parse_command(&mut buffer), Ok(Some(RedisCommand::Set { value, .. }))
+        if value[..] == b"it's \"raw\""[..]));
+}
+
+#[test]
+fn test_inline_unbalanced_quotes() {
+    for line in [&b"SET k \"open\r\n"[..], b"SET k 'open\r\n", b"SET k \"a\"b\r\n"] {
+        let mut buffer = BytesMut::from(line);
+        assert!(matches!(parse_command(&mut buffer), Err(ProtocolError::Protocol(_))));
+    }
+}
+
+#[test]
+fn test_error_replies() {
+    for (request, expected) in [
+        (&b"GET\r\n"[..], "ERR wrong number of arguments for 'get' command"),
+        (b"GET a b\r\n", "ERR wrong number of arguments for 'get' command"),
+        (b"SET k v EX notanumber\r\n", "ERR value is not an integer or out of range"),
+        (b"SET k v EX 0\r\n", "ERR invalid expire time in 'set' command"),
+        (b"SET k v EX\r\n", "ERR syntax error"),
+        (b"SET k v BOGUS\r\n", "ERR syntax error"),
+        (b"SET k v NX XX\r\n", "ERR syntax error"),
+        (b"SET k v EX 10 PX 100\r\n", "ERR syntax error"),
+        (b"SET k v PXAT -5\r\n", "ERR invalid expire time in 'set' command"),
+        (b"SET k v EX 9223372036854775807\r\n", "ERR invalid expire time in 'set' command"),
+        (b"COMMAND NOPE\r\n", "ERR unknown subcommand 'NOPE'. Try COMMAND HELP."),
+        (b"FOO bar baz\r\n", "ERR unknown command 'FOO', with args beginning with: 'bar' 'baz' "),
+        (b"foo\r\n", "ERR unknown command 'foo', with args beginning with: "),
+    ] {
+        let mut buffer = BytesMut::from(request);
+        match parse_command(&mut buffer) {
+            Err(e) => assert_eq!(e.to_string(), expected),
+            other => panic!("unexpected parse result: {:?}", other),
+        }
+        assert!(buffer.is_empty());
+    }
+}
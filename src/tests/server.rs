@@ -0,0 +1,645 @@
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::server::handle_client;
+use crate::storage::Storage;
+
+/// Serves a single connection on an ephemeral port and returns a client
+/// socket connected to it.
+async fn connect(storage: Arc<Storage>) -> TcpStream {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        let (socket, _) = listener.accept().await.unwrap();
+        let _ = handle_client(socket, storage).await;
+    });
+
+    TcpStream::connect(addr).await.unwrap()
+}
+
+/// Reads until exactly `expected.len()` bytes have arrived.
+async fn read_exact_reply(socket: &mut TcpStream, expected: &[u8]) {
+    let mut reply = vec![0u8; expected.len()];
+    socket.read_exact(&mut reply).await.unwrap();
+    assert_eq!(String::from_utf8_lossy(&reply), String::from_utf8_lossy(expected));
+}
+
+#[tokio::test]
+async fn test_pipelined_commands() {
+    let storage = Arc::new(Storage::new());
+    let mut socket = connect(Arc::clone(&storage)).await;
+
+    let mut request = Vec::new();
+    for i in 0..100 {
+        let key = format!("key:{}", i);
+        request.extend_from_slice(
+            format!("*3\r\n$3\r\nSET\r\n${}\r\n{}\r\n$1\r\n{}\r\n", key.len(), key, i % 10).as_bytes(),
+        );
+    }
+    request.extend_from_slice(b"GET key:42\r\n");
+    socket.write_all(&request).await.unwrap();
+
+    let mut expected = b"+OK\r\n".repeat(100);
+    expected.extend_from_slice(b"$1\r\n2\r\n");
+    read_exact_reply(&mut socket, &expected).await;
+
+    assert_eq!(storage.keys("key:*").len(), 100);
+}
+
+#[tokio::test]
+async fn test_pipeline_split_across_reads() {
+    let storage = Arc::new(Storage::new());
+    let mut socket = connect(storage).await;
+
+    socket.write_all(b"PING\r\n*2\r\n$3\r\nGET\r\n$1").await.unwrap();
+    read_exact_reply(&mut socket, b"+PONG\r\n").await;
+
+    socket.write_all(b"\r\nx\r\nPING\r\n").await.unwrap();
+    read_exact_reply(&mut socket, b"$-1\r\n+PONG\r\n").await;
+}
+
+#[tokio::test]
+async fn test_hello_switches_protocol() {
+    let storage = Arc::new(Storage::new());
+    let mut socket = connect(storage).await;
+
+    socket.write_all(b"GET missing\r\nHELLO 3\r\n").await.unwrap();
+    let mut reply = vec![0u8; 9];
+    socket.read_exact(&mut reply).await.unwrap();
+    assert_eq!(&reply, b"$-1\r\n%7\r\n");
+
+    // Drain the rest of the HELLO map before checking the new encoding
+    let mut buffer = bytes::BytesMut::from(&b"%7\r\n"[..]);
+    loop {
+        if crate::protocol::parse_value(&mut buffer).unwrap().is_some() {
+            break;
+        }
+        socket.read_buf(&mut buffer).await.unwrap();
+    }
+
+    socket.write_all(b"GET missing\r\nHELLO 4\r\n").await.unwrap();
+    read_exact_reply(&mut socket, b"_\r\n-NOPROTO unsupported protocol version\r\n").await;
+}
+
+#[tokio::test]
+async fn test_command_introspection() {
+    let storage = Arc::new(Storage::new());
+    let mut socket = connect(storage).await;
+
+    let count = crate::command::COMMANDS.len();
+    socket.write_all(b"COMMAND COUNT\r\nCOMMAND INFO get nosuchcommand\r\n").await.unwrap();
+
+    let mut expected = format!(":{}\r\n", count).into_bytes();
+    expected.extend_from_slice(
+        b"*2\r\n*10\r\n$3\r\nget\r\n:2\r\n*2\r\n+readonly\r\n+fast\r\n:1\r\n:1\r\n:1\r\n\
+          *3\r\n+@read\r\n+@fast\r\n+@string\r\n*0\r\n*0\r\n*0\r\n$-1\r\n",
+    );
+    read_exact_reply(&mut socket, &expected).await;
+    // Writes that can grow memory use are refused under memory pressure
+    assert_eq!(crate::command::lookup(b"pop").unwrap().flags, ["write", "denyoom", "fast"]);
+    assert_eq!(crate::command::lookup(b"expire").unwrap().flags, ["write", "fast"]);
+}
+
+#[tokio::test]
+async fn test_set_options() {
+    let storage = Arc::new(Storage::new());
+    let mut socket = connect(storage).await;
+
+    socket.write_all(
+        b"SET lock a NX PX 30000\r\nSET lock b NX\r\nSET lock c XX GET\r\nSET other x XX\r\n\
+          SET lock d NX GET\r\nSET lock e KEEPTTL GET\r\nGET lock\r\n",
+    ).await.unwrap();
+    read_exact_reply(
+        &mut socket,
+        b"+OK\r\n$-1\r\n$1\r\na\r\n$-1\r\n$1\r\nc\r\n$1\r\nc\r\n$1\r\ne\r\n",
+    ).await;
+
+    socket.write_all(b"SET past v EXAT 1\r\nGET past\r\n").await.unwrap();
+    read_exact_reply(&mut socket, b"+OK\r\n$-1\r\n").await;
+}
+
+#[tokio::test]
+async fn test_expiry_commands() {
+    let storage = Arc::new(Storage::new());
+    let mut socket = connect(storage).await;
+
+    socket.write_all(
+        b"SET k v\r\nTTL k\r\nTTL missing\r\nEXPIRE k 100 XX\r\nEXPIREAT k 4102444800\r\n\
+          EXPIRETIME k\r\nPEXPIRETIME k\r\nEXPIRE k 50 GT\r\nEXPIRE k 50 LT\r\nTTL k\r\n\
+          PERSIST k\r\nPERSIST k\r\nPTTL k\r\nPEXPIRE k 0\r\nGET k\r\nEXPIRE k 10 NX GT\r\n",
+    ).await.unwrap();
+    read_exact_reply(
+        &mut socket,
+        b"+OK\r\n:-1\r\n:-2\r\n:0\r\n:1\r\n:4102444800\r\n:4102444800000\r\n:0\r\n:1\r\n:50\r\n\
+          :1\r\n:0\r\n:-1\r\n:1\r\n$-1\r\n-ERR NX and XX, GT or LT options at the same time are not compatible\r\n",
+    ).await;
+}
+
+#[tokio::test]
+async fn test_counter_commands() {
+    let storage = Arc::new(Storage::new());
+    let mut socket = connect(storage).await;
+
+    socket.write_all(
+        b"SET n 10 EX 100\r\nINCR n\r\nDECR n\r\nINCRBY n 5\r\nDECRBY n 20\r\nTTL n\r\nINCR fresh\r\n\
+          INCRBYFLOAT f 10.5\r\nINCRBYFLOAT f 0.1\r\nINCRBYFLOAT f -5\r\nSET e 5.0e3\r\nINCRBYFLOAT e 2.0e2\r\n",
+    ).await.unwrap();
+    read_exact_reply(
+        &mut socket,
+        b"+OK\r\n:11\r\n:10\r\n:15\r\n:-5\r\n:100\r\n:1\r\n$4\r\n10.5\r\n$4\r\n10.6\r\n$3\r\n5.6\r\n\
+          +OK\r\n$4\r\n5200\r\n",
+    ).await;
+
+    // Replies and stored values hide the rounding error of binary floats
+    socket.write_all(
+        b"INCRBYFLOAT p 0.1\r\nINCRBYFLOAT p 0.2\r\nGET p\r\nHINCRBYFLOAT h p 0.1\r\nHINCRBYFLOAT h p 0.2\r\n\
+          HGET h p\r\nINCRBYFLOAT p -0.3\r\nINCRBYFLOAT m -1.5\r\nINCRBYFLOAT m 0.25\r\n",
+    ).await.unwrap();
+    read_exact_reply(
+        &mut socket,
+        b"$3\r\n0.1\r\n$3\r\n0.3\r\n$3\r\n0.3\r\n$3\r\n0.1\r\n$3\r\n0.3\r\n$3\r\n0.3\r\n\
+          $1\r\n0\r\n$4\r\n-1.5\r\n$5\r\n-1.25\r\n",
+    ).await;
+
+    // Small increments to a large value are kept, and no value is written
+    // with an exponent
+    socket.write_all(
+        b"INCRBYFLOAT g 1000000000.5\r\nINCRBYFLOAT g 0.0000001\r\nINCRBYFLOAT g 0.0000001\r\n\
+          INCRBYFLOAT g 0.0000001\r\nINCRBYFLOAT big 1e20\r\nINCRBYFLOAT big 1\r\nINCRBYFLOAT tiny 1e-5\r\n\
+          HINCRBYFLOAT h big 1e20\r\n",
+    ).await.unwrap();
+    read_exact_reply(
+        &mut socket,
+        b"$12\r\n1000000000.5\r\n$18\r\n1000000000.5000001\r\n$18\r\n1000000000.5000002\r\n\
+          $18\r\n1000000000.5000003\r\n$21\r\n100000000000000000000\r\n$21\r\n100000000000000000000\r\n\
+          $7\r\n0.00001\r\n$21\r\n100000000000000000000\r\n",
+    ).await;
+
+    socket.write_all(
+        b"SET big 9223372036854775807\r\nINCR big\r\nDECRBY n -9223372036854775808\r\nSET s abc\r\nINCR s\r\n\
+          INCRBYFLOAT s 1\r\nINCRBY n x\r\nINCRBYFLOAT n inf\r\nLPUSH l a\r\nINCR l\r\n",
+    ).await.unwrap();
+    read_exact_reply(
+        &mut socket,
+        b"+OK\r\n-ERR increment or decrement would overflow\r\n-ERR decrement would overflow\r\n+OK\r\n\
+          -ERR value is not an integer or out of range\r\n-ERR value is not a valid float\r\n\
+          -ERR value is not an integer or out of range\r\n-ERR increment would produce NaN or Infinity\r\n:1\r\n\
+          -WRONGTYPE Operation against a key holding the wrong kind of value\r\n",
+    ).await;
+}
+
+#[tokio::test]
+async fn test_string_commands() {
+    let storage = Arc::new(Storage::new());
+    let mut socket = connect(storage).await;
+
+    socket.write_all(
+        b"APPEND s Hello\r\nAPPEND s _World\r\nSTRLEN s\r\nSTRLEN missing\r\nGETRANGE s 0 4\r\nGETRANGE s -5 -1\r\n\
+          GETRANGE s 5 1\r\nSETRANGE s 6 Redis\r\nGET s\r\nSETRANGE p 2 x\r\nGET p\r\nSETRANGE s -1 x\r\n\
+          SETRANGE s 536870912 x\r\n",
+    ).await.unwrap();
+    read_exact_reply(
+        &mut socket,
+        b":5\r\n:11\r\n:11\r\n:0\r\n$5\r\nHello\r\n$5\r\nWorld\r\n$0\r\n\r\n:11\r\n$11\r\nHello_Redis\r\n\
+          :3\r\n$3\r\n\x00\x00x\r\n-ERR offset is out of range\r\n\
+          -ERR string exceeds maximum allowed size (proto-max-bulk-len)\r\n",
+    ).await;
+
+    socket.write_all(
+        b"SETEX t 100 v\r\nTTL t\r\nPSETEX t2 0 v\r\nSETNX t other\r\nSETNX n v\r\nGETSET t w\r\nTTL t\r\n\
+          GETEX t EX 50\r\nTTL t\r\nGETEX t PERSIST\r\nTTL t\r\nGETEX t EX 10 PERSIST\r\nGETEX t PXAT 1\r\nGET t\r\n\
+          GETDEL n\r\nGETDEL n\r\nLPUSH l a\r\nGETDEL l\r\n",
+    ).await.unwrap();
+    read_exact_reply(
+        &mut socket,
+        b"+OK\r\n:100\r\n-ERR invalid expire time in 'psetex' command\r\n:0\r\n:1\r\n$1\r\nv\r\n:-1\r\n\
+          $1\r\nw\r\n:50\r\n$1\r\nw\r\n:-1\r\n-ERR syntax error\r\n$1\r\nw\r\n$-1\r\n\
+          $1\r\nv\r\n$-1\r\n:1\r\n-WRONGTYPE Operation against a key holding the wrong kind of value\r\n",
+    ).await;
+}
+
+#[tokio::test]
+async fn test_queue_commands() {
+    let storage = Arc::new(Storage::new());
+    let mut socket = connect(storage).await;
+
+    socket.write_all(
+        b"QPUSH jobs a\r\nQPUSH jobs b PX 60000\r\nQPUSH jobs c\r\nQLEN jobs\r\nQPEEK jobs\r\n\
+          QPOP jobs\r\nQPOP jobs COUNT 5\r\nQPOP jobs\r\nQPOP jobs COUNT 1\r\nQLEN jobs\r\n\
+          QPOP jobs COUNT 0\r\nQPUSH jobs d EX\r\n",
+    ).await.unwrap();
+    read_exact_reply(
+        &mut socket,
+        b":1\r\n:2\r\n:3\r\n:3\r\n$1\r\na\r\n$1\r\na\r\n*2\r\n$1\r\nb\r\n$1\r\nc\r\n$-1\r\n*-1\r\n:0\r\n\
+          -ERR value is out of range, must be positive\r\n-ERR syntax error\r\n",
+    ).await;
+}
+
+#[tokio::test]
+async fn test_blocking_pop() {
+    let storage = Arc::new(Storage::new());
+    let mut producer = connect(Arc::clone(&storage)).await;
+    let mut first = connect(Arc::clone(&storage)).await;
+    let mut second = connect(Arc::clone(&storage)).await;
+    let mut gone = connect(Arc::clone(&storage)).await;
+    let pause = || tokio::time::sleep(Duration::from_millis(50));
+
+    first.write_all(b"BPOP 0.01\r\n").await.unwrap();
+    read_exact_reply(&mut first, b"*-1\r\n").await;
+
+    // Replies before a BPOP in a pipeline arrive while it is blocked, and a
+    // client that disconnects while blocked does not swallow a wake-up
+    first.write_all(b"PING\r\nBPOP 0\r\nPING\r\n").await.unwrap();
+    read_exact_reply(&mut first, b"+PONG\r\n").await;
+    gone.write_all(b"BPOP 0\r\n").await.unwrap();
+    pause().await;
+    second.write_all(b"BPOP 5\r\n").await.unwrap();
+    pause().await;
+    drop(gone);
+    pause().await;
+
+    producer.write_all(b"SET a 1\r\nSET b 2\r\n").await.unwrap();
+    read_exact_reply(&mut producer, b"+OK\r\n+OK\r\n").await;
+    read_exact_reply(&mut first, b"*2\r\n+a\r\n$1\r\n1\r\n+PONG\r\n").await;
+    read_exact_reply(&mut second, b"*2\r\n+b\r\n$1\r\n2\r\n").await;
+
+    producer.write_all(b"BPOP -1\r\nBPOP x\r\nBPOP 1e20\r\nBLPOP k 1e20\r\nPING\r\n").await.unwrap();
+    read_exact_reply(
+        &mut producer,
+        b"-ERR timeout is negative\r\n-ERR timeout is not a float or out of range\r\n\
+          -ERR timeout is not a float or out of range\r\n-ERR timeout is not a float or out of range\r\n+PONG\r\n",
+    ).await;
+}
+
+#[tokio::test]
+async fn test_queue_reservations() {
+    let storage = Arc::new(Storage::new());
+    let mut socket = connect(storage).await;
+
+    socket.write_all(
+        b"QPUSH jobs a\r\nQRESERVE jobs VISIBILITY 1000\r\nQLEN jobs\r\nQRESERVE jobs\r\nQNACK jobs 0\r\n\
+          QRESERVE jobs\r\nQACK jobs 0 1\r\nQLEN jobs\r\nQRESERVE jobs VISIBILITY 0\r\nQACK jobs -1\r\n",
+    ).await.unwrap();
+    read_exact_reply(
+        &mut socket,
+        b":1\r\n*3\r\n:0\r\n$1\r\na\r\n:1\r\n:0\r\n*-1\r\n:1\r\n*3\r\n:1\r\n$1\r\na\r\n:2\r\n:1\r\n:0\r\n\
+          -ERR value is out of range, must be positive\r\n-ERR value is not an integer or out of range\r\n",
+    ).await;
+}
+
+#[tokio::test]
+async fn test_dead_letter_commands() {
+    let storage = Arc::new(Storage::new());
+    storage.set_max_failures(1);
+    let mut socket = connect(storage).await;
+
+    socket.write_all(
+        b"QPUSH jobs a\r\nQPUSH jobs b\r\nQRESERVE jobs\r\nQREJECT jobs 0\r\nQDLQ LEN jobs\r\n\
+          QDLQ LIST jobs COUNT 5\r\nQDLQ REPLAY jobs\r\nQPEEK jobs COUNT 2\r\nQDLQ PURGE jobs\r\n\
+          QDLQ FLUSH jobs\r\nQDLQ LEN jobs extra\r\n",
+    ).await.unwrap();
+    read_exact_reply(
+        &mut socket,
+        b":1\r\n:2\r\n*3\r\n:0\r\n$1\r\na\r\n:1\r\n:1\r\n:1\r\n*1\r\n*2\r\n$1\r\na\r\n:1\r\n:1\r\n\
+          *2\r\n$1\r\na\r\n$1\r\nb\r\n:0\r\n-ERR unknown subcommand 'FLUSH'. Try QDLQ HELP.\r\n-ERR syntax error\r\n",
+    ).await;
+}
+
+#[tokio::test]
+async fn test_scheduled_queue_items() {
+    let storage = Arc::new(Storage::new());
+    let mut socket = connect(storage).await;
+
+    socket.write_all(
+        b"QPUSH jobs a DELAYAT 4102444800000 PX 100000000000\r\nQPUSH jobs b DELAY 0\r\nQSCHEDULED jobs\r\n\
+          QCANCEL jobs 0 1\r\nQSCHEDULED jobs COUNT 1\r\nQPUSH jobs c DELAY -1\r\nQPUSH jobs c DELAY\r\n",
+    ).await.unwrap();
+    read_exact_reply(
+        &mut socket,
+        b":0\r\n:1\r\n*1\r\n*3\r\n:0\r\n$1\r\na\r\n:4102444800000\r\n:1\r\n*0\r\n\
+          -ERR invalid expire time in 'qpush' command\r\n-ERR syntax error\r\n",
+    ).await;
+}
+
+#[tokio::test]
+async fn test_priority_queue_commands() {
+    let storage = Arc::new(Storage::new());
+    let mut socket = connect(storage).await;
+
+    socket.write_all(
+        b"QPUSH jobs a\r\nQPUSH jobs b PRIORITY 5 EX 60\r\nQPUSH jobs c PRIORITY -2\r\nQDEPTH jobs\r\n\
+          QPOP jobs COUNT 3\r\nQDEPTH jobs\r\nQPUSH jobs d PRIORITY high\r\n",
+    ).await.unwrap();
+    read_exact_reply(
+        &mut socket,
+        b":1\r\n:2\r\n:3\r\n*6\r\n:5\r\n:1\r\n:0\r\n:1\r\n:-2\r\n:1\r\n\
+          *3\r\n$1\r\nb\r\n$1\r\na\r\n$1\r\nc\r\n*0\r\n-ERR value is not an integer or out of range\r\n",
+    ).await;
+}
+
+#[tokio::test]
+async fn test_list_commands() {
+    let storage = Arc::new(Storage::new());
+    let mut socket = connect(storage).await;
+
+    socket.write_all(
+        b"RPUSH l a b c\r\nLPUSH l z\r\nLRANGE l 1 -2\r\nLLEN l\r\nLINDEX l 0\r\nLPOP l\r\nRPOP l 2\r\n\
+          LPOP missing 2\r\nLSET missing 0 x\r\nLSET l 5 x\r\nLINSERT l AFTER a b\r\nLMOVE l dst RIGHT LEFT\r\n\
+          GET dst\r\nSET s v\r\nLPUSH s x\r\nLINSERT l NEXT a b\r\n",
+    ).await.unwrap();
+    read_exact_reply(
+        &mut socket,
+        b":3\r\n:4\r\n*2\r\n$1\r\na\r\n$1\r\nb\r\n:4\r\n$1\r\nz\r\n$1\r\nz\r\n*2\r\n$1\r\nc\r\n$1\r\nb\r\n\
+          *-1\r\n-ERR no such key\r\n-ERR index out of range\r\n:2\r\n$1\r\nb\r\n\
+          -WRONGTYPE Operation against a key holding the wrong kind of value\r\n+OK\r\n\
+          -WRONGTYPE Operation against a key holding the wrong kind of value\r\n-ERR syntax error\r\n",
+    ).await;
+}
+
+#[tokio::test]
+async fn test_blocking_list_commands() {
+    let storage = Arc::new(Storage::new());
+    let mut producer = connect(Arc::clone(&storage)).await;
+    let mut first = connect(Arc::clone(&storage)).await;
+    let mut second = connect(Arc::clone(&storage)).await;
+    let mut gone = connect(Arc::clone(&storage)).await;
+    let pause = || tokio::time::sleep(Duration::from_millis(50));
+
+    // Served straight away when an element is available, nil array on timeout
+    producer.write_all(b"RPUSH a x\r\nBLPOP missing a 1\r\nBRPOP a 0.01\r\n").await.unwrap();
+    read_exact_reply(&mut producer, b":1\r\n*2\r\n$1\r\na\r\n$1\r\nx\r\n*-1\r\n").await;
+
+    // The oldest client blocked on a key gets the first element pushed there,
+    // whichever of its keys it was; a disconnected client is skipped
+    first.write_all(b"BLPOP a b 0\r\nPING\r\n").await.unwrap();
+    pause().await;
+    gone.write_all(b"BLPOP b 0\r\n").await.unwrap();
+    pause().await;
+    second.write_all(b"BLMPOP 5 2 c b RIGHT COUNT 2\r\n").await.unwrap();
+    pause().await;
+    drop(gone);
+    pause().await;
+
+    producer.write_all(b"RPUSH b 1 2 3\r\n").await.unwrap();
+    read_exact_reply(&mut producer, b":3\r\n").await;
+    read_exact_reply(&mut first, b"*2\r\n$1\r\nb\r\n$1\r\n1\r\n+PONG\r\n").await;
+    read_exact_reply(&mut second, b"*2\r\n$1\r\nb\r\n*2\r\n$1\r\n3\r\n$1\r\n2\r\n").await;
+
+    first.write_all(b"BLMOVE src dst LEFT RIGHT 0\r\n").await.unwrap();
+    pause().await;
+    producer.write_all(b"LPUSH src v\r\n").await.unwrap();
+    read_exact_reply(&mut producer, b":1\r\n").await;
+    read_exact_reply(&mut first, b"$1\r\nv\r\n").await;
+    producer.write_all(b"LRANGE dst 0 -1\r\n").await.unwrap();
+    read_exact_reply(&mut producer, b"*1\r\n$1\r\nv\r\n").await;
+
+    producer.write_all(b"SET s v\r\nBLPOP s 0\r\nBLMPOP 0 0 a LEFT\r\nBLMPOP 0 1 a LEFT COUNT 0\r\n").await.unwrap();
+    read_exact_reply(
+        &mut producer,
+        b"+OK\r\n-WRONGTYPE Operation against a key holding the wrong kind of value\r\n\
+          -ERR numkeys should be greater than 0\r\n-ERR count should be greater than 0\r\n",
+    ).await;
+}
+
+#[tokio::test]
+async fn test_hash_commands() {
+    let storage = Arc::new(Storage::new());
+    let mut socket = connect(storage).await;
+
+    socket.write_all(
+        b"HSET h a 1 b 2\r\nHSET h a 3\r\nHGET h a\r\nHMGET h b x\r\nHINCRBY h a 4\r\nHINCRBYFLOAT h b 0.1\r\n\
+          HINCRBYFLOAT h b 1e3\r\nHEXISTS h x\r\nHLEN h\r\nHDEL h a x\r\nHGETALL h\r\nHSCAN h 0 NOVALUES\r\n\
+          HSCAN h 0 MATCH z*\r\nHGET missing a\r\nHGETALL missing\r\n",
+    ).await.unwrap();
+    read_exact_reply(
+        &mut socket,
+        b":2\r\n:0\r\n$1\r\n3\r\n*2\r\n$1\r\n2\r\n$-1\r\n:7\r\n$3\r\n2.1\r\n$6\r\n1002.1\r\n:0\r\n:2\r\n:1\r\n\
+          *2\r\n$1\r\nb\r\n$6\r\n1002.1\r\n*2\r\n$1\r\n0\r\n*1\r\n$1\r\nb\r\n*2\r\n$1\r\n0\r\n*0\r\n$-1\r\n*0\r\n",
+    ).await;
+
+    socket.write_all(
+        b"HSET h a\r\nHINCRBY h b 1\r\nHINCRBYFLOAT h b x\r\nHINCRBYFLOAT h b inf\r\nHSCAN h x\r\n\
+          HSCAN h 0 COUNT 0\r\nSET s v\r\nHGET s a\r\n",
+    ).await.unwrap();
+    read_exact_reply(
+        &mut socket,
+        b"-ERR wrong number of arguments for 'hset' command\r\n-ERR hash value is not an integer\r\n\
+          -ERR value is not a valid float\r\n-ERR value is NaN or Infinity\r\n-ERR invalid cursor\r\n\
+          -ERR syntax error\r\n+OK\r\n-WRONGTYPE Operation against a key holding the wrong kind of value\r\n",
+    ).await;
+
+    // Only the canonical form of an integer counts, as with INCR
+    socket.write_all(b"HSET n a +5 b 007\r\nHINCRBY n a 1\r\nHINCRBY n b 1\r\nHGET n a\r\n").await.unwrap();
+    read_exact_reply(
+        &mut socket,
+        b":2\r\n-ERR hash value is not an integer\r\n-ERR hash value is not an integer\r\n$2\r\n+5\r\n",
+    ).await;
+}
+
+#[tokio::test]
+async fn test_hash_field_ttl_commands() {
+    let storage = Arc::new(Storage::new());
+    let mut socket = connect(storage).await;
+
+    socket.write_all(
+        b"HSET h a 1 b 2 c 3\r\nHEXPIRE h 100 FIELDS 2 a x\r\nHEXPIRE h 50 GT FIELDS 1 a\r\n\
+          HPEXPIRE h 20000 NX FIELDS 2 a b\r\nHTTL h FIELDS 4 a b c x\r\nHPERSIST h FIELDS 3 a c x\r\n\
+          HEXPIRE h 0 FIELDS 1 c\r\nHGETALL h\r\nHTTL missing FIELDS 1 a\r\n",
+    ).await.unwrap();
+    read_exact_reply(
+        &mut socket,
+        b":3\r\n*2\r\n:1\r\n:-2\r\n*1\r\n:0\r\n*2\r\n:0\r\n:1\r\n*4\r\n:100\r\n:20\r\n:-1\r\n:-2\r\n\
+          *3\r\n:1\r\n:-1\r\n:-2\r\n*1\r\n:2\r\n*4\r\n$1\r\na\r\n$1\r\n1\r\n$1\r\nb\r\n$1\r\n2\r\n\
+          *1\r\n:-2\r\n",
+    ).await;
+
+    socket.write_all(
+        b"HPEXPIRE h 1 FIELDS 1 a\r\nHEXPIRE h -1 FIELDS 1 a\r\nHEXPIRE h 10 FIELD 1 a\r\n\
+          HTTL h FIELDS 0 a\r\nHTTL h FIELDS 2 a\r\n",
+    ).await.unwrap();
+    read_exact_reply(
+        &mut socket,
+        b"*1\r\n:1\r\n-ERR invalid expire time, must be >= 0\r\n\
+          -ERR Mandatory argument FIELDS is missing or not at the right position\r\n\
+          -ERR Parameter `numFields` should be greater than 0\r\n\
+          -ERR The `numfields` parameter must match the number of arguments\r\n",
+    ).await;
+
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    socket.write_all(b"HGET h a\r\nHLEN h\r\n").await.unwrap();
+    read_exact_reply(&mut socket, b"$-1\r\n:1\r\n").await;
+}
+
+#[tokio::test]
+async fn test_set_commands() {
+    let storage = Arc::new(Storage::new());
+    let mut socket = connect(storage).await;
+
+    socket.write_all(
+        b"SADD s 3 1 2 1\r\nSMEMBERS s\r\nSADD t 2 3 x\r\nSISMEMBER s 2\r\nSMISMEMBER s 1 x\r\nSCARD t\r\n\
+          SINTER s t\r\nSDIFF s t\r\nSINTERSTORE d s t\r\nSMEMBERS d\r\nSINTERCARD 2 s t LIMIT 1\r\n\
+          SREM s 1 9\r\nSPOP missing\r\nSRANDMEMBER missing 3\r\nSPOP s 0\r\n",
+    ).await.unwrap();
+    read_exact_reply(
+        &mut socket,
+        b":3\r\n*3\r\n$1\r\n1\r\n$1\r\n2\r\n$1\r\n3\r\n:3\r\n:1\r\n*2\r\n:1\r\n:0\r\n:3\r\n\
+          *2\r\n$1\r\n2\r\n$1\r\n3\r\n*1\r\n$1\r\n1\r\n:2\r\n*2\r\n$1\r\n2\r\n$1\r\n3\r\n:1\r\n\
+          :1\r\n$-1\r\n*0\r\n*0\r\n",
+    ).await;
+
+    socket.write_all(
+        b"SPOP s -1\r\nSINTERCARD 0 s\r\nSINTERCARD 3 s t\r\nSINTERCARD 1 s LIMIT -1\r\nSET str v\r\n\
+          SADD str a\r\nSUNION s str\r\nSADD s2 a\r\nGET s2\r\nSRANDMEMBER s -1000000000000000000000\r\n\
+          SRANDMEMBER s -4611686018427387904\r\nSRANDMEMBER s2 4611686018427387904\r\n\
+          SRANDMEMBER s2 9223372036854775807\r\nSRANDMEMBER s2 -3\r\n",
+    ).await.unwrap();
+    read_exact_reply(
+        &mut socket,
+        b"-ERR value is out of range, must be positive\r\n-ERR numkeys should be greater than 0\r\n\
+          -ERR Number of keys can't be greater than number of args\r\n-ERR LIMIT can't be negative\r\n+OK\r\n\
+          -WRONGTYPE Operation against a key holding the wrong kind of value\r\n\
+          -WRONGTYPE Operation against a key holding the wrong kind of value\r\n:1\r\n\
+          -WRONGTYPE Operation against a key holding the wrong kind of value\r\n\
+          -ERR value is not an integer or out of range\r\n-ERR value is out of range\r\n\
+          *1\r\n$1\r\na\r\n*1\r\n$1\r\na\r\n*3\r\n$1\r\na\r\n$1\r\na\r\n$1\r\na\r\n",
+    ).await;
+}
+
+#[tokio::test]
+async fn test_sorted_set_commands() {
+    let storage = Arc::new(Storage::new());
+    let mut socket = connect(storage).await;
+
+    socket.write_all(
+        b"ZADD z 1 a 2 b 3 c\r\nZADD z XX CH GT 0 a 5 b 1 x\r\nZADD z INCR 1.5 a\r\nZINCRBY z -1 c\r\n\
+          ZRANGE z 0 -1 WITHSCORES\r\nZRANGE z (5 -inf BYSCORE REV LIMIT 0 1\r\nZRANK z b\r\nZREVRANK z b\r\n\
+          ZSCORE z x\r\nZMSCORE z a x\r\nZCOUNT z 2 +inf\r\nZCARD z\r\nZPOPMIN z\r\nZPOPMAX z 5\r\nZCARD z\r\n",
+    ).await.unwrap();
+    read_exact_reply(
+        &mut socket,
+        b":3\r\n:1\r\n$3\r\n2.5\r\n$1\r\n2\r\n*6\r\n$1\r\nc\r\n$1\r\n2\r\n$1\r\na\r\n$3\r\n2.5\r\n$1\r\nb\r\n\
+          $1\r\n5\r\n*1\r\n$1\r\na\r\n:2\r\n:0\r\n$-1\r\n*2\r\n$3\r\n2.5\r\n$-1\r\n:3\r\n:3\r\n\
+          *2\r\n$1\r\nc\r\n$1\r\n2\r\n*4\r\n$1\r\nb\r\n$1\r\n5\r\n$1\r\na\r\n$3\r\n2.5\r\n:0\r\n",
+    ).await;
+
+    socket.write_all(
+        b"ZADD l 0 a 0 b 0 c\r\nZRANGE l [b + BYLEX\r\nZREMRANGEBYLEX l - (b\r\nZADD n 1 b 2 c\r\n\
+          ZUNIONSTORE u 2 l n WEIGHTS 2 3 AGGREGATE MAX\r\nZRANGE u 0 -1 WITHSCORES\r\nZINTERSTORE i 2 l n\r\n\
+          ZREMRANGEBYSCORE u (3 6\r\nZREMRANGEBYRANK u 0 0\r\nZCARD u\r\n",
+    ).await.unwrap();
+    read_exact_reply(
+        &mut socket,
+        b":3\r\n*2\r\n$1\r\nb\r\n$1\r\nc\r\n:1\r\n:2\r\n:2\r\n*4\r\n$1\r\nb\r\n$1\r\n3\r\n$1\r\nc\r\n$1\r\n6\r\n\
+          :2\r\n:1\r\n:1\r\n:0\r\n",
+    ).await;
+
+    socket.write_all(
+        b"ZRANGE l 0 9223372036854775807\r\nZRANGE l 0 9223372036854775807 REV\r\n\
+          ZREMRANGEBYRANK l 1 9223372036854775807\r\nZCARD l\r\n",
+    ).await.unwrap();
+    read_exact_reply(
+        &mut socket,
+        b"*2\r\n$1\r\nb\r\n$1\r\nc\r\n*2\r\n$1\r\nc\r\n$1\r\nb\r\n:1\r\n:1\r\n",
+    ).await;
+
+    // Scores are replied in full, so writing one back keeps the same value
+    socket.write_all(
+        b"ZADD f 1.0000000000000002 m 0.1234567890123456789 n\r\nZSCORE f m\r\nZSCORE f n\r\n\
+          ZINCRBY f 0.1 o\r\nZINCRBY f 0.2 o\r\n",
+    ).await.unwrap();
+    read_exact_reply(
+        &mut socket,
+        b":2\r\n$18\r\n1.0000000000000002\r\n$19\r\n0.12345678901234568\r\n$3\r\n0.1\r\n\
+          $19\r\n0.30000000000000004\r\n",
+    ).await;
+
+    socket.write_all(
+        b"ZADD z NX XX 1 a\r\nZADD z NX GT 1 a\r\nZADD z INCR 1 a 2 b\r\nZADD z CH 1\r\nZADD z x a\r\n\
+          ZRANGE z 0 1 LIMIT 0 1\r\nZRANGE l - + BYLEX WITHSCORES\r\nZRANGE z x 1 BYSCORE\r\nZRANGE l a b BYLEX\r\n\
+          ZUNIONSTORE u 0 z\r\nZUNIONSTORE u 1 z WEIGHTS x\r\nZPOPMIN z -1\r\nSET s v\r\nZADD s 1 a\r\n",
+    ).await.unwrap();
+    read_exact_reply(
+        &mut socket,
+        b"-ERR XX and NX options at the same time are not compatible\r\n\
+          -ERR GT, LT, and/or NX options at the same time are not compatible\r\n\
+          -ERR INCR option supports a single increment-element pair\r\n-ERR syntax error\r\n\
+          -ERR value is not a valid float\r\n\
+          -ERR syntax error, LIMIT is only supported in combination with either BYSCORE or BYLEX\r\n\
+          -ERR syntax error, WITHSCORES not supported in combination with BYLEX\r\n\
+          -ERR min or max is not a float\r\n-ERR min or max not valid string range item\r\n\
+          -ERR at least 1 input key is needed for 'zunionstore' command\r\n-ERR weight value is not a float\r\n\
+          -ERR value is out of range, must be positive\r\n+OK\r\n\
+          -WRONGTYPE Operation against a key holding the wrong kind of value\r\n",
+    ).await;
+}
+
+#[tokio::test]
+async fn test_stream_commands() {
+    let storage = Arc::new(Storage::new());
+    let mut socket = connect(Arc::clone(&storage)).await;
+
+    socket.write_all(
+        b"XADD s 1-1 f a\r\nXADD s 1-* f b\r\nXADD s 1 f c\r\nXADD s 0-0 f c\r\nXLEN s\r\nXRANGE s - +\r\n\
+          XREVRANGE s + (1-1 COUNT 1\r\nXDEL s 1-1 9-9\r\nXADD s MAXLEN = 1 2-0 f d\r\nXRANGE s - +\r\n",
+    ).await.unwrap();
+    read_exact_reply(
+        &mut socket,
+        b"$3\r\n1-1\r\n$3\r\n1-2\r\n\
+          -ERR The ID specified in XADD is equal or smaller than the target stream top item\r\n\
+          -ERR The ID specified in XADD must be greater than 0-0\r\n:2\r\n\
+          *2\r\n*2\r\n$3\r\n1-1\r\n*2\r\n$1\r\nf\r\n$1\r\na\r\n*2\r\n$3\r\n1-2\r\n*2\r\n$1\r\nf\r\n$1\r\nb\r\n\
+          *1\r\n*2\r\n$3\r\n1-2\r\n*2\r\n$1\r\nf\r\n$1\r\nb\r\n:1\r\n$3\r\n2-0\r\n\
+          *1\r\n*2\r\n$3\r\n2-0\r\n*2\r\n$1\r\nf\r\n$1\r\nd\r\n",
+    ).await;
+
+    socket.write_all(
+        b"XGROUP CREATE s g 0\r\nXGROUP CREATE s g $\r\nXGROUP CREATE nope g $\r\nXGROUP CREATE new g $ MKSTREAM\r\n\
+          XREADGROUP GROUP g alice COUNT 1 STREAMS s >\r\nXREADGROUP GROUP g alice STREAMS s >\r\nXPENDING s g\r\n\
+          XCLAIM s g bob 0 2-0 JUSTID\r\nXACK s g 2-0 2-0\r\nXPENDING s g\r\nXINFO GROUPS s\r\n",
+    ).await.unwrap();
+    read_exact_reply(
+        &mut socket,
+        b"+OK\r\n-BUSYGROUP Consumer Group name already exists\r\n\
+          -ERR The XGROUP subcommand requires the key to exist. Note that for CREATE you may want to use the \
+          MKSTREAM option to create an empty stream automatically.\r\n+OK\r\n\
+          *1\r\n*2\r\n$1\r\ns\r\n*1\r\n*2\r\n$3\r\n2-0\r\n*2\r\n$1\r\nf\r\n$1\r\nd\r\n*-1\r\n\
+          *4\r\n:1\r\n$3\r\n2-0\r\n$3\r\n2-0\r\n*1\r\n*2\r\n$5\r\nalice\r\n$1\r\n1\r\n\
+          *1\r\n$3\r\n2-0\r\n:1\r\n*4\r\n:0\r\n$-1\r\n$-1\r\n*-1\r\n\
+          *1\r\n*8\r\n$4\r\nname\r\n$1\r\ng\r\n$9\r\nconsumers\r\n:2\r\n$7\r\npending\r\n:0\r\n\
+          $17\r\nlast-delivered-id\r\n$3\r\n2-0\r\n",
+    ).await;
+
+    // An entry wakes every client blocked on its stream
+    let mut reader = connect(Arc::clone(&storage)).await;
+    let mut member = connect(Arc::clone(&storage)).await;
+    reader.write_all(b"XREAD BLOCK 0 STREAMS s $\r\n").await.unwrap();
+    member.write_all(b"XREADGROUP GROUP g carol BLOCK 0 STREAMS s >\r\n").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    socket.write_all(b"XADD s 3-0 f e\r\n").await.unwrap();
+    read_exact_reply(&mut socket, b"$3\r\n3-0\r\n").await;
+    let entry = b"*1\r\n*2\r\n$1\r\ns\r\n*1\r\n*2\r\n$3\r\n3-0\r\n*2\r\n$1\r\nf\r\n$1\r\ne\r\n";
+    read_exact_reply(&mut reader, entry).await;
+    read_exact_reply(&mut member, entry).await;
+    reader.write_all(b"XREAD BLOCK 10 STREAMS s $\r\n").await.unwrap();
+    read_exact_reply(&mut reader, b"*-1\r\n").await;
+
+    socket.write_all(
+        b"XREAD COUNT 1 STREAMS s\r\nXREADGROUP GROUP g a STREAMS s 1 2\r\nXADD s MAXLEN -1 * f v\r\n\
+          XADD s MAXLEN 1 LIMIT 10 * f v\r\nXRANGE s x +\r\nXREAD BLOCK -1 STREAMS s 0\r\nXGROUP FOO s g\r\n\
+          XREADGROUP GROUP nope a STREAMS s >\r\nXAUTOCLAIM s g c2 0 0-0 COUNT 9223372036854775807\r\n\
+          SET str v\r\nXADD str * f v\r\n",
+    ).await.unwrap();
+    read_exact_reply(
+        &mut socket,
+        b"-ERR Unbalanced 'xread' list of streams: for each stream key an ID or '$' must be specified.\r\n\
+          -ERR Unbalanced 'xreadgroup' list of streams: for each stream key an ID or '>' must be specified.\r\n\
+          -ERR The MAXLEN argument must be >= 0.\r\n\
+          -ERR syntax error, LIMIT cannot be used without the special ~ option\r\n\
+          -ERR Invalid stream ID specified as stream command argument\r\n-ERR timeout is negative\r\n\
+          -ERR unknown subcommand 'FOO'. Try XGROUP HELP.\r\n-NOGROUP No such key 's' or consumer group 'nope'\r\n\
+          -ERR COUNT should be greater than 0\r\n+OK\r\n-WRONGTYPE Operation against a key holding the wrong kind of value\r\n",
+    ).await;
+}